@@ -0,0 +1,118 @@
+use crate::parse::InputInfo;
+use crate::render;
+use eyre::{eyre, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Collects the problems of a parsed keymap, given the result of `InputInfo::parse`.
+/// Parsing stops at the first error, such as a combo with a key that isn't in the
+/// base layer or a physical layout with the wrong number of keys, which is
+/// reported on its own.
+pub fn check(parsed: Result<(InputInfo, Vec<String>)>) -> Vec<Diagnostic> {
+    let (info, warnings) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                message: format!("{err:#}"),
+            }]
+        }
+    };
+
+    let mut res = Vec::new();
+    let missing = render::missing_colors(&info);
+    if !missing.is_empty() {
+        res.push(Diagnostic::warning(format!(
+            "no color for classes {}, using {}",
+            missing.join(", "),
+            info.render_opts.default_color
+        )));
+    }
+    res.extend(warnings.into_iter().map(Diagnostic::warning));
+    res
+}
+
+/// Prints the diagnostics and fails if any of them is an error.
+pub fn output_diagnostics(diagnostics: &[Diagnostic]) -> Result<()> {
+    for x in diagnostics {
+        println!("{}: {}", x.severity, x.message);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    println!("{errors} errors, {warnings} warnings");
+
+    if errors > 0 {
+        return Err(eyre!("Check failed with {errors} errors"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::tests::golden_info;
+
+    #[test]
+    fn test_check() -> Result<()> {
+        assert_eq!(check(golden_info().map(|x| (x, vec![]))), vec![]);
+
+        let mut info = golden_info()?;
+        info.render_opts.colors.remove("combo");
+        info.keymap.layers[1].keys[0].id.0 = "SE_4".into();
+        let warnings = info.keymap.check_duplicate_keys(false)?;
+
+        let diagnostics = check(Ok((info, warnings)));
+        assert!(diagnostics.iter().all(|x| x.severity == Severity::Warning));
+        assert!(diagnostics
+            .iter()
+            .any(|x| x.message.starts_with("no color for classes combo")));
+        assert!(diagnostics
+            .iter()
+            .any(|x| x.message.starts_with("Key SE_4 is used at multiple")));
+        assert!(output_diagnostics(&diagnostics).is_ok());
+
+        let diagnostics = check(Err(eyre!("Couldn't find combo `SE_Q` in base layer")));
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                severity: Severity::Error,
+                message: "Couldn't find combo `SE_Q` in base layer".into(),
+            }]
+        );
+        assert!(output_diagnostics(&diagnostics).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use super::stats::KeylogStats;
+use crate::parse::FingerAssignment;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A percentage in the two compared keylogs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PercDelta {
+    pub a: f32,
+    pub b: f32,
+}
+
+impl PercDelta {
+    pub fn delta(&self) -> f32 {
+        self.b - self.a
+    }
+}
+
+#[derive(Debug)]
+pub struct KeylogComparison {
+    pub sfb_perc: PercDelta,
+    pub sfb_perc_with_combos: PercDelta,
+    pub left_hand: PercDelta,
+    pub right_hand: PercDelta,
+    // Share of all key presses
+    pub finger_load: BTreeMap<FingerAssignment, PercDelta>,
+    // Share of all events
+    pub finger_sfbs: BTreeMap<FingerAssignment, PercDelta>,
+    // Sfbs by how much they changed, largest change first
+    pub sfb_movers: Vec<(String, PercDelta)>,
+}
+
+impl KeylogComparison {
+    pub fn new(a: &KeylogStats, b: &KeylogStats, include_combos: bool) -> Self {
+        let hand = |stats: &KeylogStats, presses: u32| {
+            presses as f32 / stats.total_key_presses as f32 * 100.0
+        };
+
+        Self {
+            sfb_perc: PercDelta {
+                a: a.sfb_perc(false),
+                b: b.sfb_perc(false),
+            },
+            sfb_perc_with_combos: PercDelta {
+                a: a.sfb_perc(true),
+                b: b.sfb_perc(true),
+            },
+            left_hand: PercDelta {
+                a: hand(a, a.total_key_presses_left),
+                b: hand(b, b.total_key_presses_left),
+            },
+            right_hand: PercDelta {
+                a: hand(a, a.total_key_presses_right),
+                b: hand(b, b.total_key_presses_right),
+            },
+            finger_load: zip_percs(
+                &finger_percs(&a.finger_frequency, a.total_key_presses),
+                &finger_percs(&b.finger_frequency, b.total_key_presses),
+            ),
+            finger_sfbs: zip_percs(
+                &finger_percs(&a.sfb_frequency_by_finger(include_combos), a.total_events),
+                &finger_percs(&b.sfb_frequency_by_finger(include_combos), b.total_events),
+            ),
+            sfb_movers: sfb_movers(a, b, include_combos),
+        }
+    }
+}
+
+fn finger_percs(
+    frequency: &BTreeMap<FingerAssignment, u32>,
+    total: u32,
+) -> BTreeMap<FingerAssignment, f32> {
+    frequency
+        .iter()
+        .map(|(finger, count)| (*finger, *count as f32 / total as f32 * 100.0))
+        .collect()
+}
+
+fn zip_percs<K: Ord + Clone>(a: &BTreeMap<K, f32>, b: &BTreeMap<K, f32>) -> BTreeMap<K, PercDelta> {
+    let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+    keys.into_iter()
+        .map(|key| {
+            let delta = PercDelta {
+                a: a.get(key).copied().unwrap_or(0.0),
+                b: b.get(key).copied().unwrap_or(0.0),
+            };
+            (key.clone(), delta)
+        })
+        .collect()
+}
+
+fn sfb_movers(a: &KeylogStats, b: &KeylogStats, include_combos: bool) -> Vec<(String, PercDelta)> {
+    let sfb_percs = |stats: &KeylogStats| -> BTreeMap<String, f32> {
+        let mut res: HashMap<String, u32> = HashMap::new();
+        for sfb in &stats.sfbs {
+            if !include_combos && sfb.sfb.has_combo() {
+                continue;
+            }
+            *res.entry(sfb.sfb.id()).or_insert(0) += sfb.presses;
+        }
+        res.into_iter()
+            .map(|(id, presses)| (id, presses as f32 / stats.total_events as f32 * 100.0))
+            .collect()
+    };
+
+    let mut res: Vec<_> = zip_percs(&sfb_percs(a), &sfb_percs(b))
+        .into_iter()
+        .filter(|(_, x)| x.delta() != 0.0)
+        .collect();
+    // Largest change first, ties ordered by id for a stable output
+    res.sort_by(|(a_id, a), (b_id, b)| {
+        b.delta()
+            .abs()
+            .total_cmp(&a.delta().abs())
+            .then_with(|| a_id.cmp(b_id))
+    });
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keylog::csv_parser;
+    use crate::keylog::stats::tests::{test_info, test_keylog};
+    use crate::keylog::stats::StatsOpts;
+    use crate::parse::{Finger, MatrixHalf};
+    use eyre::Result;
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let info = test_info()?;
+        let keylog = test_keylog();
+        let opts = StatsOpts::default();
+        let a = KeylogStats::from_entries(&info, csv_parser::parse_from_str(&keylog)?, &opts)?;
+        // Only the first half of the log
+        let half: Vec<_> = keylog.lines().take(9).collect();
+        let half = csv_parser::parse_from_str(&half.join("\n"))?;
+        let b = KeylogStats::from_entries(&info, half, &opts)?;
+
+        let same = KeylogComparison::new(&a, &a, true);
+        assert_eq!(same.sfb_perc.delta(), 0.0);
+        assert!(same.sfb_movers.is_empty());
+
+        let cmp = KeylogComparison::new(&a, &b, false);
+        assert_eq!(cmp.sfb_perc.a, a.sfb_perc(false));
+        assert_eq!(cmp.sfb_perc.b, b.sfb_perc(false));
+
+        let right_index = FingerAssignment {
+            finger: Finger::Index,
+            half: MatrixHalf::Right,
+        };
+        assert!(cmp.finger_load[&right_index].a > 0.0);
+        assert_eq!(cmp.finger_load[&right_index].b, 0.0);
+
+        // C -> S happens twice in both logs, but is a larger share of the shorter log
+        let (id, delta) = &cmp.sfb_movers[0];
+        assert!(id.trim_start().starts_with("SE_C"));
+        assert!(delta.delta() > 0.0);
+
+        // L -> W is only in the full log
+        let (_, delta) = cmp
+            .sfb_movers
+            .iter()
+            .find(|(id, _)| id.trim_start().starts_with("SE_L"))
+            .unwrap();
+        assert_eq!(delta.b, 0.0);
+        assert!(delta.delta() < 0.0);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use camino::Utf8Path;
-use csv::ReaderBuilder;
-use eyre::Result;
+use csv::{ReaderBuilder, StringRecord};
+use eyre::{eyre, Result};
 use serde::Deserialize;
 use std::fs::File;
-use std::io::Cursor;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
 pub struct RawKeylogEntry {
@@ -14,31 +15,164 @@ pub struct RawKeylogEntry {
     pub pressed: usize,
     pub mods: String,         // hex
     pub oneshot_mods: String, // hex
-    pub tap_count: usize,     // or combo_index
+    pub tap_count: TapCount,  // or the combo
+    #[serde(default)]
+    pub timestamp: Option<u64>, // ms, not emitted by all keyloggers
+    #[serde(skip)]
+    pub line: u64, // in the csv file, for error messages
 }
 
-pub fn parse(keylog_file: &Utf8Path) -> Result<Vec<RawKeylogEntry>> {
-    let file = File::open(keylog_file)?;
+/// The tap count of a key, or the combo for a `COMBO` row.
+/// Combos are given by their index in combos.def or by their id.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TapCount {
+    Number(usize),
+    Name(String),
+}
 
-    let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(file);
+impl std::fmt::Display for TapCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TapCount::Number(x) => write!(f, "{x}"),
+            TapCount::Name(x) => write!(f, "{x}"),
+        }
+    }
+}
 
-    let mut res = Vec::new();
-    for row in rdr.deserialize() {
-        let entry: RawKeylogEntry = row?;
-        res.push(entry);
+/// The fields of `RawKeylogEntry` in the order of the keylog columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvFormat(StringRecord);
+
+const CSV_FIELDS: [&str; 9] = [
+    "keycode",
+    "row",
+    "col",
+    "highest_layer",
+    "pressed",
+    "mods",
+    "oneshot_mods",
+    "tap_count",
+    "timestamp",
+];
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self(StringRecord::from(CSV_FIELDS.to_vec()))
     }
-    Ok(res)
+}
+
+impl FromStr for CsvFormat {
+    type Err = eyre::Report;
+
+    /// A comma separated list of field names, such as `keycode,row,col,...`.
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<_> = s.split(',').map(str::trim).collect();
+        for field in &fields {
+            if !CSV_FIELDS.contains(field) {
+                return Err(eyre!(
+                    "Unknown keylog field `{field}`, expected one of: {}",
+                    CSV_FIELDS.join(", ")
+                ));
+            }
+        }
+        Ok(Self(StringRecord::from(fields)))
+    }
+}
+
+impl CsvFormat {
+    fn column(&self, field: &str) -> Option<usize> {
+        self.0.iter().position(|x| x == field)
+    }
+}
+
+pub fn parse(keylog_file: &Utf8Path, format: &CsvFormat) -> Result<Vec<RawKeylogEntry>> {
+    let file = File::open(keylog_file)?;
+    parse_from_reader(BufReader::new(file), format)
 }
 
 pub fn parse_from_str(s: &str) -> Result<Vec<RawKeylogEntry>> {
-    let cursor = Cursor::new(s);
+    parse_from_reader(Cursor::new(s), &CsvFormat::default())
+}
+
+fn parse_from_reader<R: BufRead>(mut reader: R, format: &CsvFormat) -> Result<Vec<RawKeylogEntry>> {
+    // Peek at the first line to see if the log starts with a header.
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    let has_headers = format
+        .column("highest_layer")
+        .is_some_and(|column| is_header(&first_line, column));
 
-    let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(cursor);
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(has_headers)
+        .flexible(true)
+        .from_reader(Cursor::new(first_line).chain(reader));
 
     let mut res = Vec::new();
-    for row in rdr.deserialize() {
-        let entry: RawKeylogEntry = row?;
+    for row in rdr.records() {
+        // Deserialize by the format, as header names may differ between loggers.
+        let row = row?;
+        let mut entry: RawKeylogEntry = row.deserialize(Some(&format.0))?;
+        entry.line = row.position().map(|x| x.line()).unwrap_or_default();
         res.push(entry);
     }
     Ok(res)
 }
+
+// A header has a column name where a data row has the numeric layer.
+fn is_header(line: &str, layer_column: usize) -> bool {
+    line.split(',')
+        .nth(layer_column)
+        .is_some_and(|x| x.trim().parse::<usize>().is_err())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_with_header() -> Result<()> {
+        let rows = "0x0001,1,0,0,1,0x00,0x00,1\nCOMBO,NA,NA,0,0,0,0,3";
+
+        let without_header = parse_from_str(rows)?;
+        assert_eq!(without_header.len(), 2);
+
+        let header = "keycode,row,col,layer,pressed,mods,oneshot_mods,tap_count,timestamp";
+        let with_header = parse_from_str(&format!("{header}\n{rows}"))?;
+        assert_eq!(with_header.len(), 2);
+        assert_eq!(with_header[0].keycode, "0x0001");
+        assert_eq!(with_header[1].keycode, "COMBO");
+        assert_eq!(with_header[1].tap_count, TapCount::Number(3));
+        assert_eq!(with_header[1].line, 3);
+
+        assert!(parse_from_str("")?.is_empty());
+
+        let by_name = parse_from_str("COMBO,NA,NA,0,0,0,0,escape_sym")?;
+        assert_eq!(by_name[0].tap_count, TapCount::Name("escape_sym".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_format() -> Result<()> {
+        let format: CsvFormat =
+            "row,col,keycode,pressed,highest_layer,tap_count,mods,oneshot_mods".parse()?;
+        let header = "row,col,kc,pressed,layer,taps,mods,osm";
+        let entries = parse_from_reader(
+            Cursor::new(format!("{header}\n1,0,0x0001,1,2,1,0,0")),
+            &format,
+        )?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].keycode, "0x0001");
+        assert_eq!(entries[0].row, "1");
+        assert_eq!(entries[0].col, "0");
+        assert_eq!(entries[0].highest_layer, 2);
+        assert_eq!(entries[0].pressed, 1);
+        assert_eq!(entries[0].timestamp, None);
+
+        let err = "keycode,layer".parse::<CsvFormat>().unwrap_err();
+        assert!(err.to_string().starts_with("Unknown keylog field `layer`"));
+
+        Ok(())
+    }
+}
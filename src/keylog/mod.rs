@@ -1,14 +1,32 @@
+mod compare;
 mod csv_parser;
+mod score;
 mod stats;
 
+use compare::*;
+use score::*;
 use stats::*;
 
-use crate::parse::InputInfo;
-use camino::Utf8Path;
+pub use csv_parser::CsvFormat;
+pub use stats::{KeylogStats, StatsOpts};
+
+use crate::parse::{FingerAssignment, InputInfo, MatrixHalf};
+use camino::{Utf8Path, Utf8PathBuf};
 use eyre::Result;
+use std::fs::File;
 
-pub fn output_stats(info: &InputInfo, keylog_file: &Utf8Path) -> Result<()> {
-    let stats = KeylogStats::from_file(info, keylog_file)?;
+pub fn output_stats(
+    info: &InputInfo,
+    keylog_files: &[Utf8PathBuf],
+    opts: &StatsOpts,
+    top: usize,
+    freq_csv: Option<&Utf8Path>,
+) -> Result<()> {
+    let stats = KeylogStats::from_files(info, keylog_files, opts)?;
+    if let Some(path) = freq_csv {
+        stats.write_frequency_csv(info, File::create(path)?)?;
+    }
+    let top = if top == 0 { usize::MAX } else { top };
 
     let mut list: Vec<_> = stats
         .output_frequency
@@ -22,34 +40,165 @@ pub fn output_stats(info: &InputInfo, keylog_file: &Utf8Path) -> Result<()> {
 
     let mut finger_row = String::new();
     let mut stats_row = String::new();
-    for (x, freq) in &stats.finger_frequency {
-        finger_row.push_str(&format!("{:>8}", x.finger.to_string()));
-        let perc = (*freq) as f32 / stats.total_key_presses as f32 * 100.0;
-        stats_row.push_str(&format!("{perc:>7.2}%"));
+    for (x, perc) in stats.finger_load() {
+        finger_row.push_str(&format!("{:>13}", x.to_string()));
+        stats_row.push_str(&format!("{perc:>12.2}%"));
     }
     println!();
     println!("{}", finger_row);
     println!("{}", stats_row);
     println!();
 
-    let left = stats.total_key_presses_left as f32 / stats.total_key_presses as f32 * 100.0;
-    println!("    left: {left:>7.2}%");
-    let right = stats.total_key_presses_right as f32 / stats.total_key_presses as f32 * 100.0;
-    println!("   right: {right:>7.2}%");
+    // A one-handed board has no balance between the hands
+    if !stats.one_handed {
+        let left = stats.total_key_presses_left as f32 / stats.total_key_presses as f32 * 100.0;
+        println!("    left: {left:>7.2}%");
+        let right = stats.total_key_presses_right as f32 / stats.total_key_presses as f32 * 100.0;
+        println!("   right: {right:>7.2}%");
+        println!();
+    }
+
+    let thumbs = stats.thumb_usage();
+    println!("  thumbs: {} ({:.2}%)", thumbs.total, thumbs.perc);
+    for (id, freq) in thumbs.keys.iter().take(top) {
+        let perc = *freq as f32 / stats.total_key_presses as f32 * 100.0;
+        println!("   {id:>22}: {perc:>7.2}%");
+    }
+    println!();
+
+    println!("  rows:");
+    for (row, freq) in &stats.row_frequency {
+        let perc = *freq as f32 / stats.total_key_presses as f32 * 100.0;
+        println!("  {row:>6}: {perc:>7.2}%");
+    }
+    println!("  home row: {:>7.2}%", stats.home_row_perc());
+    println!("  columns:");
+    for (col, freq) in &stats.col_frequency {
+        let perc = *freq as f32 / stats.total_key_presses as f32 * 100.0;
+        println!("  {col:>6}: {perc:>7.2}%");
+    }
+
+    if let Some(speed) = &stats.speed {
+        println!();
+        println!("  speed:");
+        println!("     avg wpm: {:>7.2}", speed.avg_wpm);
+        println!("    peak wpm: {:>7.2}", speed.peak_wpm);
+        println!("    interval: {:>7.2}ms", speed.avg_interval_ms);
+    }
 
-    output_sfbs(&stats, "sfbs (without combos)", false);
-    output_sfbs(&stats, "sfbs (with combos)", true);
+    println!();
+    println!("  layers:");
+    let usage = stats.layer_usage();
+    let total: u32 = usage.values().sum();
+    for layer in &info.keymap.layers {
+        let presses = usage.get(&layer.id).copied().unwrap_or(0);
+        let perc = presses as f32 / total.max(1) as f32 * 100.0;
+        println!("   {:>22}: {perc:>7.2}%", layer.id);
+    }
+    println!("  layer switches: {}", stats.layer_switches);
+
+    if !stats.one_handed {
+        println!();
+        let alternation = stats.hand_alternation_perc();
+        println!("  hand alternation: {alternation:>7.2}%");
+        println!("  same hand runs:");
+        for (len, count) in stats.same_hand_runs() {
+            println!("  {:>6}: {count}", run_length_label(len));
+        }
+    }
+
+    println!();
+    println!("  top bigrams:");
+    for ((a, b), freq) in stats.top_bigrams(top) {
+        let perc = freq as f32 / stats.total_events as f32 * 100.0;
+        println!("   {:>22}    {:<20}     {perc:>.2}%", a, b);
+    }
+
+    println!();
+    println!("  same hand finger transitions (from row to column):");
+    output_finger_transitions(&stats);
+
+    println!();
+    let scissors = stats.scissor_stats();
+    println!("  scissors: {} ({:.2}%)", scissors.total, scissors.perc);
+    for ((a, b), freq) in scissors.bigrams.iter().take(top) {
+        let perc = *freq as f32 / stats.total_events as f32 * 100.0;
+        println!("   {:>22}    {:<20}     {perc:>.2}%", a, b);
+    }
+
+    println!();
+    println!("  same finger runs:");
+    for (len, count) in stats.same_finger_runs() {
+        println!("  {:>6}: {count}", run_length_label(len));
+    }
+    println!("  top same finger runs:");
+    for (keys, count) in stats.top_same_finger_sequences(top) {
+        println!("   {:<35}     {count}", keys.join(" "));
+    }
+
+    if !stats.hold_times.is_empty() {
+        println!();
+        println!("  avg hold:");
+        for (id, ms) in stats.avg_hold_ms().into_iter().take(top) {
+            println!("   {id:>22}: {ms:>7.2}ms");
+        }
+    }
+
+    println!();
+    let saved: u32 = stats.keystrokes_saved.values().sum();
+    println!("  keystrokes saved by combos: {saved}");
+    for (id, saved) in stats.top_keystrokes_saved(top) {
+        println!("   {id:>22}: {saved}");
+    }
+
+    output_sfbs(&stats, "sfbs (without combos)", false, opts, top);
+    output_sfbs(&stats, "sfbs (with combos)", true, opts, top);
 
     Ok(())
 }
 
-fn output_sfbs(stats: &KeylogStats, title: &str, include_combos: bool) {
+// The last bucket of run lengths includes longer runs, see `MAX_RUN_BUCKET`.
+fn run_length_label(len: usize) -> String {
+    if len == MAX_RUN_BUCKET {
+        format!("{len}+")
+    } else {
+        len.to_string()
+    }
+}
+
+fn output_finger_transitions(stats: &KeylogStats) {
+    for half in [MatrixHalf::Left, MatrixHalf::Right] {
+        let fingers: Vec<_> = FingerAssignment::all().filter(|x| x.half == half).collect();
+
+        let mut header = format!("{:>13}", "");
+        for to in &fingers {
+            header.push_str(&format!("{:>13}", to.to_string()));
+        }
+        println!("{header}");
+        for from in &fingers {
+            let mut row = format!("{:>13}", from.to_string());
+            for to in &fingers {
+                let count = stats.finger_transitions.get(&(*from, *to)).unwrap_or(&0);
+                row.push_str(&format!("{count:>13}"));
+            }
+            println!("{row}");
+        }
+    }
+}
+
+fn output_sfbs(
+    stats: &KeylogStats,
+    title: &str,
+    include_combos: bool,
+    opts: &StatsOpts,
+    top: usize,
+) {
     let mut finger_row = String::new();
     let mut stats_row = String::new();
     for (finger, presses) in &stats.sfb_frequency_by_finger(include_combos) {
-        finger_row.push_str(&format!("{:>8}", finger.finger.to_string()));
+        finger_row.push_str(&format!("{:>13}", finger.to_string()));
         let perc = *presses as f32 / stats.total_events as f32 * 100.0;
-        stats_row.push_str(&format!("{perc:>7.2}%"));
+        stats_row.push_str(&format!("{perc:>12.2}%"));
     }
     println!();
     println!();
@@ -57,19 +206,127 @@ fn output_sfbs(stats: &KeylogStats, title: &str, include_combos: bool) {
     println!("{}", finger_row);
     println!("{}", stats_row);
     println!();
-    let perc = stats.sfb_perc(include_combos);
-    println!("  total: {perc:>7.3}%",);
+    if stats.min_sfb_count > 1 {
+        let perc = stats.filtered_sfb_perc(include_combos);
+        println!("  total: {perc:>7.3}% (filtered)");
+    } else {
+        let perc = stats.sfb_perc(include_combos);
+        println!("  total: {perc:>7.3}%",);
+    }
+    let thumbs = stats.thumb_sfb_perc(include_combos);
+    if stats.exclude_thumbs {
+        println!("  thumbs: {thumbs:>7.3}% (excluded)");
+    } else {
+        println!("  thumbs: {thumbs:>7.3}%");
+    }
+    if !stats.one_handed {
+        let by_hand = stats.sfb_perc_by_hand(include_combos);
+        for half in [MatrixHalf::Left, MatrixHalf::Right] {
+            let perc = by_hand.get(&half).unwrap_or(&0.0);
+            println!("  {:>5}: {perc:>7.3}%", half.to_string());
+        }
+    }
+    let weighted = stats.sfb_weighted_perc(include_combos);
+    println!("  weighted by distance: {weighted:>7.3}%");
+    let travel = stats.avg_sfb_travel(include_combos);
+    println!("  avg travel: {travel:>7.2} keys");
 
     println!("  top sfbs:");
-    for sfb in stats.top_sfbs(10, include_combos) {
+    for sfb in stats.top_sfbs(top, include_combos) {
         let perc = sfb.presses as f32 / stats.total_events as f32 * 100.0;
         println!("   {:<35}     {perc:>.2}%", sfb.sfb.id());
     }
 
+    if opts.sfb_severity {
+        println!();
+        println!("  top sfbs by severity:");
+        for sfb in stats.top_sfbs_by_severity(top, include_combos) {
+            let weighted = sfb.weighted() / stats.total_events as f32 * 100.0;
+            println!("   {:<35}     {weighted:>.2}%", sfb.sfb.id());
+        }
+    }
+
     println!();
     println!("  top sfbs by key:");
-    for (id, freq) in stats.top_sfbs_by_key(10, include_combos) {
+    for (id, freq) in stats.top_sfbs_by_key(top, include_combos) {
         let perc = freq as f32 / stats.total_events as f32 * 100.0;
         println!("   {:<35}     {perc:>.2}%", id);
     }
 }
+
+pub fn output_compare(
+    info: &InputInfo,
+    log_a: &Utf8Path,
+    log_b: &Utf8Path,
+    opts: &StatsOpts,
+    top: usize,
+) -> Result<()> {
+    let a = KeylogStats::from_file(info, log_a, opts)?;
+    let b = KeylogStats::from_file(info, log_b, opts)?;
+    let top = if top == 0 { usize::MAX } else { top };
+    let cmp = KeylogComparison::new(&a, &b, false);
+
+    println!("  a: {log_a}");
+    println!("  b: {log_b}");
+    println!();
+    println!("{:>24} {:>8} {:>8} {:>8}", "", "a", "b", "delta");
+    output_delta("sfbs (without combos)", &cmp.sfb_perc);
+    output_delta("sfbs (with combos)", &cmp.sfb_perc_with_combos);
+    output_delta("left", &cmp.left_hand);
+    output_delta("right", &cmp.right_hand);
+
+    println!();
+    println!("  finger load:");
+    for (finger, delta) in &cmp.finger_load {
+        output_delta(&finger.to_string(), delta);
+    }
+
+    println!();
+    println!("  finger sfbs:");
+    for (finger, delta) in &cmp.finger_sfbs {
+        output_delta(&finger.to_string(), delta);
+    }
+
+    println!();
+    println!("  top sfb movers:");
+    for (id, delta) in cmp.sfb_movers.iter().take(top) {
+        println!(
+            "   {:<35} {:>7.2}% {:>7.2}% {:>+7.2}%",
+            id,
+            delta.a,
+            delta.b,
+            delta.delta()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn output_score(info: &InputInfo, keylog_file: &Utf8Path, opts: &StatsOpts) -> Result<()> {
+    let stats = KeylogStats::from_file(info, keylog_file, opts)?;
+    let score = LayoutScore::new(&stats, &info.render_opts.score_weights);
+
+    println!("{:>16} {:>8} {:>8} {:>8}", "", "value", "weight", "score");
+    for component in &score.components {
+        println!(
+            "{:>16} {:>8.2} {:>8.2} {:>8.2}",
+            component.name,
+            component.value,
+            component.weight,
+            component.weighted()
+        );
+    }
+    println!();
+    println!("{:>16} {:>26.2}", "total", score.total());
+
+    Ok(())
+}
+
+fn output_delta(title: &str, delta: &PercDelta) {
+    println!(
+        "{title:>24} {:>7.2}% {:>7.2}% {:>+7.2}%",
+        delta.a,
+        delta.b,
+        delta.delta()
+    );
+}
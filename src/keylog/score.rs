@@ -0,0 +1,110 @@
+use super::stats::KeylogStats;
+use crate::parse::{Finger, ScoreWeights};
+
+/// One weighted part of the layout score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreComponent {
+    pub name: &'static str,
+    pub value: f32,
+    pub weight: f32,
+}
+
+impl ScoreComponent {
+    pub fn weighted(&self) -> f32 {
+        self.value * self.weight
+    }
+}
+
+/// A single number summary of a layout, lower is better.
+#[derive(Debug, Clone)]
+pub struct LayoutScore {
+    pub components: Vec<ScoreComponent>,
+}
+
+impl LayoutScore {
+    pub fn new(stats: &KeylogStats, weights: &ScoreWeights) -> Self {
+        let perc = |x: u32| x as f32 / stats.total_key_presses.max(1) as f32 * 100.0;
+
+        let pinky: u32 = stats
+            .finger_frequency
+            .iter()
+            .filter(|(finger, _)| finger.finger == Finger::Pinky)
+            .map(|(_, presses)| presses)
+            .sum();
+        let imbalance = if stats.one_handed {
+            0.0
+        } else {
+            (perc(stats.total_key_presses_left) - perc(stats.total_key_presses_right)).abs()
+        };
+        let effort = stats.total_effort as f32 / stats.total_key_presses.max(1) as f32;
+
+        Self {
+            components: vec![
+                ScoreComponent {
+                    name: "sfbs",
+                    value: stats.sfb_perc(true),
+                    weight: weights.sfb,
+                },
+                ScoreComponent {
+                    name: "pinky load",
+                    value: perc(pinky),
+                    weight: weights.pinky,
+                },
+                ScoreComponent {
+                    name: "hand imbalance",
+                    value: imbalance,
+                    weight: weights.imbalance,
+                },
+                ScoreComponent {
+                    name: "effort",
+                    value: effort,
+                    weight: weights.effort,
+                },
+            ],
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.components.iter().map(|x| x.weighted()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keylog::csv_parser;
+    use crate::keylog::stats::tests::{test_info, test_keylog};
+    use crate::keylog::stats::StatsOpts;
+    use eyre::Result;
+
+    #[test]
+    fn test_score() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let weights = ScoreWeights {
+            sfb: 2.0,
+            pinky: 0.0,
+            imbalance: 0.0,
+            effort: 0.0,
+        };
+        let score = LayoutScore::new(&stats, &weights);
+        assert_eq!(score.components.len(), 4);
+        assert_eq!(score.components[0].value, stats.sfb_perc(true));
+        assert_eq!(score.total(), stats.sfb_perc(true) * 2.0);
+
+        let effort = &score.components[3];
+        assert_eq!(effort.name, "effort");
+        assert!(effort.value > 0.0);
+        assert_eq!(effort.weighted(), 0.0);
+
+        // An empty log scores zero rather than NaN
+        let entries = csv_parser::parse_from_str("")?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+        let score = LayoutScore::new(&stats, &weights);
+        assert!(score.components.iter().all(|x| x.value == 0.0));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use super::csv_parser::{self, RawKeylogEntry};
+use super::csv_parser::{self, CsvFormat, RawKeylogEntry, TapCount};
 use crate::parse::Combo;
+use crate::parse::ComboKind;
 use crate::parse::Finger;
 use crate::parse::FingerAssignment;
 use crate::parse::InputInfo;
@@ -7,27 +8,75 @@ use crate::parse::Key;
 use crate::parse::KeyId;
 use crate::parse::LayerId;
 use crate::parse::MatrixHalf;
-use camino::Utf8Path;
-use eyre::{OptionExt, Result};
+use crate::parse::PhysicalPos;
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{eyre, OptionExt, Result};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::hash::Hash;
 
 #[derive(Debug)]
 pub struct KeylogStats {
     pub output_frequency: HashMap<String, u32>,
     pub finger_frequency: BTreeMap<FingerAssignment, u32>,
+    // Key presses by the row and column of the physical layout
+    pub row_frequency: BTreeMap<usize, u32>,
+    pub col_frequency: BTreeMap<usize, u32>,
+    // Key presses by layer and the (col, row) of the physical layout, including the keys
+    // of combos, which count on the base layer
+    pub position_frequency: HashMap<(LayerId, (usize, usize)), u32>,
+    // Key presses on the thumbs by key id, including the keys of combos
+    pub thumb_key_frequency: HashMap<String, u32>,
+    // See `PhysicalLayout::home_row`
+    pub home_row: usize,
+    // See `PhysicalLayout::is_one_handed`
+    pub one_handed: bool,
     // One combo produces a single event (relevant for sfb calculations)
     pub total_events: u32,
     // Note that one combo can produce multiple key presses
     pub total_key_presses: u32,
     pub total_key_presses_left: u32,
     pub total_key_presses_right: u32,
+    // Sum of the effort of every key press
+    pub total_effort: u32,
     pub sfb_series: Vec<Sfb>,
     pub sfbs: Vec<SfbStats>,
     pub sfbs_by_finger: BTreeMap<FingerAssignment, HashMap<String, SfbStats>>,
     pub sfbs_by_id: HashMap<String, SfbStats>,
+    // Only available if the keylog contains timestamps
+    pub speed: Option<SpeedStats>,
+    pub hand_alternations: u32,
+    pub same_hand_bigrams: u32,
+    // Length of runs of events on the same hand -> number of runs
+    pub same_hand_run_lengths: BTreeMap<usize, u32>,
+    // Length of runs of single key presses on the same finger -> number of runs,
+    // a run of two is an sfb. Combos interrupt a run.
+    pub same_finger_run_lengths: BTreeMap<usize, u32>,
+    // The keys of same finger runs longer than an sfb -> number of runs
+    pub same_finger_sequences: HashMap<Vec<String>, u32>,
+    // Consecutive single key presses by key id. Combos aren't counted and
+    // break up bigrams, as they don't roll from or into a key.
+    pub bigram_frequency: HashMap<(String, String), u32>,
+    // The bigrams above that are scissors, see `is_scissor`
+    pub scissor_frequency: HashMap<(String, String), u32>,
+    // The bigrams above typed with the same hand, by the fingers used
+    pub finger_transitions: BTreeMap<(FingerAssignment, FingerAssignment), u32>,
+    // Single key presses by the highest active layer, combos don't log a layer
+    pub layer_frequency: HashMap<LayerId, u32>,
+    // Consecutive single key presses on different layers
+    pub layer_switches: u32,
+    // Combo id -> keystrokes saved compared to typing the output
+    pub keystrokes_saved: HashMap<String, u32>,
+    // Key id -> time between press and release, only with `StatsOpts::hold_durations`
+    pub hold_times: HashMap<String, HoldTime>,
+    // Only report sfbs and frequencies for this finger
+    pub finger_filter: Option<FingerAssignment>,
+    // Only report sfbs that occur at least this many times
+    pub min_sfb_count: u32,
+    // Leave out sfbs on the thumbs, see `Sfb::is_thumb_sfb`
+    pub exclude_thumbs: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -48,21 +97,81 @@ impl Ord for SfbStats {
     }
 }
 
+impl SfbStats {
+    /// Presses weighted by the travel distance of the sfb.
+    pub fn weighted(&self) -> f32 {
+        self.presses as f32 * self.sfb.severity()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct StatsOpts {
+    /// Count a press of the repeat key as a press of the key it repeats.
+    pub resolve_repeat: bool,
+    /// List the sfbs with the highest distance weighted count.
+    pub sfb_severity: bool,
+    /// Restrict the sfbs and finger frequencies to a single finger.
+    pub finger: Option<FingerAssignment>,
+    /// Skip rows with unknown keys or combos instead of failing.
+    pub skip_unknown: bool,
+    /// Only list sfbs that occur at least this many times.
+    pub min_count: u32,
+    /// The order of the columns in the keylog.
+    pub csv_format: CsvFormat,
+    /// Key ids and combo outputs to drop from the log, as if they weren't pressed.
+    pub exclude: HashSet<String>,
+    /// Leave out sfbs on the thumbs.
+    pub exclude_thumbs: bool,
+    /// Pair presses with releases to find how long keys are held, see `avg_hold_ms`.
+    pub hold_durations: bool,
+}
+
 impl KeylogStats {
-    pub fn from_file(info: &InputInfo, keylog_file: &Utf8Path) -> Result<Self> {
-        let raw_entries = csv_parser::parse(keylog_file)?;
-        Self::from_entries(info, raw_entries)
+    pub fn from_file(info: &InputInfo, keylog_file: &Utf8Path, opts: &StatsOpts) -> Result<Self> {
+        let raw_entries = csv_parser::parse(keylog_file, &opts.csv_format)?;
+        Self::from_entries(info, raw_entries, opts)
+    }
+
+    /// The stats of several keylogs combined, see `merge`.
+    pub fn from_files(
+        info: &InputInfo,
+        keylog_files: &[Utf8PathBuf],
+        opts: &StatsOpts,
+    ) -> Result<Self> {
+        let (first, rest) = keylog_files
+            .split_first()
+            .ok_or_eyre("No keylog files given")?;
+        let mut stats = Self::from_file(info, first, opts)?;
+        for file in rest {
+            stats.merge(&Self::from_file(info, file, opts)?);
+        }
+        Ok(stats)
     }
 
-    pub fn from_entries(info: &InputInfo, raw_entries: Vec<RawKeylogEntry>) -> Result<Self> {
-        let entries = convert_keylog_entries(&raw_entries, info)?;
+    pub fn from_entries(
+        info: &InputInfo,
+        raw_entries: Vec<RawKeylogEntry>,
+        opts: &StatsOpts,
+    ) -> Result<Self> {
+        let entries = convert_keylog_entries(&raw_entries, info, opts)?;
+        let hold_times = if opts.hold_durations {
+            find_hold_times(&raw_entries, info, opts)
+        } else {
+            HashMap::new()
+        };
 
         let mut frequency = HashMap::new();
         let mut finger_frequency = BTreeMap::new();
+        let mut row_frequency = BTreeMap::new();
+        let mut col_frequency = BTreeMap::new();
+        let mut position_frequency = HashMap::new();
+        let mut thumb_key_frequency = HashMap::new();
+        let mut total_effort = 0;
+        let base_layer = &info.keymap.layers[0].id;
 
         for entry in &entries {
             match entry {
-                KeylogEntry::Combo(combo) => {
+                KeylogEntry::Combo { combo, .. } => {
                     frequency
                         .entry(combo.output.to_string())
                         .and_modify(|x| *x += 1)
@@ -72,9 +181,20 @@ impl KeylogStats {
                             .entry(key.physical_pos.finger)
                             .and_modify(|x| *x += 1)
                             .or_insert(1);
+                        *row_frequency.entry(key.physical_pos.row).or_insert(0) += 1;
+                        *col_frequency.entry(key.physical_pos.col).or_insert(0) += 1;
+                        *position_frequency
+                            .entry((base_layer.clone(), key.physical_pos.pos()))
+                            .or_insert(0) += 1;
+                        if key.physical_pos.finger.finger == Finger::Thumb {
+                            *thumb_key_frequency.entry(key.id.0.clone()).or_insert(0) += 1;
+                        }
+                        total_effort += key.physical_pos.effort;
                     }
                 }
-                KeylogEntry::Single { key, .. } => {
+                KeylogEntry::Single {
+                    key, highest_layer, ..
+                } => {
                     frequency
                         .entry(key.id.0.to_string())
                         .and_modify(|x| *x += 1)
@@ -83,6 +203,15 @@ impl KeylogStats {
                         .entry(key.physical_pos.finger)
                         .and_modify(|x| *x += 1)
                         .or_insert(1);
+                    *row_frequency.entry(key.physical_pos.row).or_insert(0) += 1;
+                    *col_frequency.entry(key.physical_pos.col).or_insert(0) += 1;
+                    *position_frequency
+                        .entry((highest_layer.clone(), key.physical_pos.pos()))
+                        .or_insert(0) += 1;
+                    if key.physical_pos.finger.finger == Finger::Thumb {
+                        *thumb_key_frequency.entry(key.id.0.clone()).or_insert(0) += 1;
+                    }
+                    total_effort += key.physical_pos.effort;
                 }
             }
         }
@@ -98,13 +227,25 @@ impl KeylogStats {
             }
         }
 
-        let sfb_series: Vec<Sfb> = entries
+        let mut sfb_series: Vec<Sfb> = entries
             .iter()
             .zip(entries.iter().skip(1))
             .filter_map(|(current, next)| Sfb::new_if_sfb(current, next))
             // .filter(|x| !x.has_key("SE_J"))
             // .filter(|x| !x.has_key("SE_K"))
             .collect();
+        // The keys of a sequence combo are typed one after another
+        for entry in &entries {
+            if let KeylogEntry::Combo { combo, .. } = entry {
+                for (first_key, second_key) in combo.sequence_sfbs() {
+                    sfb_series.push(Sfb::Single {
+                        first_key: first_key.clone(),
+                        second_key: second_key.clone(),
+                        finger: first_key.physical_pos.finger,
+                    });
+                }
+            }
+        }
 
         let mut sfbs_by_id: HashMap<String, SfbStats> = HashMap::new();
         // let mut sfb_frequency_by_key: HashMap<KeyId, u32> = HashMap::new();
@@ -118,36 +259,86 @@ impl KeylogStats {
                 });
         }
 
-        let mut sfbs: Vec<SfbStats> = Vec::new();
-        let mut sfbs_by_finger: BTreeMap<FingerAssignment, HashMap<String, SfbStats>> =
-            BTreeMap::new();
-        for (_id, sfb) in sfbs_by_id.iter() {
-            sfbs.push(sfb.clone());
-            for finger in sfb.sfb.get_fingers() {
-                sfbs_by_finger
-                    .entry(finger)
-                    .and_modify(|x| {
-                        x.entry(sfb.sfb.id())
-                            .and_modify(|x| x.presses += sfb.presses)
-                            .or_insert_with(|| SfbStats {
-                                presses: sfb.presses,
-                                sfb: sfb.sfb.clone(),
-                            });
-                    })
-                    .or_insert_with(|| {
-                        [(
-                            sfb.sfb.id(),
-                            SfbStats {
-                                presses: sfb.presses,
-                                sfb: sfb.sfb.clone(),
-                            },
-                        )]
-                        .into_iter()
-                        .collect()
-                    });
+        let (sfbs, sfbs_by_finger) = group_sfbs(&sfbs_by_id);
+
+        let mut same_finger_run_lengths = BTreeMap::new();
+        let mut same_finger_sequences = HashMap::new();
+        for run in find_same_finger_runs(&entries) {
+            *same_finger_run_lengths.entry(run.len()).or_insert(0) += 1;
+            if run.len() > 2 {
+                *same_finger_sequences.entry(run).or_insert(0) += 1;
+            }
+        }
+
+        let timestamps: Vec<u64> = entries.iter().filter_map(|x| x.timestamp()).collect();
+        let speed = SpeedStats::from_timestamps(&timestamps);
+
+        let mut hand_alternations = 0;
+        let mut same_hand_bigrams = 0;
+        let mut same_hand_run_lengths = BTreeMap::new();
+        let mut run_length = 1;
+        for (current, next) in entries.iter().zip(entries.iter().skip(1)) {
+            if current.is_same_hand(next) {
+                same_hand_bigrams += 1;
+                run_length += 1;
+            } else {
+                hand_alternations += 1;
+                if run_length > 1 {
+                    *same_hand_run_lengths.entry(run_length).or_insert(0) += 1;
+                }
+                run_length = 1;
+            }
+        }
+        if run_length > 1 {
+            *same_hand_run_lengths.entry(run_length).or_insert(0) += 1;
+        }
+
+        let mut layer_frequency = HashMap::new();
+        let mut layer_switches = 0;
+        let mut prev_layer = None;
+        for entry in &entries {
+            if let KeylogEntry::Single { highest_layer, .. } = entry {
+                *layer_frequency.entry(highest_layer.clone()).or_insert(0) += 1;
+                if prev_layer.is_some_and(|prev| prev != highest_layer) {
+                    layer_switches += 1;
+                }
+                prev_layer = Some(highest_layer);
+            }
+        }
+
+        let mut keystrokes_saved = HashMap::new();
+        for entry in &entries {
+            if let KeylogEntry::Combo { combo, .. } = entry {
+                // A `COMB` replaces a single keystroke, only strings save any
+                if combo.kind != ComboKind::Subs {
+                    continue;
+                }
+                // A combo is pressed as a single chord
+                let saved = combo.output_len().saturating_sub(1) as u32;
+                if saved > 0 {
+                    *keystrokes_saved.entry(combo.id.clone()).or_insert(0) += saved;
+                }
+            }
+        }
+
+        let mut bigram_frequency = HashMap::new();
+        let mut scissor_frequency = HashMap::new();
+        let mut finger_transitions = BTreeMap::new();
+        for (current, next) in entries.iter().zip(entries.iter().skip(1)) {
+            if let (KeylogEntry::Single { key: a, .. }, KeylogEntry::Single { key: b, .. }) =
+                (current, next)
+            {
+                let bigram = (a.id.0.clone(), b.id.0.clone());
+                if is_scissor(&a.physical_pos, &b.physical_pos) {
+                    *scissor_frequency.entry(bigram.clone()).or_insert(0) += 1;
+                }
+                let (from, to) = (a.physical_pos.finger, b.physical_pos.finger);
+                if from.half == to.half {
+                    *finger_transitions.entry((from, to)).or_insert(0) += 1;
+                }
+                *bigram_frequency.entry(bigram).or_insert(0) += 1;
             }
         }
-        sfbs.sort();
 
         Ok(Self {
             sfbs,
@@ -157,39 +348,298 @@ impl KeylogStats {
             sfb_series,
             output_frequency: frequency,
             finger_frequency,
+            row_frequency,
+            col_frequency,
+            position_frequency,
+            thumb_key_frequency,
+            home_row: info.render_opts.physical_layout.home_row(),
+            one_handed: info.render_opts.physical_layout.is_one_handed(),
             total_key_presses: total_presses,
             total_key_presses_left: total_left,
             total_key_presses_right: total_right,
+            total_effort,
+            speed,
+            hand_alternations,
+            same_hand_bigrams,
+            same_hand_run_lengths,
+            same_finger_run_lengths,
+            same_finger_sequences,
+            bigram_frequency,
+            scissor_frequency,
+            finger_transitions,
+            layer_frequency,
+            layer_switches,
+            keystrokes_saved,
+            hold_times,
+            finger_filter: opts.finger,
+            min_sfb_count: opts.min_count,
+            exclude_thumbs: opts.exclude_thumbs,
         })
     }
 
+    /// Key presses on a layer by the (col, row) of the physical layout.
+    pub fn layer_position_frequency(&self, layer: &LayerId) -> HashMap<(usize, usize), u32> {
+        self.position_frequency
+            .iter()
+            .filter(|((id, _), _)| id == layer)
+            .map(|((_, pos), count)| (*pos, *count))
+            .collect()
+    }
+
+    /// Combines the stats of another keylog, such as the log of another day.
+    /// Sfbs and other sequences are only counted within each log, a bigram
+    /// across the end of one log and the start of the other isn't counted.
+    pub fn merge(&mut self, other: &KeylogStats) {
+        fn sum<K: Clone + Eq + Hash>(a: &mut HashMap<K, u32>, b: &HashMap<K, u32>) {
+            for (k, v) in b {
+                *a.entry(k.clone()).or_insert(0) += v;
+            }
+        }
+        fn sum_ordered<K: Clone + Ord>(a: &mut BTreeMap<K, u32>, b: &BTreeMap<K, u32>) {
+            for (k, v) in b {
+                *a.entry(k.clone()).or_insert(0) += v;
+            }
+        }
+
+        sum(&mut self.output_frequency, &other.output_frequency);
+        sum_ordered(&mut self.finger_frequency, &other.finger_frequency);
+        sum_ordered(&mut self.row_frequency, &other.row_frequency);
+        sum_ordered(&mut self.col_frequency, &other.col_frequency);
+        sum(&mut self.position_frequency, &other.position_frequency);
+        sum(&mut self.thumb_key_frequency, &other.thumb_key_frequency);
+        self.total_key_presses += other.total_key_presses;
+        self.total_key_presses_left += other.total_key_presses_left;
+        self.total_key_presses_right += other.total_key_presses_right;
+        self.total_effort += other.total_effort;
+
+        self.speed = match (self.speed.take(), &other.speed) {
+            (Some(a), Some(b)) => Some(a.merge(b, self.total_events, other.total_events)),
+            (a, b) => a.or_else(|| b.clone()),
+        };
+        self.total_events += other.total_events;
+
+        self.sfb_series.extend(other.sfb_series.iter().cloned());
+        for (id, sfb) in &other.sfbs_by_id {
+            self.sfbs_by_id
+                .entry(id.clone())
+                .and_modify(|x| x.presses += sfb.presses)
+                .or_insert_with(|| sfb.clone());
+        }
+        (self.sfbs, self.sfbs_by_finger) = group_sfbs(&self.sfbs_by_id);
+
+        self.hand_alternations += other.hand_alternations;
+        self.same_hand_bigrams += other.same_hand_bigrams;
+        for (len, runs) in &other.same_hand_run_lengths {
+            *self.same_hand_run_lengths.entry(*len).or_insert(0) += runs;
+        }
+        sum_ordered(
+            &mut self.same_finger_run_lengths,
+            &other.same_finger_run_lengths,
+        );
+        sum(
+            &mut self.same_finger_sequences,
+            &other.same_finger_sequences,
+        );
+        sum(&mut self.bigram_frequency, &other.bigram_frequency);
+        sum(&mut self.scissor_frequency, &other.scissor_frequency);
+        sum_ordered(&mut self.finger_transitions, &other.finger_transitions);
+        sum(&mut self.layer_frequency, &other.layer_frequency);
+        self.layer_switches += other.layer_switches;
+        sum(&mut self.keystrokes_saved, &other.keystrokes_saved);
+        for (id, hold) in &other.hold_times {
+            let x = self.hold_times.entry(id.clone()).or_default();
+            x.total_ms += hold.total_ms;
+            x.holds += hold.holds;
+        }
+    }
+
+    /// The number of single key presses made on each layer.
+    pub fn layer_usage(&self) -> &HashMap<LayerId, u32> {
+        &self.layer_frequency
+    }
+
+    /// Percentage of key presses on the home row.
+    pub fn home_row_perc(&self) -> f32 {
+        let presses = self.row_frequency.get(&self.home_row).copied().unwrap_or(0);
+        presses as f32 / self.total_key_presses as f32 * 100.0
+    }
+
+    pub fn scissor_stats(&self) -> ScissorStats {
+        let total: u32 = self.scissor_frequency.values().sum();
+        let mut bigrams: Vec<_> = self
+            .scissor_frequency
+            .iter()
+            .map(|(bigram, freq)| (bigram.clone(), *freq))
+            .collect();
+        // Most frequent first, ties ordered by bigram for a stable output
+        bigrams.sort_by(|(a_bigram, a_freq), (b_bigram, b_freq)| {
+            b_freq.cmp(a_freq).then_with(|| a_bigram.cmp(b_bigram))
+        });
+
+        ScissorStats {
+            total,
+            perc: total as f32 / self.total_events as f32 * 100.0,
+            bigrams,
+        }
+    }
+
+    /// The percentage of key presses per finger, in the order of `FingerAssignment::all`.
+    /// Fingers without any presses are included, so tables line up across keylogs.
+    pub fn finger_load(&self) -> Vec<(FingerAssignment, f32)> {
+        FingerAssignment::all()
+            .filter(|x| self.includes_finger(x))
+            .map(|x| {
+                let freq = self.finger_frequency.get(&x).copied().unwrap_or(0);
+                (x, freq as f32 / self.total_key_presses as f32 * 100.0)
+            })
+            .collect()
+    }
+
+    /// Presses on the thumbs, as a thumb can press several keys comfortably.
+    pub fn thumb_usage(&self) -> ThumbUsage {
+        let total: u32 = self
+            .finger_frequency
+            .iter()
+            .filter(|(x, _)| x.finger == Finger::Thumb)
+            .map(|(_, freq)| freq)
+            .sum();
+        let mut keys: Vec<_> = self
+            .thumb_key_frequency
+            .iter()
+            .map(|(id, freq)| (id.clone(), *freq))
+            .collect();
+        // Most frequent first, ties ordered by key for a stable output
+        keys.sort_by(|(a_id, a_freq), (b_id, b_freq)| {
+            b_freq.cmp(a_freq).then_with(|| a_id.cmp(b_id))
+        });
+
+        ThumbUsage {
+            total,
+            perc: total as f32 / self.total_key_presses.max(1) as f32 * 100.0,
+            keys,
+        }
+    }
+
+    /// False if the stats are filtered to another finger.
+    pub fn includes_finger(&self, finger: &FingerAssignment) -> bool {
+        self.finger_filter.is_none_or(|x| x == *finger)
+    }
+
+    fn includes_sfb(&self, sfb: &Sfb, include_combos: bool) -> bool {
+        self.counts_sfb(sfb, include_combos)
+            && self
+                .finger_filter
+                .is_none_or(|x| sfb.get_fingers().contains(&x))
+    }
+
+    // If the sfb is part of the totals, which aren't filtered by finger.
+    fn counts_sfb(&self, sfb: &Sfb, include_combos: bool) -> bool {
+        (include_combos || !sfb.has_combo()) && !(self.exclude_thumbs && sfb.is_thumb_sfb())
+    }
+
+    /// Share of events that are sfbs on the thumbs, whether they're excluded or not.
+    pub fn thumb_sfb_perc(&self, include_combos: bool) -> f32 {
+        let sfb_events = self
+            .sfb_series
+            .iter()
+            .filter(|x| include_combos || !x.has_combo())
+            .filter(|x| x.is_thumb_sfb())
+            .count();
+        sfb_events as f32 / self.total_events.max(1) as f32 * 100.0
+    }
+
+    /// Writes `key_id,frequency,finger,hand,effort` rows, most frequent first.
+    /// The key is looked up in the base layer first, outputs that aren't on any
+    /// layer, such as combo outputs, have empty key columns.
+    pub fn write_frequency_csv(&self, info: &InputInfo, out: impl std::io::Write) -> Result<()> {
+        let mut list: Vec<_> = self.output_frequency.iter().collect();
+        // Ties ordered by key for a stable output
+        list.sort_by(|(a_key, a_freq), (b_key, b_freq)| {
+            b_freq.cmp(a_freq).then_with(|| a_key.cmp(b_key))
+        });
+
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(["key_id", "frequency", "finger", "hand", "effort"])?;
+        for (id, freq) in list {
+            let key = info
+                .keymap
+                .layers
+                .iter()
+                .find_map(|layer| layer.find_key_by_id(id));
+            let (finger, hand, effort) = match key {
+                Some(key) => {
+                    let pos = &key.physical_pos;
+                    (
+                        pos.finger.finger.to_string(),
+                        pos.finger.half.to_string(),
+                        pos.effort.to_string(),
+                    )
+                }
+                None => Default::default(),
+            };
+            writer.write_record([id, &freq.to_string(), &finger, &hand, &effort])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The average time each key is held in ms, longest first.
+    pub fn avg_hold_ms(&self) -> Vec<(&String, f32)> {
+        let mut res: Vec<_> = self
+            .hold_times
+            .iter()
+            .map(|(id, hold)| (id, hold.avg_ms()))
+            .collect();
+        // Ties ordered by key for a stable output
+        res.sort_by(|(a_id, a_ms), (b_id, b_ms)| b_ms.total_cmp(a_ms).then_with(|| a_id.cmp(b_id)));
+        res
+    }
+
+    pub fn top_keystrokes_saved(&self, count: usize) -> Vec<(&String, u32)> {
+        let mut res: Vec<_> = self
+            .keystrokes_saved
+            .iter()
+            .map(|(id, saved)| (id, *saved))
+            .collect();
+        // Most saved first, ties ordered by id for a stable output
+        res.sort_by(|(a_id, a_saved), (b_id, b_saved)| {
+            b_saved.cmp(a_saved).then_with(|| a_id.cmp(b_id))
+        });
+        res.into_iter().take(count).collect()
+    }
+
+    pub fn top_bigrams(&self, count: usize) -> Vec<(&(String, String), u32)> {
+        let mut res: Vec<_> = self
+            .bigram_frequency
+            .iter()
+            .map(|(bigram, freq)| (bigram, *freq))
+            .collect();
+        // Most frequent first, ties ordered by bigram for a stable output
+        res.sort_by(|(a_bigram, a_freq), (b_bigram, b_freq)| {
+            b_freq.cmp(a_freq).then_with(|| a_bigram.cmp(b_bigram))
+        });
+        res.into_iter().take(count).collect()
+    }
+
     pub fn top_sfbs(&self, count: usize, include_combos: bool) -> impl Iterator<Item = &SfbStats> {
         self.sfbs
             .iter()
             .rev()
-            .filter(move |x| {
-                if !include_combos {
-                    !x.sfb.has_combo()
-                } else {
-                    true
-                }
-            })
+            .filter(move |x| self.includes_sfb(&x.sfb, include_combos))
+            .filter(|x| x.presses >= self.min_sfb_count)
             .take(count)
     }
 
     pub fn sfb_frequency_by_finger(&self, include_combos: bool) -> BTreeMap<FingerAssignment, u32> {
         self.sfbs_by_finger
             .iter()
+            .filter(|(finger, _)| self.includes_finger(finger))
+            .filter(|(finger, _)| !(self.exclude_thumbs && finger.finger == Finger::Thumb))
             .map(|(finger, sfbs_by_id)| {
                 let presses: u32 = sfbs_by_id
                     .values()
-                    .filter(move |x| {
-                        if !include_combos {
-                            !x.sfb.has_combo()
-                        } else {
-                            true
-                        }
-                    })
+                    .filter(|x| self.counts_sfb(&x.sfb, include_combos))
+                    .filter(|x| x.presses >= self.min_sfb_count)
                     .map(|x| x.presses)
                     .sum();
                 (*finger, presses)
@@ -200,13 +650,7 @@ impl KeylogStats {
     pub fn sfb_event_count(&self, include_combos: bool) -> u32 {
         self.sfb_series
             .iter()
-            .filter(move |x| {
-                if !include_combos {
-                    !x.has_combo()
-                } else {
-                    true
-                }
-            })
+            .filter(|x| self.counts_sfb(x, include_combos))
             .count() as u32
     }
 
@@ -214,21 +658,126 @@ impl KeylogStats {
         let sfb_events = self
             .sfb_series
             .iter()
-            .filter(move |x| {
-                if !include_combos {
-                    !x.has_combo()
-                } else {
-                    true
-                }
-            })
+            .filter(|x| self.counts_sfb(x, include_combos))
             .count();
-        sfb_events as f32 / self.total_events as f32 * 100.0
+        sfb_events as f32 / self.total_events.max(1) as f32 * 100.0
+    }
+
+    /// Like `sfb_perc` but split by the hand of the finger used for the sfb.
+    /// An sfb between two combos can count for both hands.
+    pub fn sfb_perc_by_hand(&self, include_combos: bool) -> BTreeMap<MatrixHalf, f32> {
+        let mut res = BTreeMap::new();
+        for sfb in &self.sfb_series {
+            if !self.counts_sfb(sfb, include_combos) {
+                continue;
+            }
+            for half in sfb.halves() {
+                *res.entry(half).or_insert(0.0) += 1.0;
+            }
+        }
+        for events in res.values_mut() {
+            *events = *events / self.total_events as f32 * 100.0;
+        }
+        res
+    }
+
+    /// Like `sfb_perc` but only counts sfbs that occur at least `min_sfb_count` times.
+    pub fn filtered_sfb_perc(&self, include_combos: bool) -> f32 {
+        let presses: u32 = self
+            .sfbs
+            .iter()
+            .filter(|x| self.counts_sfb(&x.sfb, include_combos))
+            .filter(|x| x.presses >= self.min_sfb_count)
+            .map(|x| x.presses)
+            .sum();
+        presses as f32 / self.total_events as f32 * 100.0
+    }
+
+    /// Like `sfb_perc` but each sfb is weighted by its severity.
+    pub fn sfb_weighted_perc(&self, include_combos: bool) -> f32 {
+        let weighted: f32 = self
+            .sfb_series
+            .iter()
+            .filter(|x| self.counts_sfb(x, include_combos))
+            .map(|x| x.severity())
+            .sum();
+        weighted / self.total_events as f32 * 100.0
+    }
+
+    /// Average travel distance of the sfbs, in key units.
+    pub fn avg_sfb_travel(&self, include_combos: bool) -> f32 {
+        let travel: Vec<f32> = self
+            .sfb_series
+            .iter()
+            .filter(|x| self.counts_sfb(x, include_combos))
+            .map(|x| x.travel_distance())
+            .collect();
+        if travel.is_empty() {
+            return 0.0;
+        }
+        travel.iter().sum::<f32>() / travel.len() as f32
+    }
+
+    pub fn top_sfbs_by_severity(&self, count: usize, include_combos: bool) -> Vec<&SfbStats> {
+        let mut res: Vec<_> = self
+            .sfbs
+            .iter()
+            .filter(|x| self.includes_sfb(&x.sfb, include_combos))
+            .collect();
+        // Most severe first, ties ordered by id for a stable output
+        res.sort_by(|a, b| {
+            b.weighted()
+                .total_cmp(&a.weighted())
+                .then_with(|| a.sfb.id().cmp(&b.sfb.id()))
+        });
+        res.into_iter().take(count).collect()
+    }
+
+    pub fn hand_alternation_perc(&self) -> f32 {
+        let bigrams = self.hand_alternations + self.same_hand_bigrams;
+        self.hand_alternations as f32 / bigrams.max(1) as f32 * 100.0
+    }
+
+    /// Number of same hand runs by length, where runs of `MAX_RUN_BUCKET` or more
+    /// are counted together.
+    pub fn same_hand_runs(&self) -> BTreeMap<usize, u32> {
+        let mut res = BTreeMap::new();
+        for (len, count) in &self.same_hand_run_lengths {
+            *res.entry((*len).min(MAX_RUN_BUCKET)).or_insert(0) += count;
+        }
+        res
+    }
+
+    /// Number of same finger runs by length, where runs of `MAX_RUN_BUCKET` or more
+    /// are counted together.
+    pub fn same_finger_runs(&self) -> BTreeMap<usize, u32> {
+        let mut res = BTreeMap::new();
+        for (len, count) in &self.same_finger_run_lengths {
+            *res.entry((*len).min(MAX_RUN_BUCKET)).or_insert(0) += count;
+        }
+        res
+    }
+
+    /// The most frequent same finger runs longer than an sfb, longer runs first on ties.
+    pub fn top_same_finger_sequences(&self, count: usize) -> Vec<(&Vec<String>, u32)> {
+        let mut res: Vec<_> = self
+            .same_finger_sequences
+            .iter()
+            .map(|(keys, freq)| (keys, *freq))
+            .collect();
+        res.sort_by(|(a_keys, a_freq), (b_keys, b_freq)| {
+            b_freq
+                .cmp(a_freq)
+                .then_with(|| b_keys.len().cmp(&a_keys.len()))
+                .then_with(|| a_keys.cmp(b_keys))
+        });
+        res.into_iter().take(count).collect()
     }
 
     pub fn top_sfbs_by_key(&self, count: usize, include_combos: bool) -> Vec<(KeyId, u32)> {
         let mut sfb_frequency_by_key: HashMap<KeyId, u32> = HashMap::new();
         for sfb in &self.sfb_series {
-            if !include_combos && sfb.has_combo() {
+            if !self.includes_sfb(sfb, include_combos) {
                 continue;
             }
             for key in sfb.all_keys() {
@@ -243,9 +792,86 @@ impl KeylogStats {
             .iter()
             .map(|(key, freq)| (key.clone(), *freq))
             .collect();
-        res.sort_by_key(|(_, freq)| *freq);
-        res.into_iter().rev().take(count).collect()
+        // Most frequent first, ties ordered by key for a stable output
+        res.sort_by(|(a_key, a_freq), (b_key, b_freq)| {
+            b_freq.cmp(a_freq).then_with(|| a_key.0.cmp(&b_key.0))
+        });
+        res.into_iter().take(count).collect()
+    }
+}
+
+pub const MAX_RUN_BUCKET: usize = 4;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HoldTime {
+    pub total_ms: u64,
+    pub holds: u32,
+}
+
+impl HoldTime {
+    pub fn avg_ms(&self) -> f32 {
+        self.total_ms as f32 / self.holds.max(1) as f32
+    }
+}
+
+// Pairs each press with the next release at the same matrix position. The key is
+// the one on the layer active at the press, as the layer may change while it's held.
+// Rows without a timestamp, combos and unknown keys are skipped.
+fn find_hold_times(
+    entries: &[RawKeylogEntry],
+    info: &InputInfo,
+    opts: &StatsOpts,
+) -> HashMap<String, HoldTime> {
+    let mut held: HashMap<(usize, usize), (&Key, u64)> = HashMap::new();
+    let mut res: HashMap<String, HoldTime> = HashMap::new();
+    for entry in entries {
+        let Some(timestamp) = entry.timestamp else {
+            continue;
+        };
+        if entry.keycode == "COMBO" {
+            continue;
+        }
+        let (Ok(row), Ok(col)) = (entry.row.parse(), entry.col.parse()) else {
+            continue;
+        };
+
+        if entry.pressed != 0 {
+            let key = info
+                .keymap
+                .find_key_by_matrix(entry.highest_layer, (row, col))
+                .filter(|key| !opts.exclude.contains(&key.id.0));
+            if let Some(key) = key {
+                held.insert((row, col), (key, timestamp));
+            }
+        } else if let Some((key, pressed_at)) = held.remove(&(row, col)) {
+            let x = res.entry(key.id.0.clone()).or_default();
+            x.total_ms += timestamp.saturating_sub(pressed_at);
+            x.holds += 1;
+        }
     }
+    res
+}
+
+// Runs of two or more consecutive single key presses on the same finger, as key ids.
+// Pressing the same key again or a combo ends a run.
+fn find_same_finger_runs(entries: &[KeylogEntry]) -> Vec<Vec<String>> {
+    let mut runs: Vec<Vec<&Key>> = Vec::new();
+    let mut run: Vec<&Key> = Vec::new();
+    for entry in entries {
+        match entry {
+            KeylogEntry::Single { key, .. } if run.last().is_none_or(|last| last.is_sfb(key)) => {
+                run.push(key)
+            }
+            KeylogEntry::Single { key, .. } => runs.push(std::mem::replace(&mut run, vec![key])),
+            KeylogEntry::Combo { .. } => runs.push(std::mem::take(&mut run)),
+        }
+    }
+    runs.push(run);
+
+    runs.into_iter()
+        .filter(|run| run.len() > 1)
+        .map(|run| run.iter().map(|key| key.id.0.clone()).collect())
+        .collect()
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -269,7 +895,15 @@ impl Sfb {
         }
 
         let res = match (current, next) {
-            (KeylogEntry::Combo(current_combo), KeylogEntry::Combo(next_combo)) => {
+            (
+                KeylogEntry::Combo {
+                    combo: current_combo,
+                    ..
+                },
+                KeylogEntry::Combo {
+                    combo: next_combo, ..
+                },
+            ) => {
                 let mut fingers = current_combo.get_fingers();
                 fingers.extend(next_combo.get_fingers().iter());
                 Self::Combo {
@@ -278,7 +912,7 @@ impl Sfb {
                     fingers,
                 }
             }
-            (KeylogEntry::Combo(combo), KeylogEntry::Single { key, .. }) => {
+            (KeylogEntry::Combo { combo, .. }, KeylogEntry::Single { key, .. }) => {
                 let mut fingers = combo.get_fingers();
                 fingers.insert(key.physical_pos.finger);
                 Self::Combo {
@@ -287,7 +921,7 @@ impl Sfb {
                     fingers,
                 }
             }
-            (KeylogEntry::Single { key, .. }, KeylogEntry::Combo(combo)) => {
+            (KeylogEntry::Single { key, .. }, KeylogEntry::Combo { combo, .. }) => {
                 let mut fingers = combo.get_fingers();
                 fingers.insert(key.physical_pos.finger);
                 Self::Combo {
@@ -315,6 +949,57 @@ impl Sfb {
         matches!(self, Self::Combo { .. })
     }
 
+    /// True if only thumbs are used twice in a row.
+    pub fn is_thumb_sfb(&self) -> bool {
+        match self {
+            Self::Combo {
+                first_keys,
+                second_keys,
+                ..
+            } => first_keys
+                .iter()
+                .filter(|a| second_keys.iter().any(|b| a.is_sfb(b)))
+                .all(|key| key.physical_pos.finger.finger == Finger::Thumb),
+            Self::Single { finger, .. } => finger.finger == Finger::Thumb,
+        }
+    }
+
+    /// The distance the finger travels between the keys, as a farther sfb is worse.
+    /// For combos it's the longest travel of any finger used twice.
+    pub fn severity(&self) -> f32 {
+        self.max_finger_travel(|a, b| a.physical_pos.distance(&b.physical_pos))
+    }
+
+    /// Like `severity` but measured with the render positions, which include stagger.
+    pub fn travel_distance(&self) -> f32 {
+        self.max_finger_travel(Key::travel_distance)
+    }
+
+    fn max_finger_travel(&self, distance: impl Fn(&Key, &Key) -> f32) -> f32 {
+        let (first, second): (Vec<&Key>, Vec<&Key>) = match self {
+            Sfb::Combo {
+                first_keys,
+                second_keys,
+                ..
+            } => (first_keys.iter().collect(), second_keys.iter().collect()),
+            Sfb::Single {
+                first_key,
+                second_key,
+                ..
+            } => (vec![first_key], vec![second_key]),
+        };
+
+        let mut res: f32 = 0.0;
+        for a in &first {
+            for b in &second {
+                if a.is_sfb(b) {
+                    res = res.max(distance(a, b));
+                }
+            }
+        }
+        res
+    }
+
     pub fn has_key(&self, key_id: &str) -> bool {
         self.all_keys().iter().any(|key| key.id.0 == key_id)
     }
@@ -368,59 +1053,321 @@ impl Sfb {
             Self::Single { finger, .. } => [*finger].into_iter().collect(),
         }
     }
+
+    /// The hands of the fingers that press a key on both sides of the sfb.
+    pub fn halves(&self) -> HashSet<MatrixHalf> {
+        match self {
+            Self::Combo {
+                first_keys,
+                second_keys,
+                ..
+            } => first_keys
+                .iter()
+                .filter(|a| second_keys.iter().any(|b| a.is_sfb(b)))
+                .map(|key| key.physical_pos.finger.half)
+                .collect(),
+            Self::Single { finger, .. } => [finger.half].into_iter().collect(),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum KeylogEntry<'a> {
-    Combo(&'a Combo),
-    Single {
-        key: &'a Key,
-        keycode: String,
-        highest_layer: LayerId,
-        pressed: bool,
-        // mods, oneshot_mods
-        tap_count: usize,
-    },
+#[derive(Debug, Clone)]
+pub struct ThumbUsage {
+    pub total: u32,
+    // Share of all key presses
+    pub perc: f32,
+    // Presses by key id, most frequent first
+    pub keys: Vec<(String, u32)>,
 }
 
-impl KeylogEntry<'_> {
-    pub fn is_key_sfb(&self, key: &Key) -> bool {
-        match self {
-            KeylogEntry::Combo(combo) => combo.is_key_sfb(key),
-            KeylogEntry::Single { key: other, .. } => key.is_sfb(other),
+#[derive(Debug, Clone)]
+pub struct ScissorStats {
+    pub total: u32,
+    // Share of all events
+    pub perc: f32,
+    // Most frequent first
+    pub bigrams: Vec<((String, String), u32)>,
+}
+
+/// A scissor is a bigram on adjacent fingers of the same hand, not counting the thumb,
+/// where the rows are at least two apart and the shorter finger reaches up while the
+/// longer finger reaches down. Middle is the longest finger, followed by ring, index and
+/// pinky, so the uncomfortable directions are:
+///
+/// - pinky top and ring bottom
+/// - ring top and middle bottom
+/// - index top and middle bottom
+///
+/// The order of the keys in the bigram doesn't matter.
+pub fn is_scissor(a: &PhysicalPos, b: &PhysicalPos) -> bool {
+    fn length(finger: Finger) -> u8 {
+        match finger {
+            Finger::Pinky => 0,
+            Finger::Index => 1,
+            Finger::Ring => 2,
+            Finger::Middle => 3,
+            Finger::Thumb => 4,
         }
     }
 
-    pub fn is_combo_sfb(&self, combo: &Combo) -> bool {
-        match self {
-            KeylogEntry::Combo(my_combo) => my_combo.is_combo_sfb(combo),
-            KeylogEntry::Single { key, .. } => combo.is_key_sfb(key),
-        }
+    if a.finger.half != b.finger.half
+        || a.finger.finger == Finger::Thumb
+        || b.finger.finger == Finger::Thumb
+        || (a.finger.finger as i32 - b.finger.finger as i32).abs() != 1
+    {
+        return false;
     }
 
-    pub fn is_entry_sfb(&self, other: &KeylogEntry) -> bool {
-        match self {
-            KeylogEntry::Combo(combo) => other.is_combo_sfb(combo),
-            KeylogEntry::Single { key, .. } => other.is_key_sfb(key),
+    let (short, long) = if length(a.finger.finger) < length(b.finger.finger) {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    long.row >= short.row + 2
+}
+
+// A word is by convention five characters, where we treat each event as one character.
+const CHARS_PER_WORD: f32 = 5.0;
+// Number of intervals in the rolling window used for peak wpm.
+const SPEED_WINDOW: usize = 50;
+// Bursts with fewer intervals don't count for peak wpm, as a few fast keys would inflate it.
+const MIN_PEAK_INTERVALS: usize = 10;
+// Pauses longer than this aren't counted as typing time.
+const IDLE_THRESHOLD_MS: u64 = 5000;
+
+// Sfbs sorted by presses and grouped by the fingers they use.
+#[allow(clippy::type_complexity)]
+fn group_sfbs(
+    sfbs_by_id: &HashMap<String, SfbStats>,
+) -> (
+    Vec<SfbStats>,
+    BTreeMap<FingerAssignment, HashMap<String, SfbStats>>,
+) {
+    let mut sfbs: Vec<SfbStats> = Vec::new();
+    let mut sfbs_by_finger: BTreeMap<FingerAssignment, HashMap<String, SfbStats>> = BTreeMap::new();
+    for (_id, sfb) in sfbs_by_id.iter() {
+        sfbs.push(sfb.clone());
+        for finger in sfb.sfb.get_fingers() {
+            sfbs_by_finger
+                .entry(finger)
+                .and_modify(|x| {
+                    x.entry(sfb.sfb.id())
+                        .and_modify(|x| x.presses += sfb.presses)
+                        .or_insert_with(|| SfbStats {
+                            presses: sfb.presses,
+                            sfb: sfb.sfb.clone(),
+                        });
+                })
+                .or_insert_with(|| {
+                    [(
+                        sfb.sfb.id(),
+                        SfbStats {
+                            presses: sfb.presses,
+                            sfb: sfb.sfb.clone(),
+                        },
+                    )]
+                    .into_iter()
+                    .collect()
+                });
         }
     }
+    sfbs.sort();
+
+    (sfbs, sfbs_by_finger)
 }
 
-fn convert_keylog_entries<'a>(
+#[derive(Debug, Clone)]
+pub struct SpeedStats {
+    pub avg_wpm: f32,
+    pub peak_wpm: f32,
+    pub avg_interval_ms: f32,
+}
+
+impl SpeedStats {
+    pub fn from_timestamps(timestamps: &[u64]) -> Option<Self> {
+        // Intervals between consecutive events, split into typing bursts by idle pauses.
+        let mut bursts = Vec::new();
+        let mut curr = Vec::new();
+        for (prev, next) in timestamps.iter().zip(timestamps.iter().skip(1)) {
+            let interval = next.saturating_sub(*prev);
+            if interval > IDLE_THRESHOLD_MS {
+                if !curr.is_empty() {
+                    bursts.push(std::mem::take(&mut curr));
+                }
+            } else {
+                curr.push(interval);
+            }
+        }
+        if !curr.is_empty() {
+            bursts.push(curr);
+        }
+
+        let count: usize = bursts.iter().map(Vec::len).sum();
+        let total_ms: u64 = bursts.iter().flatten().sum();
+        if count == 0 || total_ms == 0 {
+            return None;
+        }
+
+        let avg_interval_ms = total_ms as f32 / count as f32;
+
+        let mut peak_wpm: f32 = 0.0;
+        for burst in bursts.iter().filter(|x| x.len() >= MIN_PEAK_INTERVALS) {
+            let window = SPEED_WINDOW.min(burst.len());
+            for intervals in burst.windows(window) {
+                let ms: u64 = intervals.iter().sum();
+                peak_wpm = peak_wpm.max(interval_wpm(window, ms));
+            }
+        }
+
+        Some(Self {
+            avg_wpm: interval_wpm(count, total_ms),
+            peak_wpm,
+            avg_interval_ms,
+        })
+    }
+
+    /// The averages are weighted by the number of events in each log, as the
+    /// intervals themselves aren't kept.
+    fn merge(self, other: &SpeedStats, events: u32, other_events: u32) -> Self {
+        let total = (events + other_events).max(1) as f32;
+        let weigh = |a: f32, b: f32| (a * events as f32 + b * other_events as f32) / total;
+        Self {
+            avg_wpm: weigh(self.avg_wpm, other.avg_wpm),
+            peak_wpm: self.peak_wpm.max(other.peak_wpm),
+            avg_interval_ms: weigh(self.avg_interval_ms, other.avg_interval_ms),
+        }
+    }
+}
+
+fn interval_wpm(events: usize, ms: u64) -> f32 {
+    if ms == 0 {
+        return 0.0;
+    }
+    let minutes = ms as f32 / 60_000.0;
+    events as f32 / CHARS_PER_WORD / minutes
+}
+
+#[derive(Debug, Clone)]
+pub enum KeylogEntry<'a> {
+    Combo {
+        combo: &'a Combo,
+        timestamp: Option<u64>,
+    },
+    Single {
+        key: &'a Key,
+        keycode: String,
+        highest_layer: LayerId,
+        pressed: bool,
+        // mods, oneshot_mods
+        tap_count: usize,
+        timestamp: Option<u64>,
+    },
+}
+
+impl KeylogEntry<'_> {
+    pub fn timestamp(&self) -> Option<u64> {
+        match self {
+            KeylogEntry::Combo { timestamp, .. } => *timestamp,
+            KeylogEntry::Single { timestamp, .. } => *timestamp,
+        }
+    }
+
+    pub fn with_timestamp(mut self, ts: Option<u64>) -> Self {
+        match &mut self {
+            KeylogEntry::Combo { timestamp, .. } => *timestamp = ts,
+            KeylogEntry::Single { timestamp, .. } => *timestamp = ts,
+        }
+        self
+    }
+
+    // A combo occupies all hands of its keys.
+    pub fn halves(&self) -> HashSet<MatrixHalf> {
+        match self {
+            KeylogEntry::Combo { combo, .. } => combo
+                .keys
+                .iter()
+                .map(|key| key.physical_pos.finger.half)
+                .collect(),
+            KeylogEntry::Single { key, .. } => [key.physical_pos.finger.half].into_iter().collect(),
+        }
+    }
+
+    pub fn is_same_hand(&self, other: &KeylogEntry) -> bool {
+        !self.halves().is_disjoint(&other.halves())
+    }
+
+    pub fn is_key_sfb(&self, key: &Key) -> bool {
+        match self {
+            KeylogEntry::Combo { combo, .. } => combo.is_key_sfb(key),
+            KeylogEntry::Single { key: other, .. } => key.is_sfb(other),
+        }
+    }
+
+    pub fn is_combo_sfb(&self, combo: &Combo) -> bool {
+        match self {
+            KeylogEntry::Combo {
+                combo: my_combo, ..
+            } => my_combo.is_combo_sfb(combo),
+            KeylogEntry::Single { key, .. } => combo.is_key_sfb(key),
+        }
+    }
+
+    pub fn is_entry_sfb(&self, other: &KeylogEntry) -> bool {
+        match self {
+            KeylogEntry::Combo { combo, .. } => other.is_combo_sfb(combo),
+            KeylogEntry::Single { key, .. } => other.is_key_sfb(key),
+        }
+    }
+}
+
+// Keycodes of QMK's repeat key, which replays the previous keycode.
+const REPEAT_KEYS: [&str; 3] = ["REPEAT", "QK_REP", "QK_REPEAT_KEY"];
+
+fn is_repeat_key(key: &Key) -> bool {
+    REPEAT_KEYS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(&key.id.0))
+}
+
+fn convert_keylog_entries<'a>(
     entries: &[RawKeylogEntry],
     info: &'a InputInfo,
+    opts: &StatsOpts,
 ) -> Result<Vec<KeylogEntry<'a>>> {
     let mut res = Vec::with_capacity(entries.len());
+    // The last entry that wasn't a repeat, which is what the repeat key repeats.
+    let mut last_non_repeat: Option<KeylogEntry<'a>> = None;
+    let mut skipped = 0;
 
     for entry in entries {
         if entry.keycode == "COMBO" {
-            let combo = info
-                .keymap
-                .combos
-                .get(entry.tap_count)
-                .expect("Combo index out of bounds");
+            // An index is only correct if the combos are parsed in the order QMK
+            // generates them from combos.def, see `Keymap::combos`
+            let combo = match &entry.tap_count {
+                TapCount::Number(index) => info.keymap.combos.get(*index),
+                TapCount::Name(id) => info.keymap.find_combo_by_id(id),
+            };
+            let Some(combo) = combo else {
+                if opts.skip_unknown {
+                    skipped += 1;
+                    continue;
+                }
+                return Err(eyre!(
+                    "Could not find combo {} on line {}",
+                    entry.tap_count,
+                    entry.line
+                ));
+            };
+            if opts.exclude.contains(&combo.output) {
+                continue;
+            }
 
-            res.push(KeylogEntry::Combo(combo));
+            let combo = KeylogEntry::Combo {
+                combo,
+                timestamp: entry.timestamp,
+            };
+            last_non_repeat = Some(combo.clone());
+            res.push(combo);
             continue;
         }
         let pressed = entry.pressed != 0;
@@ -434,16 +1381,25 @@ fn convert_keylog_entries<'a>(
             continue;
         }
 
-        // TODO fetch from specific layer
-        let key = match info
+        let Some(key) = info
             .keymap
             .find_key_by_matrix(entry.highest_layer, (row, col))
-        {
-            Some(key) => key,
-            None => {
-                panic!("Could not find key for position {} {}", row, col);
+        else {
+            if opts.skip_unknown {
+                skipped += 1;
+                continue;
             }
+            return Err(eyre!(
+                "Could not find key for position {} {} on line {}",
+                row,
+                col,
+                entry.line
+            ));
         };
+        // Dropped before the bigrams are formed, so the keys around it become adjacent.
+        if opts.exclude.contains(&key.id.0) {
+            continue;
+        }
 
         let highest_layer = info
             .keymap
@@ -454,26 +1410,47 @@ fn convert_keylog_entries<'a>(
                 info.keymap.layers.len()
             ))?;
 
-        res.push(KeylogEntry::Single {
+        if opts.resolve_repeat && is_repeat_key(key) {
+            // Nothing to repeat, so the press does nothing.
+            if let Some(last) = &last_non_repeat {
+                res.push(last.clone().with_timestamp(entry.timestamp));
+            }
+            continue;
+        }
+
+        let TapCount::Number(tap_count) = entry.tap_count else {
+            return Err(eyre!(
+                "Expected a tap count but got {} on line {}",
+                entry.tap_count,
+                entry.line
+            ));
+        };
+
+        let single = KeylogEntry::Single {
             keycode: entry.keycode.clone(),
             key,
             highest_layer,
             pressed,
-            tap_count: entry.tap_count,
-        });
+            tap_count,
+            timestamp: entry.timestamp,
+        };
+        last_non_repeat = Some(single.clone());
+        res.push(single);
+    }
+
+    if skipped > 0 {
+        eprintln!("Warning: skipped {skipped} keylog rows with unknown keys or combos");
     }
 
     Ok(res)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::parse::*;
 
-    #[test]
-    fn test_sfb_stats() -> Result<()> {
-        let keymap_c = r#"
+    const KEYMAP_C: &str = r#"
 // clang-format off
 const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
     [_BASE] = LAYOUT(
@@ -491,8 +1468,9 @@ const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
                                  _______, _______,      _______
     )
 };
-        "#;
-        let keyboard_json = r#"
+"#;
+
+    const KEYBOARD_JSON: &str = r#"
 {
     "layouts": {
         "LAYOUT": {
@@ -540,9 +1518,9 @@ const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
         }
     }
 }
-        "#;
+"#;
 
-        let combos_def = r##"
+    const COMBOS_DEF: &str = r##"
 // Comment
 COMB(num,               NUMWORD,        MT_SPC, SE_E)
 
@@ -554,9 +1532,9 @@ SUBS(lt_eq,             "<=",           SE_F, SE_H)
 
 SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
 COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
-        "##;
+"##;
 
-        let render_input = r#"
+    const RENDER_INPUT: &str = r#"
 {
   "colors": {},
   "legend": [],
@@ -600,20 +1578,23 @@ COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
     ]
   }
 }
-        "#;
+"#;
 
-        let render_opts = RenderOpts::parse_from_str("id", render_input)?;
-        let keymap = Keymap::parse_from_source(keymap_c, keyboard_json, combos_def, &render_opts)?;
+    pub(crate) fn test_info() -> Result<InputInfo> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
 
-        let info = InputInfo {
+        Ok(InputInfo {
             keymap,
             render_opts,
-        };
+        })
+    }
 
+    pub(crate) fn test_keylog() -> String {
         // 2nd + 3rd for a regular keylog entry is the matrix position and the 5th needs to be 1
         // (pressed)
         // For a COMBO, the last entry is the combo index from combo.def
-        let keylog = [
+        [
             // MT_SPC
             "0x0001,3,4,0,1,0x00,0x00,1",
             // Both thumb keys, no sfb because it's the same
@@ -647,94 +1628,968 @@ COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
             // sfb :
             "COMBO,NA,NA,0,0,0,0,6",
         ]
-        .join("\n");
-        let entries = csv_parser::parse_from_str(&keylog)?;
-
-        let stats = KeylogStats::from_entries(&info, entries)?;
+        .join("\n")
+    }
 
-        assert_eq!(stats.sfb_series.len(), 8);
-        assert_eq!(stats.sfb_event_count(true), 8);
-        assert_eq!(stats.sfb_event_count(false), 5);
-        assert_eq!(stats.total_events, 17);
-        assert_eq!(stats.total_key_presses, 26);
+    #[test]
+    fn test_sfb_stats() -> Result<()> {
+        let keymap_c = r#"
+// clang-format off
+const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
+    [_BASE] = LAYOUT(
+      SE_J,    SE_C,    SE_Y,    SE_F,    SE_P,         SE_X,    SE_W,    SE_O,    SE_U,    SE_DOT,
+      SE_R,    SE_S,    SE_T,    SE_H,    SE_K,         SE_M,    SE_N,    SE_A,    SE_I,    REPEAT,
+      SE_COMM, SE_V,    SE_G,    SE_D,    SE_B,         SE_SLSH, SE_L,    SE_LPRN, SE_RPRN, SE_UNDS,
+               xxxxxxx, xxxxxxx,
+                                 FUN,     MT_SPC,       SE_E
+    ),
+    [_NUM]  = LAYOUT(
+      SE_J,    SE_PLUS, SE_ASTR, SE_EXLM, SE_P,         SE_X,    _______, AT_U,    REPEAT,  _______,
+      SE_6,    SE_4,    SE_0,    SE_2,    SE_K,         _______, SE_3,    SE_1,    SE_5,    SE_7,
+      SE_COMM, _______, NUM_G,   SE_8,    _______,      SE_SLSH, SE_9,    SE_LPRN, SE_RPRN, SE_UNDS,
+               _______, _______,
+                                 _______, _______,      _______
+    )
+};
+        "#;
+        let keyboard_json = r#"
+{
+    "layouts": {
+        "LAYOUT": {
+            "layout": [
+                { "matrix": [1, 0], "x": 0, "y": 0.93 },
+                { "matrix": [0, 1], "x": 1, "y": 0.31 },
+                { "matrix": [0, 2], "x": 2, "y": 0 },
+                { "matrix": [0, 3], "x": 3, "y": 0.28 },
+                { "matrix": [0, 4], "x": 4, "y": 0.42 },
+                { "matrix": [4, 0], "x": 7, "y": 0.42 },
+                { "matrix": [4, 1], "x": 8, "y": 0.28 },
+                { "matrix": [4, 2], "x": 9, "y": 0 },
+                { "matrix": [4, 3], "x": 10, "y": 0.31 },
+                { "matrix": [4, 4], "x": 11, "y": 0.93 },
 
-        assert_eq!(
-            stats.finger_frequency.get(&FingerAssignment {
-                finger: Finger::Pinky,
-                half: MatrixHalf::Left,
-            }),
-            None
-        );
-        assert_eq!(
-            stats.finger_frequency.get(&FingerAssignment {
-                finger: Finger::Ring,
-                half: MatrixHalf::Left,
-            }),
-            Some(&7)
-        );
-        assert_eq!(
-            stats.finger_frequency.get(&FingerAssignment {
-                finger: Finger::Index,
-                half: MatrixHalf::Right,
-            }),
-            Some(&5)
-        );
+                { "matrix": [2, 0], "x": 0, "y": 1.93 },
+                { "matrix": [1, 1], "x": 1, "y": 1.31 },
+                { "matrix": [1, 2], "x": 2, "y": 1 },
+                { "matrix": [1, 3], "x": 3, "y": 1.28 },
+                { "matrix": [1, 4], "x": 4, "y": 1.42 },
+                { "matrix": [5, 0], "x": 7, "y": 1.42 },
+                { "matrix": [5, 1], "x": 8, "y": 1.28 },
+                { "matrix": [5, 2], "x": 9, "y": 1 },
+                { "matrix": [5, 3], "x": 10, "y": 1.31 },
+                { "matrix": [5, 4], "x": 11, "y": 1.93 },
 
-        let sfb_frequency_by_finger = stats.sfb_frequency_by_finger(true);
+                { "matrix": [3, 0], "x": 0, "y": 2.93 },
+                { "matrix": [2, 1], "x": 1, "y": 2.31 },
+                { "matrix": [2, 2], "x": 2, "y": 2 },
+                { "matrix": [2, 3], "x": 3, "y": 2.28 },
+                { "matrix": [2, 4], "x": 4, "y": 2.42 },
+                { "matrix": [6, 0], "x": 7, "y": 2.42 },
+                { "matrix": [6, 1], "x": 8, "y": 2.28 },
+                { "matrix": [6, 2], "x": 9, "y": 2 },
+                { "matrix": [6, 3], "x": 10, "y": 2.31 },
+                { "matrix": [6, 4], "x": 11, "y": 2.93 },
 
-        assert_eq!(
-            sfb_frequency_by_finger.get(&FingerAssignment {
-                finger: Finger::Pinky,
-                half: MatrixHalf::Left,
-            }),
-            None
-        );
-        assert_eq!(
-            sfb_frequency_by_finger.get(&FingerAssignment {
-                finger: Finger::Ring,
-                half: MatrixHalf::Left,
-            }),
-            Some(&4)
-        );
-        assert_eq!(
-            sfb_frequency_by_finger.get(&FingerAssignment {
-                finger: Finger::Index,
-                half: MatrixHalf::Right,
-            }),
-            Some(&4)
-        );
+                { "matrix": [3, 1], "x": 1, "y": 3.31 },
+                { "matrix": [3, 2], "x": 2, "y": 3 },
 
-        Ok(())
+                { "matrix": [3, 3], "x": 3.5, "y": 3.75 },
+                { "matrix": [3, 4], "x": 4.5, "y": 4 },
+                { "matrix": [7, 0], "x": 6.5, "y": 4 }
+            ]
+        }
     }
+}
+        "#;
 
-    #[test]
-    fn test_is_sfb() {
-        let combo_a = Combo {
-            id: "comb_boot_r".into(),
-            output: "QK_BOOT".into(),
-            keys: vec![
-                Key {
-                    id: KeyId("SE_E".into()),
-                    x: 6.5,
-                    y: 4.0,
-                    physical_pos: PhysicalPos {
-                        col: 4,
-                        row: 4,
-                        finger: FingerAssignment {
-                            finger: Finger::Thumb,
-                            half: MatrixHalf::Right,
-                        },
-                        effort: 0,
-                    },
-                    matrix_pos: (7, 0),
-                },
-                Key {
-                    id: KeyId("SE_L".into()),
-                    x: 8.0,
-                    y: 2.28,
-                    physical_pos: PhysicalPos {
-                        col: 6,
-                        row: 2,
+        let combos_def = r##"
+// Comment
+COMB(num,               NUMWORD,        MT_SPC, SE_E)
+
+SUBS(https,             "https://",     MT_SPC, SE_SLSH)
+COMB(comb_boot_r,       QK_BOOT,        SE_E, SE_L, SE_LPRN, SE_RPRN, SE_UNDS)
+
+COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
+SUBS(lt_eq,             "<=",           SE_F, SE_H)
+
+SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
+COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
+        "##;
+
+        let render_input = r#"
+{
+  "colors": {},
+  "legend": [],
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": [
+    "54446    64445",
+    "21005    50012",
+    "64436    63446",
+    " 77",
+    "   80    0"
+  ],
+  "finger_assignments": [
+    "11233    33211",
+    "01233    33210",
+    "01233    33210",
+    " 12",
+    "   44    4"
+  ],
+  "layers": {
+    "default": [
+        {
+        "keys": ["_______", "xxxxxxx"],
+        "title": "",
+        "class": "blank"
+        },
+        {
+        "keys": ["SE_LPRN"],
+        "title": "("
+        }
+    ],
+    "_NUM": [
+        {
+        "keys": ["SE_J", "SE_P", "SE_K", "AT_U", "SE_LPRN", "SE_RPRN", "NUM_G"],
+        "class": "management"
+        }
+    ]
+  }
+}
+        "#;
+
+        let render_opts = RenderOpts::parse_from_str("id", render_input)?;
+        let keymap = Keymap::parse_from_source(keymap_c, keyboard_json, combos_def, &render_opts)?;
+
+        let info = InputInfo {
+            keymap,
+            render_opts,
+        };
+
+        // 2nd + 3rd for a regular keylog entry is the matrix position and the 5th needs to be 1
+        // (pressed)
+        // For a COMBO, the last entry is the combo index from combo.def
+        let keylog = [
+            // MT_SPC
+            "0x0001,3,4,0,1,0x00,0x00,1",
+            // Both thumb keys, no sfb because it's the same
+            "COMBO,NA,NA,0,0,0,0,0",
+            // SE_J
+            "0x0001,1,0,0,1,0x00,0x00,1",
+            // SE_C, sfb using ring
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_S, sfb with C
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            // SE_C, sfb with S
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_S, sfb with C
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            // SE_T
+            "0x0001,1,2,0,1,0x00,0x00,1",
+            // ESC SYM, no sfb as it uses same key
+            "COMBO,NA,NA,0,0,0,0,3",
+            // <=, no sfb as it uses same key (but maybe it should be...?)
+            "COMBO,NA,NA,0,0,0,0,4",
+            // SE_L
+            "0x0001,6,1,0,1,0x00,0x00,1",
+            // SE_W sfb
+            "0x0001,4,1,0,1,0x00,0x00,1",
+            // sfb :
+            "COMBO,NA,NA,0,0,0,0,6",
+            // sfb boot
+            "COMBO,NA,NA,0,0,0,0,2",
+            // sfb :
+            "COMBO,NA,NA,0,0,0,0,6",
+        ]
+        .join("\n");
+        let entries = csv_parser::parse_from_str(&keylog)?;
+
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        assert_eq!(stats.sfb_series.len(), 8);
+        assert_eq!(stats.sfb_event_count(true), 8);
+        assert_eq!(stats.sfb_event_count(false), 5);
+        assert_eq!(stats.total_events, 17);
+        assert_eq!(stats.total_key_presses, 26);
+
+        assert_eq!(
+            stats.finger_frequency.get(&FingerAssignment {
+                finger: Finger::Pinky,
+                half: MatrixHalf::Left,
+            }),
+            None
+        );
+        assert_eq!(
+            stats.finger_frequency.get(&FingerAssignment {
+                finger: Finger::Ring,
+                half: MatrixHalf::Left,
+            }),
+            Some(&7)
+        );
+        assert_eq!(
+            stats.finger_frequency.get(&FingerAssignment {
+                finger: Finger::Index,
+                half: MatrixHalf::Right,
+            }),
+            Some(&5)
+        );
+        let load = stats.finger_load();
+        assert_eq!(load.len(), 10);
+        assert_eq!(load[0].0.to_string(), "left-pinky");
+        assert_eq!(load[0].1, 0.0);
+        assert_eq!(load[1].0.to_string(), "left-ring");
+        assert_eq!(load[1].1, 7.0 / 26.0 * 100.0);
+
+        let sfb_frequency_by_finger = stats.sfb_frequency_by_finger(true);
+
+        assert_eq!(
+            sfb_frequency_by_finger.get(&FingerAssignment {
+                finger: Finger::Pinky,
+                half: MatrixHalf::Left,
+            }),
+            None
+        );
+        assert_eq!(
+            sfb_frequency_by_finger.get(&FingerAssignment {
+                finger: Finger::Ring,
+                half: MatrixHalf::Left,
+            }),
+            Some(&4)
+        );
+        assert_eq!(
+            sfb_frequency_by_finger.get(&FingerAssignment {
+                finger: Finger::Index,
+                half: MatrixHalf::Right,
+            }),
+            Some(&4)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_sfbs_by_key() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let key = |id: &str, freq: u32| (KeyId(id.into()), freq);
+
+        assert_eq!(
+            stats.top_sfbs_by_key(2, false),
+            vec![key("SE_C", 4), key("SE_S", 3)]
+        );
+        assert_eq!(
+            stats.top_sfbs_by_key(5, true),
+            vec![
+                key("SE_C", 4),
+                key("SE_A", 3),
+                key("SE_L", 3),
+                key("SE_N", 3),
+                key("SE_S", 3),
+            ]
+        );
+        assert_eq!(stats.top_sfbs_by_key(usize::MAX, false).len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sfb_perc_by_hand() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let perc = |events: f32| events / 17.0 * 100.0;
+        let by_hand = stats.sfb_perc_by_hand(false);
+        assert_eq!(by_hand[&MatrixHalf::Left], perc(4.0));
+        assert_eq!(by_hand[&MatrixHalf::Right], perc(1.0));
+
+        let by_hand = stats.sfb_perc_by_hand(true);
+        assert_eq!(by_hand[&MatrixHalf::Left], perc(4.0));
+        assert_eq!(by_hand[&MatrixHalf::Right], perc(4.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sfb_severity() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        // SE_L -> SE_W is two rows apart
+        let l_w = stats
+            .sfbs
+            .iter()
+            .find(|x| x.sfb.has_key("SE_L") && x.sfb.has_key("SE_W"))
+            .unwrap();
+        assert_eq!(l_w.sfb.severity(), 2.0);
+        assert_eq!(l_w.weighted(), 2.0);
+        // SE_L is at y 2.28 and SE_W at y 0.28
+        assert!((l_w.sfb.travel_distance() - 2.0).abs() < 0.001);
+
+        // The other sfbs are one row apart
+        let expected = (1.0 + 1.0 + 1.0 + 1.0 + 2.0) / 17.0 * 100.0;
+        assert!((stats.sfb_weighted_perc(false) - expected).abs() < 0.001);
+
+        let top: Vec<_> = stats
+            .top_sfbs_by_severity(2, false)
+            .into_iter()
+            .map(|x| x.weighted())
+            .collect();
+        assert_eq!(top, vec![2.0, 2.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finger_filter() -> Result<()> {
+        let info = test_info()?;
+        let left_ring: FingerAssignment = "left-ring".parse()?;
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&test_keylog())?,
+            &StatsOpts {
+                finger: Some(left_ring),
+                ..Default::default()
+            },
+        )?;
+
+        let by_finger = stats.sfb_frequency_by_finger(false);
+        assert_eq!(by_finger.keys().collect::<Vec<_>>(), vec![&left_ring]);
+        // J -> C, C -> S twice and S -> C
+        assert_eq!(by_finger[&left_ring], 4);
+
+        let top: Vec<_> = stats.top_sfbs(usize::MAX, true).collect();
+        assert!(!top.is_empty());
+        assert!(top.iter().all(|x| x.sfb.get_fingers().contains(&left_ring)));
+        assert!(!top.iter().any(|x| x.sfb.has_key("SE_L")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbs() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // FUN, MT_SPC: sfb on the left thumb
+            "0x0001,3,3,0,1,0x00,0x00,1",
+            "0x0001,3,4,0,1,0x00,0x00,1",
+            // SE_S, SE_C: sfb on the left ring
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_E
+            "0x0001,7,0,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let parse = |opts: StatsOpts| {
+            KeylogStats::from_entries(&info, csv_parser::parse_from_str(&keylog)?, &opts)
+        };
+        let left_thumb: FingerAssignment = "left-thumb".parse()?;
+
+        let stats = parse(StatsOpts::default())?;
+        assert_eq!(stats.sfb_event_count(false), 2);
+        assert_eq!(stats.sfb_frequency_by_finger(false)[&left_thumb], 1);
+        assert_eq!(stats.thumb_sfb_perc(false), 1.0 / 5.0 * 100.0);
+
+        let usage = stats.thumb_usage();
+        assert_eq!(usage.total, 3);
+        assert_eq!(usage.perc, 3.0 / 5.0 * 100.0);
+        assert_eq!(
+            usage.keys,
+            vec![
+                ("FUN".to_string(), 1),
+                ("MT_SPC".to_string(), 1),
+                ("SE_E".to_string(), 1)
+            ]
+        );
+
+        let stats = parse(StatsOpts {
+            exclude_thumbs: true,
+            ..Default::default()
+        })?;
+        assert_eq!(stats.sfb_event_count(false), 1);
+        assert_eq!(stats.sfb_perc(false), 1.0 / 5.0 * 100.0);
+        assert!(!stats
+            .sfb_frequency_by_finger(false)
+            .contains_key(&left_thumb));
+        assert!(stats
+            .top_sfbs(usize::MAX, false)
+            .all(|x| !x.sfb.is_thumb_sfb()));
+        // Excluded sfbs are still reported on their own
+        assert_eq!(stats.thumb_sfb_perc(false), 1.0 / 5.0 * 100.0);
+        assert_eq!(stats.thumb_usage().total, 3);
+        // Only the sfbs are excluded, the thumbs are still part of the finger load
+        assert!(stats.finger_load().iter().any(|(x, _)| *x == left_thumb));
+
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str("")?,
+            &StatsOpts {
+                exclude_thumbs: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stats.thumb_usage().perc, 0.0);
+        assert_eq!(stats.thumb_sfb_perc(false), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_sfb_count() -> Result<()> {
+        let info = test_info()?;
+        let left_ring: FingerAssignment = "left-ring".parse()?;
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&test_keylog())?,
+            &StatsOpts {
+                finger: Some(left_ring),
+                min_count: 2,
+                ..Default::default()
+            },
+        )?;
+
+        // Only C -> S occurs twice
+        assert_eq!(stats.sfb_frequency_by_finger(false)[&left_ring], 2);
+        let top: Vec<_> = stats.top_sfbs(usize::MAX, true).collect();
+        assert!(top.iter().all(|x| x.presses >= 2));
+        assert!(top
+            .iter()
+            .any(|x| x.sfb.has_key("SE_C") && x.sfb.has_key("SE_S")));
+        assert!(stats.filtered_sfb_perc(false) < stats.sfb_perc(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let info = test_info()?;
+        let opts = StatsOpts::default();
+        let single =
+            KeylogStats::from_entries(&info, csv_parser::parse_from_str(&test_keylog())?, &opts)?;
+        let mut stats =
+            KeylogStats::from_entries(&info, csv_parser::parse_from_str(&test_keylog())?, &opts)?;
+        stats.merge(&single);
+
+        assert_eq!(stats.total_events, 34);
+        assert_eq!(stats.total_key_presses, 52);
+        assert_eq!(stats.sfb_series.len(), 16);
+        assert_eq!(stats.sfbs.len(), single.sfbs.len());
+        assert_eq!(stats.sfb_perc(true), single.sfb_perc(true));
+        assert_eq!(
+            stats.sfb_frequency_by_finger(true)[&"left-ring".parse()?],
+            8
+        );
+
+        // SE_C at the end of one log and SE_S at the start of the next isn't an sfb
+        let mut stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str("0x0001,0,1,0,1,0x00,0x00,1")?,
+            &opts,
+        )?;
+        stats.merge(&KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str("0x0001,1,1,0,1,0x00,0x00,1")?,
+            &opts,
+        )?);
+        assert_eq!(stats.total_events, 2);
+        assert!(stats.sfb_series.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude() -> Result<()> {
+        let info = test_info()?;
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&test_keylog())?,
+            &StatsOpts {
+                exclude: ["SE_S".to_string()].into(),
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(stats.total_events, 13);
+        assert!(!stats.output_frequency.contains_key("SE_S"));
+        assert!(!stats.sfb_series.iter().any(|x| x.has_key("SE_S")));
+        // The C's around the excluded S's are now consecutive
+        assert_eq!(
+            stats.bigram_frequency[&("SE_C".to_string(), "SE_C".to_string())],
+            1
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frequency_csv() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let mut out = Vec::new();
+        stats.write_frequency_csv(&info, &mut out)?;
+        let csv = String::from_utf8(out)?;
+        let lines: Vec<_> = csv.lines().collect();
+
+        assert_eq!(lines[0], "key_id,frequency,finger,hand,effort");
+        assert_eq!(lines.len(), stats.output_frequency.len() + 1);
+        assert_eq!(lines[1], "SE_S,4,ring,left,1");
+        assert!(lines.contains(&"SE_C,2,ring,left,4"));
+        // Combo outputs aren't keys
+        assert!(lines.contains(&"ESC_SYM,1,,,"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scissors() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C, ring top
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_G, middle bottom
+            "0x0001,2,2,0,1,0x00,0x00,1",
+            // SE_Y, middle top
+            "0x0001,0,2,0,1,0x00,0x00,1",
+            // SE_V, ring bottom, the comfortable direction
+            "0x0001,2,1,0,1,0x00,0x00,1",
+            // SE_F, index top, but not adjacent to ring
+            "0x0001,0,3,0,1,0x00,0x00,1",
+            // SE_G, middle bottom
+            "0x0001,2,2,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+
+        let scissors = stats.scissor_stats();
+        assert_eq!(scissors.total, 2);
+        assert_eq!(scissors.perc, 2.0 / 6.0 * 100.0);
+        assert_eq!(
+            scissors.bigrams,
+            vec![
+                (("SE_C".to_string(), "SE_G".to_string()), 1),
+                (("SE_F".to_string(), "SE_G".to_string()), 1),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_col_frequency() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C, top row
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_S, home row
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            // SE_T, home row
+            "0x0001,1,2,0,1,0x00,0x00,1",
+            // SE_G, bottom row
+            "0x0001,2,2,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+
+        assert_eq!(
+            stats.row_frequency,
+            [(0, 1), (1, 2), (2, 1)].into_iter().collect()
+        );
+        assert_eq!(stats.col_frequency, [(1, 2), (2, 2)].into_iter().collect());
+        assert_eq!(stats.home_row, 1);
+        assert_eq!(stats.home_row_perc(), 50.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finger_transitions() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C, left ring
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_T, left middle
+            "0x0001,1,2,0,1,0x00,0x00,1",
+            // SE_C, left ring
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // SE_T, left middle
+            "0x0001,1,2,0,1,0x00,0x00,1",
+            // SE_N, right hand isn't a transition
+            "0x0001,5,1,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+
+        let finger = |finger| FingerAssignment {
+            finger,
+            half: MatrixHalf::Left,
+        };
+        assert_eq!(
+            stats.finger_transitions,
+            [
+                ((finger(Finger::Ring), finger(Finger::Middle)), 2),
+                ((finger(Finger::Middle), finger(Finger::Ring)), 1),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layer_usage() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            "0x0001,1,1,1,1,0x00,0x00,1",
+            // Combos don't log a layer and don't break up the layer sequence
+            "COMBO,NA,NA,0,0,0,0,3",
+            "0x0001,1,2,1,1,0x00,0x00,1",
+            "0x0001,1,2,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+
+        let base = &info.keymap.layers[0].id;
+        let other = &info.keymap.layers[1].id;
+        assert_eq!(stats.layer_usage()[base], 2);
+        assert_eq!(stats.layer_usage()[other], 2);
+        assert_eq!(stats.layer_switches, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_by_name() -> Result<()> {
+        let info = test_info()?;
+        let by_index = csv_parser::parse_from_str("COMBO,NA,NA,0,0,0,0,3")?;
+        let by_name = csv_parser::parse_from_str("COMBO,NA,NA,0,0,0,0,escape_sym")?;
+        let opts = StatsOpts::default();
+
+        let a = KeylogStats::from_entries(&info, by_index, &opts)?;
+        let b = KeylogStats::from_entries(&info, by_name, &opts)?;
+        assert_eq!(a.output_frequency.get("ESC_SYM"), Some(&1));
+        assert_eq!(a.output_frequency, b.output_frequency);
+
+        let unknown = csv_parser::parse_from_str("COMBO,NA,NA,0,0,0,0,no_such_combo")?;
+        assert!(KeylogStats::from_entries(&info, unknown, &opts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_index_order() -> Result<()> {
+        let combos_def = r#"
+// Comment
+
+COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
+// COMB(disabled,       KC_NO,          SE_T, SE_S)
+
+
+SUBS(lt_eq,             "<=",           SE_F, SE_H) // Trailing comment
+  // Indented comment
+COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
+"#;
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let info = InputInfo {
+            keymap: Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?,
+            render_opts,
+        };
+
+        // A single skipped or extra index would shift every combo after it
+        let opts = StatsOpts::default();
+        for (index, id) in ["escape_sym", "lt_eq", "coln_sym"].into_iter().enumerate() {
+            let by_index = csv_parser::parse_from_str(&format!("COMBO,NA,NA,0,0,0,0,{index}"))?;
+            let by_name = csv_parser::parse_from_str(&format!("COMBO,NA,NA,0,0,0,0,{id}"))?;
+            let a = KeylogStats::from_entries(&info, by_index, &opts)?;
+            let b = KeylogStats::from_entries(&info, by_name, &opts)?;
+            assert_eq!(a.output_frequency, b.output_frequency, "combo {index}");
+        }
+        let past_end = csv_parser::parse_from_str("COMBO,NA,NA,0,0,0,0,3")?;
+        assert!(KeylogStats::from_entries(&info, past_end, &opts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_unknown() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // Not a key
+            "0x0001,9,9,0,1,0x00,0x00,1",
+            // Not a combo
+            "COMBO,NA,NA,0,0,0,0,99",
+        ]
+        .join("\n");
+
+        let err = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not find key for position 9 9 on line 2"
+        );
+
+        let stats = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts {
+                skip_unknown: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stats.total_events, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_hand_runs() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_J, SE_C, SE_Y on the left
+            "0x0001,1,0,0,1,0x00,0x00,1",
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            "0x0001,0,2,0,1,0x00,0x00,1",
+            // SE_X, SE_W on the right
+            "0x0001,4,0,0,1,0x00,0x00,1",
+            "0x0001,4,1,0,1,0x00,0x00,1",
+            // SE_J
+            "0x0001,1,0,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let entries = csv_parser::parse_from_str(&keylog)?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        assert_eq!(stats.hand_alternations, 2);
+        assert_eq!(stats.same_hand_bigrams, 3);
+        assert_eq!(stats.hand_alternation_perc(), 40.0);
+        assert_eq!(
+            stats.same_hand_runs(),
+            [(2, 1), (3, 1)].into_iter().collect()
+        );
+
+        let entries = csv_parser::parse_from_str("0x0001,1,0,0,1,0x00,0x00,1")?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+        assert_eq!(stats.hand_alternation_perc(), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_position_frequency() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // MT_SPC
+            "0x0001,3,4,0,1,0x00,0x00,1",
+            // The num combo with MT_SPC and SE_E
+            "COMBO,NA,NA,0,0,0,0,0",
+            // SE_PLUS on the num layer
+            "0x0001,0,1,1,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let entries = csv_parser::parse_from_str(&keylog)?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let pos = |id| {
+            info.keymap.layers[0]
+                .find_key_by_id(id)
+                .unwrap()
+                .physical_pos
+                .pos()
+        };
+        let base = stats.layer_position_frequency(&info.keymap.layers[0].id);
+        assert_eq!(base.get(&pos("MT_SPC")), Some(&2));
+        assert_eq!(base.get(&pos("SE_E")), Some(&1));
+        assert_eq!(base.get(&pos("SE_T")), None);
+        assert_eq!(base.get(&pos("SE_C")), None);
+        let num = stats.layer_position_frequency(&info.keymap.layers[1].id);
+        assert_eq!(num, [(pos("SE_C"), 1)].into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_finger_runs() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C, SE_S, SE_V on the left ring finger
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            "0x0001,2,1,0,1,0x00,0x00,1",
+            // SE_T
+            "0x0001,1,2,0,1,0x00,0x00,1",
+            // SE_C, SE_S
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            "0x0001,1,1,0,1,0x00,0x00,1",
+            // The num combo interrupts the run
+            "COMBO,NA,NA,0,0,0,0,0",
+            // SE_V
+            "0x0001,2,1,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+        let entries = csv_parser::parse_from_str(&keylog)?;
+        let mut stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        assert_eq!(
+            stats.same_finger_runs(),
+            [(2, 1), (3, 1)].into_iter().collect()
+        );
+        let seq = vec!["SE_C".to_string(), "SE_S".into(), "SE_V".into()];
+        assert_eq!(stats.top_same_finger_sequences(10), vec![(&seq, 1)]);
+
+        let other = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+        stats.merge(&other);
+        assert_eq!(
+            stats.same_finger_runs(),
+            [(2, 2), (3, 2)].into_iter().collect()
+        );
+        assert_eq!(stats.top_same_finger_sequences(10), vec![(&seq, 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_repeat() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_C
+            "0x0001,0,1,0,1,0x00,0x00,1",
+            // REPEAT
+            "0x0001,5,4,0,1,0x00,0x00,1",
+            // SE_S, sfb with the repeated C
+            "0x0001,1,1,0,1,0x00,0x00,1",
+        ]
+        .join("\n");
+
+        let raw = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts::default(),
+        )?;
+        assert_eq!(raw.output_frequency.get("REPEAT"), Some(&1));
+        assert_eq!(raw.output_frequency.get("SE_C"), Some(&1));
+        assert_eq!(raw.sfb_series.len(), 0);
+
+        let resolved = KeylogStats::from_entries(
+            &info,
+            csv_parser::parse_from_str(&keylog)?,
+            &StatsOpts {
+                resolve_repeat: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(resolved.output_frequency.get("REPEAT"), None);
+        assert_eq!(resolved.output_frequency.get("SE_C"), Some(&2));
+        assert_eq!(resolved.sfb_series.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_bigrams() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        let bigram = |a: &str, b: &str| (a.to_string(), b.to_string());
+
+        assert_eq!(
+            stats.top_bigrams(3),
+            vec![
+                (&bigram("SE_C", "SE_S"), 2),
+                (&bigram("SE_S", "SE_S"), 2),
+                (&bigram("SE_J", "SE_C"), 1),
+            ]
+        );
+        // Combos break up bigrams
+        assert_eq!(stats.bigram_frequency.get(&bigram("SE_T", "SE_L")), None);
+        assert_eq!(stats.bigram_frequency.values().sum::<u32>(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keystrokes_saved() -> Result<()> {
+        let info = test_info()?;
+        let entries = csv_parser::parse_from_str(&test_keylog())?;
+        let stats = KeylogStats::from_entries(&info, entries, &StatsOpts::default())?;
+
+        // Only `<=` outputs more than a single keystroke
+        assert_eq!(
+            stats.top_keystrokes_saved(10),
+            vec![(&"lt_eq".to_string(), 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_sfb() {
+        let combo_a = Combo {
+            id: "comb_boot_r".into(),
+            output: "QK_BOOT".into(),
+            ordered: false,
+            kind: ComboKind::Comb,
+            keys: vec![
+                Key {
+                    id: KeyId("SE_E".into()),
+                    x: 6.5,
+                    y: 4.0,
+                    physical_pos: PhysicalPos {
+                        col: 4,
+                        row: 4,
+                        finger: FingerAssignment {
+                            finger: Finger::Thumb,
+                            half: MatrixHalf::Right,
+                        },
+                        effort: 0,
+                    },
+                    matrix_pos: (7, 0),
+                },
+                Key {
+                    id: KeyId("SE_L".into()),
+                    x: 8.0,
+                    y: 2.28,
+                    physical_pos: PhysicalPos {
+                        col: 6,
+                        row: 2,
                         finger: FingerAssignment {
                             finger: Finger::Index,
                             half: MatrixHalf::Right,
@@ -790,11 +2645,16 @@ COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
                 },
             ],
         };
-        let a = KeylogEntry::Combo(&combo_a);
+        let a = KeylogEntry::Combo {
+            combo: &combo_a,
+            timestamp: None,
+        };
 
         let combo_b = Combo {
             id: "combo_coln".into(),
             output: "SE_COLN".into(),
+            ordered: false,
+            kind: ComboKind::Comb,
             keys: vec![
                 Key {
                     id: KeyId("SE_R".into()),
@@ -828,8 +2688,161 @@ COMB(coln_sym,          COLN_SYM,       SE_N, SE_A)
                 },
             ],
         };
-        let b = KeylogEntry::Combo(&combo_b);
+        let b = KeylogEntry::Combo {
+            combo: &combo_b,
+            timestamp: None,
+        };
 
         assert!(a.is_entry_sfb(&b));
     }
+
+    #[test]
+    fn test_is_entry_sfb() -> Result<()> {
+        let info = test_info()?;
+        let base = &info.keymap.layers[0];
+        let num = &info.keymap.layers[1];
+
+        fn single<'a>(key: &str, layer: &'a Layer) -> KeylogEntry<'a> {
+            KeylogEntry::Single {
+                key: layer.find_key_by_id(key).unwrap(),
+                keycode: "0x0001".into(),
+                highest_layer: layer.id.clone(),
+                pressed: true,
+                tap_count: 1,
+                timestamp: None,
+            }
+        }
+        fn combo(combo: &Combo) -> KeylogEntry<'_> {
+            KeylogEntry::Combo {
+                combo,
+                timestamp: None,
+            }
+        }
+
+        // SE_T (left middle) + SE_H (left index)
+        let escape_sym = &info.keymap.combos[3];
+        // SE_F (left index) + SE_H (left index)
+        let lt_eq = &info.keymap.combos[4];
+        // SE_S (left ring) + SE_K (left index)
+        let s_k = Combo::new(
+            "s_k".into(),
+            "KC_NO".into(),
+            vec![
+                base.find_key_by_id("SE_S").unwrap().clone(),
+                base.find_key_by_id("SE_K").unwrap().clone(),
+            ],
+        );
+
+        // Combo -> single
+        assert!(combo(escape_sym).is_entry_sfb(&single("SE_D", base)));
+        assert!(!combo(escape_sym).is_entry_sfb(&single("SE_H", base)));
+        assert!(!combo(escape_sym).is_entry_sfb(&single("SE_S", base)));
+        // The same position on another layer is the same key
+        assert!(!combo(escape_sym).is_entry_sfb(&single("SE_2", num)));
+
+        // Single -> combo
+        assert!(single("SE_D", base).is_entry_sfb(&combo(escape_sym)));
+        assert!(!single("SE_H", base).is_entry_sfb(&combo(escape_sym)));
+        assert!(!single("SE_2", num).is_entry_sfb(&combo(escape_sym)));
+
+        // Combo -> combo
+        assert!(combo(escape_sym).is_entry_sfb(&combo(&s_k)));
+        assert!(combo(&s_k).is_entry_sfb(&combo(escape_sym)));
+        // Sharing a position
+        assert!(!combo(escape_sym).is_entry_sfb(&combo(lt_eq)));
+        assert!(!combo(lt_eq).is_entry_sfb(&combo(escape_sym)));
+
+        // Single -> single
+        assert!(single("SE_H", base).is_entry_sfb(&single("SE_D", base)));
+        assert!(!single("SE_H", base).is_entry_sfb(&single("SE_2", num)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hold_times() -> Result<()> {
+        let info = test_info()?;
+        let keylog = [
+            // SE_J held for 80ms
+            "0x0001,1,0,0,1,0x00,0x00,1,1000",
+            "0x0001,1,0,0,0,0x00,0x00,1,1080",
+            // A roll, SE_C is released after SE_S is pressed
+            "0x0001,0,1,0,1,0x00,0x00,1,1100",
+            "0x0001,1,1,0,1,0x00,0x00,1,1120",
+            "0x0001,0,1,0,0,0x00,0x00,1,1160",
+            "0x0001,1,1,0,0,0x00,0x00,1,1180",
+            // SE_J again for 120ms
+            "0x0001,1,0,0,1,0x00,0x00,1,1200",
+            "0x0001,1,0,0,0,0x00,0x00,1,1320",
+            // A release without a press is ignored
+            "0x0001,1,2,0,0,0x00,0x00,1,1400",
+        ]
+        .join("\n");
+        let parse = |opts: StatsOpts| {
+            KeylogStats::from_entries(&info, csv_parser::parse_from_str(&keylog)?, &opts)
+        };
+
+        let stats = parse(StatsOpts::default())?;
+        assert!(stats.hold_times.is_empty());
+        assert_eq!(stats.total_events, 4);
+
+        let stats = parse(StatsOpts {
+            hold_durations: true,
+            ..Default::default()
+        })?;
+        // Only presses are counted as events
+        assert_eq!(stats.total_events, 4);
+        assert_eq!(
+            stats.hold_times["SE_J"],
+            HoldTime {
+                total_ms: 200,
+                holds: 2
+            }
+        );
+        assert_eq!(
+            stats.avg_hold_ms(),
+            vec![
+                (&"SE_J".to_string(), 100.0),
+                (&"SE_C".to_string(), 60.0),
+                (&"SE_S".to_string(), 60.0),
+            ]
+        );
+        assert_eq!(HoldTime::default().avg_ms(), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_speed_stats() -> Result<()> {
+        let entries = csv_parser::parse_from_str(
+            "0x0001,1,0,0,1,0x00,0x00,1,1000\n0x0001,1,1,0,1,0x00,0x00,1,1100",
+        )?;
+        assert_eq!(entries[1].timestamp, Some(1100));
+        let entries = csv_parser::parse_from_str("0x0001,1,0,0,1,0x00,0x00,1")?;
+        assert_eq!(entries[0].timestamp, None);
+
+        assert!(SpeedStats::from_timestamps(&[]).is_none());
+        assert!(SpeedStats::from_timestamps(&[0, 10_000]).is_none());
+
+        // 100ms between keys is 120 wpm, the idle pause is ignored
+        let speed = SpeedStats::from_timestamps(&[0, 100, 200, 300, 20_000, 20_050]).unwrap();
+        assert_eq!(speed.avg_interval_ms, 87.5);
+        assert_eq!(speed.avg_wpm, 4.0 / 5.0 / (350.0 / 60_000.0));
+        // Too short to count as a peak
+        assert_eq!(speed.peak_wpm, 0.0);
+
+        // A short fast burst doesn't beat a long steady one
+        let mut timestamps: Vec<u64> = (0..=10).map(|i| i * 100).collect();
+        timestamps.extend([20_000, 20_020, 20_040]);
+        let speed = SpeedStats::from_timestamps(&timestamps).unwrap();
+        assert!((speed.peak_wpm - 120.0).abs() < 0.01);
+
+        // Logs without events don't make the merged averages NaN
+        let merged = speed.clone().merge(&speed, 0, 0);
+        assert_eq!(merged.avg_wpm, 0.0);
+        assert_eq!(merged.peak_wpm, speed.peak_wpm);
+        assert_eq!(interval_wpm(3, 0), 0.0);
+
+        Ok(())
+    }
 }
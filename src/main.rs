@@ -1,3 +1,6 @@
+#[macro_use]
+mod status;
+mod check;
 mod keylog;
 mod parse;
 mod render;
@@ -5,8 +8,10 @@ mod render;
 use camino::Utf8PathBuf;
 use clap::{Parser, Subcommand};
 use eyre::Result;
+use parse::FingerAssignment;
 use parse::InputInfo;
 use parse::Keymap;
+use parse::ParseSettings;
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -14,6 +19,10 @@ struct Args {
     #[command(flatten)]
     keymap: KeymapArgs,
 
+    /// Don't print progress, such as the paths of written files. Warnings are still printed.
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -29,34 +38,281 @@ struct KeymapArgs {
     #[arg(long, default_value = "default")]
     keymap: String,
 
-    #[arg(long, value_name = "RENDER_OPTS.json")]
-    render_opts: String,
+    /// Can be given multiple times, later files override earlier ones.
+    /// The files may contain `//` and `/* */` comments.
+    #[arg(long, value_name = "RENDER_OPTS.json", required = true)]
+    render_opts: Vec<Utf8PathBuf>,
+
+    /// Fail on keymap warnings, such as a key used twice in a layer.
+    #[arg(long)]
+    strict: bool,
+
+    /// Use this keymap.c instead of the one in the QMK keymap directory.
+    /// A `.json` file is read as a keymap exported from QMK Configurator.
+    #[arg(long, value_name = "KEYMAP.C")]
+    keymap_c: Option<Utf8PathBuf>,
+
+    /// Use this combos.def instead of the one in the QMK keymap directory.
+    #[arg(long, value_name = "COMBOS.DEF")]
+    combos_def: Option<Utf8PathBuf>,
+
+    /// Use this keyboard.json instead of the one in the QMK keyboard directory.
+    #[arg(long, value_name = "KEYBOARD.JSON")]
+    keyboard_json: Option<Utf8PathBuf>,
+}
+
+impl From<KeymapArgs> for ParseSettings {
+    fn from(args: KeymapArgs) -> Self {
+        Self {
+            qmk_root: args.qmk_root.into(),
+            keyboard: args.keyboard,
+            keymap: args.keymap,
+            strict: args.strict,
+            keymap_c_path: args.keymap_c,
+            combos_def_path: args.combos_def,
+            keyboard_json_path: args.keyboard_json,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// Count presses of the repeat key as the key it repeats.
+    #[arg(long)]
+    resolve_repeat: bool,
+
+    /// List the sfbs weighted by the distance between the keys.
+    #[arg(long)]
+    sfb_severity: bool,
+
+    /// Only show sfbs and frequencies for a finger, such as `left-pinky`.
+    #[arg(long)]
+    finger: Option<FingerAssignment>,
+
+    /// Skip keylog rows with unknown keys or combos instead of failing.
+    #[arg(long)]
+    skip_unknown: bool,
+
+    /// Only list sfbs that occur at least this many times.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_count: u32,
+
+    /// The keylog columns as a comma separated list of fields, defaults to
+    /// `keycode,row,col,highest_layer,pressed,mods,oneshot_mods,tap_count,timestamp`.
+    #[arg(long, value_name = "FIELDS")]
+    csv_format: Option<keylog::CsvFormat>,
+
+    /// Drop these comma separated keys or combo outputs from the log, such as `KC_NO,KC_MS_U`.
+    #[arg(
+        long,
+        visible_alias = "exclude-keys",
+        value_name = "KEYS",
+        value_delimiter = ','
+    )]
+    exclude: Vec<String>,
+
+    /// Leave out sfbs on the thumbs, which can press several keys in a row comfortably.
+    #[arg(long)]
+    exclude_thumbs: bool,
+
+    /// Pair key presses with their releases to report how long keys are held.
+    /// Needs a keylog with release rows and timestamps.
+    #[arg(long)]
+    hold_durations: bool,
+}
+
+impl From<StatsArgs> for keylog::StatsOpts {
+    fn from(args: StatsArgs) -> Self {
+        Self {
+            resolve_repeat: args.resolve_repeat,
+            sfb_severity: args.sfb_severity,
+            finger: args.finger,
+            skip_unknown: args.skip_unknown,
+            min_count: args.min_count,
+            csv_format: args.csv_format.unwrap_or_default(),
+            exclude: args.exclude.into_iter().collect(),
+            exclude_thumbs: args.exclude_thumbs,
+            hold_durations: args.hold_durations,
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
     Render {
+        #[arg(long, required_unless_present = "stdout")]
+        output: Option<String>,
+
+        /// Print the image of a single layer, such as `_BASE`, instead of writing files.
+        #[arg(long, requires = "layer")]
+        stdout: bool,
+
+        /// The layer to print with `--stdout`.
+        #[arg(long, requires = "stdout")]
+        layer: Option<String>,
+
+        /// Also render `matrix_debug.svg`, with the matrix and physical position of each key.
         #[arg(long)]
-        output: String,
+        debug_matrix: bool,
     },
     Stats {
+        /// Can be given multiple times to combine the stats of several logs.
+        #[arg(long, value_name = "KEYLOG.CSV", required = true)]
+        log: Vec<Utf8PathBuf>,
+
+        /// Length of the top lists, 0 prints all.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// Also write the frequency of each key to a csv file.
+        #[arg(long, value_name = "OUT.CSV")]
+        freq_csv: Option<String>,
+
+        #[command(flatten)]
+        stats_opts: StatsArgs,
+    },
+    /// Compare the stats of two keylogs.
+    Compare {
+        #[arg(long, value_name = "KEYLOG_A.CSV")]
+        log_a: String,
+
+        #[arg(long, value_name = "KEYLOG_B.CSV")]
+        log_b: String,
+
+        /// Length of the top lists, 0 prints all.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        #[command(flatten)]
+        stats_opts: StatsArgs,
+    },
+    /// Summarize a keylog as a single weighted layout score, lower is better.
+    Score {
         #[arg(long, value_name = "KEYLOG.CSV")]
         log: String,
+
+        #[command(flatten)]
+        stats_opts: StatsArgs,
+    },
+    /// Summarize the combos of the keymap, such as the number of keys and fingers they use.
+    Combos,
+    /// Report problems with the keymap and render opts without writing any files.
+    Check,
+    /// Render a layer with the number of times each key was pressed in a keylog.
+    Usage {
+        /// Can be given multiple times to combine several logs.
+        #[arg(long, value_name = "KEYLOG.CSV", required = true)]
+        log: Vec<Utf8PathBuf>,
+
+        #[arg(long, value_name = "OUT.SVG")]
+        output: Utf8PathBuf,
+
+        /// The layer to annotate, defaults to the base layer.
+        #[arg(long)]
+        layer: Option<String>,
+
+        /// Show the share of the presses on this layer instead of the number of presses.
+        #[arg(long)]
+        percent: bool,
+
+        /// Color the keys by how often they're pressed.
+        #[arg(long)]
+        heatmap: bool,
+
+        #[command(flatten)]
+        stats_opts: StatsArgs,
     },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    status::set_quiet(args.quiet);
 
-    let info = InputInfo::parse(
-        args.keymap.qmk_root.into(),
-        args.keymap.keyboard,
-        args.keymap.keymap,
-        args.keymap.render_opts.into(),
-    )?;
+    let render_opts = args.keymap.render_opts.clone();
+    let parsed = InputInfo::parse(&args.keymap.into(), &render_opts);
+    if let Command::Check = args.command {
+        return check::output_diagnostics(&check::check(parsed));
+    }
+    let (info, warnings) = parsed?;
+    for msg in warnings {
+        eprintln!("Warning: {msg}");
+    }
 
     match args.command {
-        Command::Render { output } => render::render(&info, &Utf8PathBuf::from(output)),
-        Command::Stats { log } => keylog::output_stats(&info, &Utf8PathBuf::from(log)),
+        Command::Render {
+            output,
+            stdout,
+            layer,
+            debug_matrix,
+        } => match (stdout, layer, output) {
+            (true, Some(layer), _) => {
+                render::write_layer_by_id(&info, &layer, &mut std::io::stdout().lock())
+            }
+            (_, _, Some(output)) => render::render(&info, &Utf8PathBuf::from(output), debug_matrix),
+            _ => unreachable!("clap requires --output or --stdout with --layer"),
+        },
+        Command::Stats {
+            log,
+            top,
+            freq_csv,
+            stats_opts,
+        } => keylog::output_stats(
+            &info,
+            &log,
+            &stats_opts.into(),
+            top,
+            freq_csv.map(Utf8PathBuf::from).as_deref(),
+        ),
+        Command::Compare {
+            log_a,
+            log_b,
+            top,
+            stats_opts,
+        } => keylog::output_compare(
+            &info,
+            &Utf8PathBuf::from(log_a),
+            &Utf8PathBuf::from(log_b),
+            &stats_opts.into(),
+            top,
+        ),
+        Command::Score { log, stats_opts } => {
+            keylog::output_score(&info, &Utf8PathBuf::from(log), &stats_opts.into())
+        }
+        Command::Combos => {
+            output_combo_summary(&info.keymap);
+            Ok(())
+        }
+        Command::Usage {
+            log,
+            output,
+            layer,
+            percent,
+            heatmap,
+            stats_opts,
+        } => {
+            let stats = keylog::KeylogStats::from_files(&info, &log, &stats_opts.into())?;
+            let opts = render::UsageOpts { percent, heatmap };
+            render::render_usage(&info, &stats, layer.as_deref(), &opts, &output)
+        }
+        Command::Check => unreachable!("check is handled before the keymap is required"),
+    }
+}
+
+fn output_combo_summary(keymap: &Keymap) {
+    let summary = keymap.combo_summary();
+
+    println!("  combos: {}", summary.total);
+    for (count, combos) in &summary.by_key_count {
+        println!("  {count:>6} keys: {combos}");
+    }
+
+    println!();
+    println!("  same hand: {}", summary.same_hand);
+    println!("  cross hand: {}", summary.cross_hand);
+
+    println!();
+    println!("  fingers required:");
+    for (finger, combos) in summary.top_fingers() {
+        println!("   {:>13}: {combos}", finger.to_string());
     }
 }
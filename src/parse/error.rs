@@ -0,0 +1,113 @@
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use super::keymap::{KeyId, LayerId};
+use super::render_opts::SplitMode;
+
+/// Errors from parsing the keymap and the render opts. They're returned
+/// wrapped in an `eyre::Report`, use `downcast_ref::<ParseError>` to match on them.
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("Couldn't find keyboard.json or info.json at {keyboard_json} nor {info_json}")]
+    KeyboardJsonNotFound {
+        keyboard_json: Utf8PathBuf,
+        info_json: Utf8PathBuf,
+    },
+    #[error("Couldn't find any layers in keymap")]
+    NoLayers,
+    #[error("Base layer {layer} has {keys} keys but the physical_layout and finger_assignments in the render opts specify {physical_keys} keys")]
+    BaseLayerKeyCount {
+        layer: LayerId,
+        keys: usize,
+        physical_keys: usize,
+    },
+    #[error("Failed to find layout spec {layout} for layer {layer}, available layouts are: {}", .available.join(", "))]
+    LayoutNotFound {
+        layout: String,
+        layer: LayerId,
+        available: Vec<String>,
+    },
+    #[error("Layer {layer} has {keys} keys but its layout {layout} has {layout_keys} keys")]
+    LayerKeyCount {
+        layer: LayerId,
+        keys: usize,
+        layout: String,
+        layout_keys: usize,
+    },
+    #[error("Key {key} at matrix position {matrix:?} in layer {layer} isn't in the base layer {base_layer}")]
+    KeyNotInBaseLayer {
+        key: KeyId,
+        matrix: (usize, usize),
+        layer: LayerId,
+        base_layer: LayerId,
+    },
+    #[error(
+        "Matrix position {matrix:?} is used by both key {first} and {second} in layout {layout}"
+    )]
+    DuplicateMatrixPos {
+        matrix: (usize, usize),
+        first: usize,
+        second: usize,
+        layout: String,
+    },
+    #[error("Key {key} is used at multiple matrix positions {positions:?} in layer {layer}")]
+    DuplicateKey {
+        key: KeyId,
+        positions: Vec<(usize, usize)>,
+        layer: LayerId,
+    },
+    #[error("Layer {layer} in keymaps isn't declared in enum layers {declared:?}")]
+    LayerNotDeclared {
+        layer: LayerId,
+        declared: Vec<String>,
+    },
+    #[error("Layer {layer} is declared in enum layers but missing in keymaps")]
+    LayerMissing { layer: String },
+    #[error("Expected two keycodes in ENCODER_CCW_CW for layer {layer}, got `{}`", .args.join(", "))]
+    EncoderArgs { layer: LayerId, args: Vec<String> },
+    #[error("{reason} in `{line}`")]
+    InvalidMacroCall { reason: String, line: String },
+    #[error("Couldn't find combo `{key}` in base layer")]
+    ComboKeyNotFound { combo: String, key: String },
+    #[error("No render opts given")]
+    NoRenderOpts,
+    #[error("Only one of {key} and {file_key} can be given")]
+    LayoutFileConflict { key: String, file_key: String },
+    #[error("{file_key} should be a path")]
+    LayoutFileNotPath { file_key: String },
+    #[error("Failed to read {path}: {source}")]
+    ReadLayoutFile {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+    #[error("physical_layout has {effort_rows} rows but finger_assignments has {finger_rows}")]
+    LayoutRowCount {
+        effort_rows: usize,
+        finger_rows: usize,
+    },
+    #[error("Effort `{effort}` isn't a digit in physical_layout row {row}: `{line}`")]
+    InvalidEffort {
+        effort: char,
+        row: usize,
+        line: String,
+    },
+    #[error("More splits in physical_layout row {row} than the {split:?} split allows: `{line}`")]
+    TooManySplits {
+        split: SplitMode,
+        row: usize,
+        line: String,
+    },
+    #[error("{reason} in finger_assignments row {row}: `{line}`")]
+    InvalidFingerAssignment {
+        reason: String,
+        row: usize,
+        line: String,
+    },
+    #[error("Unknown finger `{finger}`, expected one of: {}", .accepted.join(", "))]
+    UnknownFinger {
+        finger: String,
+        accepted: Vec<String>,
+    },
+    #[error("Invalid palette color `{color}`: {reason}")]
+    InvalidPaletteColor { color: String, reason: String },
+}
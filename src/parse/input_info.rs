@@ -1,7 +1,7 @@
 use camino::Utf8PathBuf;
 use eyre::Result;
 
-use super::keymap::{Keymap, ParseSettings};
+use super::keymap::{parse_defines, Keymap, ParseSettings};
 use super::render_opts::RenderOpts;
 
 #[derive(Debug, Clone)]
@@ -11,26 +11,26 @@ pub struct InputInfo {
 }
 
 impl InputInfo {
+    /// Parses the inputs along with warnings about the keymap, see `Keymap::parse`.
     pub fn parse(
-        qmk_root: Utf8PathBuf,
-        keyboard: String,
-        keymap: String,
-        render_opts: Utf8PathBuf,
-    ) -> Result<Self> {
-        let render_opts = RenderOpts::parse(&render_opts)?;
+        settings: &ParseSettings,
+        render_opts: &[Utf8PathBuf],
+    ) -> Result<(Self, Vec<String>)> {
+        let mut render_opts = RenderOpts::parse_merged(render_opts)?;
+        render_opts.keyboard = settings.keyboard.clone();
+        render_opts.keymap = settings.keymap.clone();
 
-        let keymap = Keymap::parse(
-            &ParseSettings {
-                qmk_root,
-                keyboard,
+        if render_opts.resolve_defines {
+            render_opts.defines = parse_defines(settings)?;
+        }
+        let (keymap, warnings) = Keymap::parse(settings, &render_opts)?;
+
+        Ok((
+            Self {
                 keymap,
+                render_opts,
             },
-            &render_opts,
-        )?;
-
-        Ok(Self {
-            keymap,
-            render_opts,
-        })
+            warnings,
+        ))
     }
 }
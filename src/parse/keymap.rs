@@ -1,34 +1,48 @@
 use crate::parse::Finger;
 use crate::parse::FingerAssignment;
-use camino::Utf8PathBuf;
-use eyre::{eyre, OptionExt, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::Result;
 use regex::Regex;
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::hash::Hash;
 use std::sync::LazyLock;
 
-use super::render_opts::{PhysicalPos, RenderOpts};
+use super::normalize_source;
+use super::render_opts::{display_width, ComboMacro, PhysicalPos, RenderOpts};
+use super::ParseError;
 
 #[derive(Debug)]
 pub struct ParseSettings {
     pub qmk_root: Utf8PathBuf,
     pub keyboard: String,
     pub keymap: String,
+    // Treat warnings about the keymap as errors
+    pub strict: bool,
+    // Override the paths derived from the QMK directory layout
+    pub keymap_c_path: Option<Utf8PathBuf>,
+    pub combos_def_path: Option<Utf8PathBuf>,
+    pub keyboard_json_path: Option<Utf8PathBuf>,
 }
 
 impl ParseSettings {
     pub fn combos_def(&self) -> Utf8PathBuf {
-        self.keymap_dir().join("combos.def")
+        self.combos_def_path
+            .clone()
+            .unwrap_or_else(|| self.keymap_dir().join("combos.def"))
     }
 
     pub fn keymap_c(&self) -> Utf8PathBuf {
-        self.keymap_dir().join("keymap.c")
+        self.keymap_c_path
+            .clone()
+            .unwrap_or_else(|| self.keymap_dir().join("keymap.c"))
     }
 
     pub fn keyboard_json(&self) -> Utf8PathBuf {
-        self.keyboard_dir().join("keyboard.json")
+        self.keyboard_json_path
+            .clone()
+            .unwrap_or_else(|| self.keyboard_dir().join("keyboard.json"))
     }
 
     pub fn info_json(&self) -> Utf8PathBuf {
@@ -56,24 +70,83 @@ impl ParseSettings {
 #[derive(Debug, Clone)]
 pub struct Keymap {
     pub layers: Vec<Layer>,
+    // In the order of combos.def, which is the index QMK gives the combo and the
+    // keylog uses for `COMBO` rows. Comments and blank lines don't take an index.
     pub combos: Vec<Combo>,
+    pub encoders: Vec<EncoderDef>,
 }
 
 impl Keymap {
-    pub fn parse(input: &ParseSettings, render_opts: &RenderOpts) -> Result<Self> {
+    /// Parses the keymap along with warnings about it, such as duplicate keys and
+    /// finger conflicts, for the caller to print or report.
+    pub fn parse(input: &ParseSettings, render_opts: &RenderOpts) -> Result<(Self, Vec<String>)> {
         let keymap_c = fs::read_to_string(input.keymap_c())?;
         let keyboard_json_path = input.keyboard_json();
         let info_json_path = input.info_json();
-        let info = if keyboard_json_path.is_file() {
+        // An overridden path is read even if it's missing, to report it in the error
+        let info = if input.keyboard_json_path.is_some() || keyboard_json_path.is_file() {
             fs::read_to_string(keyboard_json_path)?
         } else if info_json_path.is_file() {
             fs::read_to_string(info_json_path)?
         } else {
-            return Err(eyre!("Couldn't find keyboard.json or info.json at {keyboard_json_path} nor {info_json_path}"));
+            return Err(ParseError::KeyboardJsonNotFound {
+                keyboard_json: keyboard_json_path,
+                info_json: info_json_path,
+            }
+            .into());
         };
 
         let combos_def = fs::read_to_string(input.combos_def())?;
-        Self::parse_from_source(&keymap_c, &info, &combos_def, render_opts)
+        let keymap = if input.keymap_c().extension() == Some("json") {
+            Self::parse_from_qmk_json(&keymap_c, &info, &combos_def, render_opts)?
+        } else {
+            Self::parse_from_source(&keymap_c, &info, &combos_def, render_opts)?
+        };
+        let mut warnings = keymap.check_duplicate_keys(input.strict)?;
+        warnings.extend(keymap.finger_conflicts());
+        Ok((keymap, warnings))
+    }
+
+    pub fn finger_conflicts(&self) -> Vec<String> {
+        self.combos
+            .iter()
+            .filter(|x| x.has_finger_conflict())
+            .map(|combo| {
+                let keys: Vec<_> = combo.keys.iter().map(|key| key.id.0.as_str()).collect();
+                format!(
+                    "combo {} uses the same finger for more than one of {}",
+                    combo.id,
+                    keys.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    /// Reports keys used at multiple positions in a layer, as warnings or
+    /// as an error if `strict` is set.
+    pub fn check_duplicate_keys(&self, strict: bool) -> Result<Vec<String>> {
+        let mut warnings = Vec::new();
+        for err in self.duplicate_keys() {
+            if strict {
+                return Err(err.into());
+            }
+            warnings.push(err.to_string());
+        }
+        Ok(warnings)
+    }
+
+    pub fn duplicate_keys(&self) -> Vec<ParseError> {
+        let mut res = Vec::new();
+        for layer in &self.layers {
+            for (id, positions) in layer.duplicate_keys() {
+                res.push(ParseError::DuplicateKey {
+                    key: id.clone(),
+                    positions,
+                    layer: layer.id.clone(),
+                });
+            }
+        }
+        res
     }
 
     pub fn parse_from_source(
@@ -82,29 +155,128 @@ impl Keymap {
         combos_def: &str,
         render_opts: &RenderOpts,
     ) -> Result<Self> {
-        let layer_defs = parse_layers_from_source(keymap_c)?;
-        let keyboard_spec: KeyboardSpec = serde_json::from_str(keyboard_json)?;
+        let keymap_c = &normalize_source(keymap_c);
+        let keyboard_json = &normalize_source(keyboard_json);
+        let combos_def = &normalize_source(combos_def);
+
+        let mut layer_defs = parse_layers_from_source(keymap_c)?;
+        if let Some(layer_names) = parse_layer_enum(keymap_c) {
+            layer_defs = order_layers_by_enum(layer_defs, &layer_names)?;
+        }
+        let encoders = parse_encoders_from_source(keymap_c)?;
+        Self::from_layer_defs(layer_defs, encoders, keyboard_json, combos_def, render_opts)
+    }
 
-        let layers = layer_defs
+    /// Parses a keymap exported from QMK Configurator, such as
+    /// `{ "layout": "LAYOUT_split_3x5_2", "layers": [["KC_Q", ...], ...] }`.
+    /// The layers don't have names in the export and are named by their index.
+    pub fn parse_from_qmk_json(
+        keymap_json: &str,
+        keyboard_json: &str,
+        combos_def: &str,
+        render_opts: &RenderOpts,
+    ) -> Result<Self> {
+        let keymap: QmkKeymapJson = serde_json::from_str(&normalize_source(keymap_json))?;
+        let layer_defs = keymap
+            .layers
             .into_iter()
-            .map(|def| Layer::new(def, &keyboard_spec, render_opts))
-            .collect::<Result<Vec<_>>>()?;
+            .enumerate()
+            .map(|(i, keys)| LayerDef {
+                layer_id: LayerId(i.to_string()),
+                layout_id: LayoutId(keymap.layout.clone()),
+                keys: keys.into_iter().map(KeyId).collect(),
+            })
+            .collect();
+        Self::from_layer_defs(
+            layer_defs,
+            Vec::new(),
+            &normalize_source(keyboard_json),
+            &normalize_source(combos_def),
+            render_opts,
+        )
+    }
+
+    fn from_layer_defs(
+        layer_defs: Vec<LayerDef>,
+        encoders: Vec<EncoderDef>,
+        keyboard_json: &str,
+        combos_def: &str,
+        render_opts: &RenderOpts,
+    ) -> Result<Self> {
+        let keyboard_spec: KeyboardSpec = serde_json::from_str(keyboard_json)?;
+
+        let base_layer_def = layer_defs.first().ok_or(ParseError::NoLayers)?;
+        let physical_key_count = render_opts.physical_layout.key_count();
+        if base_layer_def.keys.len() != physical_key_count {
+            return Err(ParseError::BaseLayerKeyCount {
+                layer: base_layer_def.layer_id.clone(),
+                keys: base_layer_def.keys.len(),
+                physical_keys: physical_key_count,
+            }
+            .into());
+        }
+
+        let mut layers: Vec<Layer> = Vec::with_capacity(layer_defs.len());
+        for def in layer_defs {
+            let layer = Layer::new(def, &keyboard_spec, render_opts, layers.first())?;
+            layers.push(layer);
+        }
 
         let base_layer = &layers[0];
 
-        let combos = parse_combos_from_source(combos_def, base_layer)?;
+        let combos = parse_combos_from_source(combos_def, base_layer, &render_opts.combo_macros)?;
+
+        Ok(Self {
+            layers,
+            combos,
+            encoders,
+        })
+    }
 
-        Ok(Self { layers, combos })
+    /// Aggregates of all combos, independent of how often they're used.
+    pub fn combo_summary(&self) -> ComboSummary {
+        let mut summary = ComboSummary {
+            total: self.combos.len(),
+            ..Default::default()
+        };
+        for combo in &self.combos {
+            *summary.by_key_count.entry(combo.keys.len()).or_default() += 1;
+            if combo.is_same_hand() {
+                summary.same_hand += 1;
+            } else {
+                summary.cross_hand += 1;
+            }
+            for finger in combo.get_fingers() {
+                *summary.finger_usage.entry(finger).or_default() += 1;
+            }
+        }
+        summary
     }
 
     pub fn get_layer_id(&self, i: usize) -> Option<LayerId> {
         self.layers.get(i).map(|layer| layer.id.clone())
     }
 
+    pub fn find_encoders(&self, layer_id: &LayerId) -> Option<&EncoderDef> {
+        self.encoders.iter().find(|def| def.layer_id == *layer_id)
+    }
+
+    /// Finds a combo by the id it's given in combos.def, which is unique.
+    pub fn find_combo_by_id(&self, id: &str) -> Option<&Combo> {
+        self.combos.iter().find(|combo| combo.id == id)
+    }
+
+    /// Finds the first combo that produces `output`.
+    pub fn find_combo_by_output(&self, output: &str) -> Option<&Combo> {
+        self.combos.iter().find(|combo| combo.output == output)
+    }
+
+    /// Finds the key that's active at a matrix position, starting from `highest_layer`
+    /// and falling through transparent keys to the layers below.
     pub fn find_key_by_matrix(&self, highest_layer: usize, pos: (usize, usize)) -> Option<&Key> {
         let mut curr_layer = highest_layer;
         loop {
-            let layer = &self.layers[curr_layer];
+            let layer = self.layers.get(curr_layer)?;
 
             if let Some(key) = layer.find_key_by_matrix(pos) {
                 if !is_fallback_key(&key.id) {
@@ -121,7 +293,10 @@ impl Keymap {
 }
 
 fn is_fallback_key(id: &KeyId) -> bool {
-    matches!(id.0.as_str(), "_______" | "xxxxxxx")
+    matches!(
+        id.0.as_str(),
+        "_______" | "xxxxxxx" | "XXXXXXX" | "KC_TRNS" | "KC_TRANSPARENT"
+    )
 }
 
 #[derive(Debug, Clone)]
@@ -131,34 +306,63 @@ pub struct Layer {
 }
 
 impl Layer {
-    pub fn new(def: LayerDef, spec: &KeyboardSpec, render_opts: &RenderOpts) -> Result<Self> {
+    /// Each layer is validated against the layout macro it uses. A layer with a
+    /// smaller layout than the base layer gets the physical positions of the base
+    /// layer keys at the same matrix positions.
+    pub fn new(
+        def: LayerDef,
+        spec: &KeyboardSpec,
+        render_opts: &RenderOpts,
+        base_layer: Option<&Layer>,
+    ) -> Result<Self> {
         let layout_id = &def.layout_id.0;
         let layout_spec = spec
             .get_layout(layout_id)
-            .ok_or_eyre(format!("Failed to find layout spec for {}", layout_id))?;
+            .ok_or_else(|| ParseError::LayoutNotFound {
+                layout: layout_id.clone(),
+                layer: def.layer_id.clone(),
+                available: spec.layout_names().iter().map(|x| x.to_string()).collect(),
+            })?;
+        layout_spec.validate(layout_id)?;
 
         if def.keys.len() != layout_spec.layout.len() {
-            return Err(eyre!(
-                "Layer and it's spec has a mismatched number of keys {} != {} for layer {}",
-                def.keys.len(),
-                layout_spec.layout.len(),
-                def.layer_id.0
-            ));
+            return Err(ParseError::LayerKeyCount {
+                layer: def.layer_id.clone(),
+                keys: def.keys.len(),
+                layout: layout_id.clone(),
+                layout_keys: layout_spec.layout.len(),
+            }
+            .into());
         }
 
+        let by_index = def.keys.len() == render_opts.physical_layout.key_count();
         let keys = def
             .keys
             .into_iter()
             .zip(layout_spec.layout.iter())
             .enumerate()
-            .map(|(i, (id, spec))| Key {
-                id,
-                x: spec.x,
-                y: spec.y,
-                matrix_pos: spec.matrix,
-                physical_pos: render_opts.physical_layout.index_to_pos(i),
+            .map(|(i, (id, spec))| {
+                let physical_pos = match base_layer {
+                    Some(base_layer) if !by_index => base_layer
+                        .find_key_by_matrix(spec.matrix)
+                        .map(|key| key.physical_pos)
+                        .ok_or_else(|| ParseError::KeyNotInBaseLayer {
+                            key: id.clone(),
+                            matrix: spec.matrix,
+                            layer: def.layer_id.clone(),
+                            base_layer: base_layer.id.clone(),
+                        })?,
+                    _ => render_opts.physical_layout.index_to_pos(i),
+                };
+                Ok(Key {
+                    id,
+                    x: spec.x,
+                    y: spec.y,
+                    matrix_pos: spec.matrix,
+                    physical_pos,
+                })
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Layer {
             id: def.layer_id,
@@ -166,6 +370,23 @@ impl Layer {
         })
     }
 
+    /// Keys, ignoring fallback keys, that are used at more than one position
+    /// together with their matrix positions.
+    pub fn duplicate_keys(&self) -> Vec<(&KeyId, Vec<(usize, usize)>)> {
+        let mut res: Vec<(&KeyId, Vec<(usize, usize)>)> = Vec::new();
+        for key in &self.keys {
+            if is_fallback_key(&key.id) {
+                continue;
+            }
+            match res.iter_mut().find(|(id, _)| **id == key.id) {
+                Some((_, positions)) => positions.push(key.matrix_pos),
+                None => res.push((&key.id, vec![key.matrix_pos])),
+            }
+        }
+        res.retain(|(_, positions)| positions.len() > 1);
+        res
+    }
+
     pub fn find_key_by_id(&self, id: &str) -> Option<&Key> {
         self.keys.iter().find(|key| key.id.0 == id)
     }
@@ -198,6 +419,11 @@ impl Key {
     pub fn is_sfb(&self, other: &Key) -> bool {
         self.physical_pos.is_sfb(&other.physical_pos)
     }
+
+    /// Euclidean distance between the render positions, in key units.
+    pub fn travel_distance(&self, other: &Key) -> f32 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+    }
 }
 
 impl Eq for Key {}
@@ -224,7 +450,7 @@ impl std::fmt::Display for KeyId {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct LayerId(pub String);
 
 impl std::fmt::Display for LayerId {
@@ -249,18 +475,119 @@ pub struct LayerDef {
     pub keys: Vec<KeyId>,
 }
 
+// The parts of a QMK Configurator keymap export that are used
+#[derive(Deserialize, Debug)]
+struct QmkKeymapJson {
+    layout: String,
+    layers: Vec<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EncoderDef {
+    pub layer_id: LayerId,
+    pub encoders: Vec<Encoder>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Encoder {
+    pub ccw: KeyId,
+    pub cw: KeyId,
+}
+
+#[derive(Debug, Default)]
+pub struct ComboSummary {
+    pub total: usize,
+    pub by_key_count: BTreeMap<usize, usize>,
+    pub same_hand: usize,
+    pub cross_hand: usize,
+    /// The number of combos that require a finger.
+    pub finger_usage: BTreeMap<FingerAssignment, usize>,
+}
+
+impl ComboSummary {
+    /// Fingers sorted by the number of combos they're required for, most first.
+    pub fn top_fingers(&self) -> Vec<(FingerAssignment, usize)> {
+        let mut res: Vec<_> = self.finger_usage.iter().map(|(f, x)| (*f, *x)).collect();
+        res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        res
+    }
+}
+
+/// Whether a combo outputs a single keycode, as `COMB`, or a string, as `SUBS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboKind {
+    Comb,
+    Subs,
+}
+
 #[derive(Debug, Clone)]
 pub struct Combo {
     pub id: String,
     pub output: String,
     pub keys: Vec<Key>,
+    // A sequence combo, the keys are in the order they're pressed
+    pub ordered: bool,
+    pub kind: ComboKind,
 }
 
 impl Combo {
     pub fn new(id: String, output: String, mut keys: Vec<Key>) -> Self {
         // Make sure that keys are sorted in matrix position
         keys.sort_by_key(|k| (k.physical_pos.col, k.physical_pos.row));
-        Combo { id, output, keys }
+        Combo {
+            id,
+            output,
+            keys,
+            ordered: false,
+            kind: ComboKind::Comb,
+        }
+    }
+
+    /// A combo whose keys are pressed in sequence, in the given order.
+    pub fn new_ordered(id: String, output: String, keys: Vec<Key>) -> Self {
+        Combo {
+            id,
+            output,
+            keys,
+            ordered: true,
+            kind: ComboKind::Comb,
+        }
+    }
+
+    /// Consecutive keys of an ordered combo that are typed with the same finger.
+    /// The keys of a chord are pressed at once and never form an sfb.
+    pub fn sequence_sfbs(&self) -> Vec<(&Key, &Key)> {
+        if !self.ordered {
+            return Vec::new();
+        }
+        self.keys
+            .windows(2)
+            .filter(|x| x[0].is_sfb(&x[1]))
+            .map(|x| (&x[0], &x[1]))
+            .collect()
+    }
+
+    /// Width of the title the output is shown with, see `display_width`. Without a
+    /// title of its own a `SUBS` output is shown without its quotes and send string macros.
+    pub fn output_display_length(&self, render_opts: &RenderOpts, layer_id: &LayerId) -> f32 {
+        display_width(&render_opts.get(&layer_id.0, &self.output).title)
+    }
+
+    /// Number of keystrokes the output corresponds to. A keycode is a single keystroke
+    /// while a string is a keystroke per character, ignoring QMK send string macros
+    /// such as `SS_TAP(X_LEFT)`.
+    pub fn output_len(&self) -> usize {
+        static SEND_STRING_MACRO: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"SS_\w+\((?:[^()]|\([^()]*\))*\)").unwrap());
+
+        if self.kind == ComboKind::Comb {
+            return 1;
+        }
+        SEND_STRING_MACRO
+            .replace_all(&self.output, "")
+            .chars()
+            .filter(|c| *c != '"')
+            .count()
     }
 
     pub fn min_x(&self) -> f32 {
@@ -317,6 +644,58 @@ impl Combo {
             && (a.physical_pos.row as i32 - b.physical_pos.row as i32).abs() == 1
     }
 
+    pub fn is_diagonal_neighbour(&self) -> bool {
+        if self.keys.len() != 2 {
+            return false;
+        }
+        let a = &self.keys[0];
+        let b = &self.keys[1];
+
+        (a.physical_pos.col as i32 - b.physical_pos.col as i32).abs() == 1
+            && (a.physical_pos.row as i32 - b.physical_pos.row as i32).abs() == 1
+    }
+
+    /// True if the two keys are next to each other in the rendered layout,
+    /// which may differ from the matrix on a column staggered board.
+    pub fn is_physical_neighbour(&self) -> bool {
+        if self.keys.len() != 2 {
+            return false;
+        }
+        let distance = self.keys[0].travel_distance(&self.keys[1]);
+        distance > 0.0 && distance <= 1.1
+    }
+
+    /// The combo with its keys looked up in the layer, as they're resolved from the
+    /// base layer when parsing. None if a key isn't on the layer.
+    pub fn on_layer(&self, layer: &Layer) -> Option<Combo> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| layer.find_key_by_id(&key.id.0).cloned())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Combo {
+            keys,
+            ..self.clone()
+        })
+    }
+
+    pub fn is_same_hand(&self) -> bool {
+        self.keys
+            .windows(2)
+            .all(|x| x[0].physical_pos.finger.half == x[1].physical_pos.finger.half)
+    }
+
+    /// A combo of a thumb key on each half.
+    pub fn is_cross_hand_thumb(&self) -> bool {
+        if self.keys.len() != 2 {
+            return false;
+        }
+        let a = &self.keys[0].physical_pos.finger;
+        let b = &self.keys[1].physical_pos.finger;
+
+        a.half != b.half && a.finger == Finger::Thumb && b.finger == Finger::Thumb
+    }
+
     pub fn is_mid_triple(&self) -> bool {
         if self.keys.len() != 3 {
             return false;
@@ -347,6 +726,9 @@ impl Combo {
             .any(|combo_key| combo_key.physical_pos.finger == *finger)
     }
 
+    /// A key following (or followed by) the combo is an sfb if one of the combo keys
+    /// uses the same finger at another position. Positions are physical, so a key
+    /// on another layer at the same position as a combo key is the same key.
     pub fn is_key_sfb(&self, key: &Key) -> bool {
         // Can't have the same position in the combo
         if self.contains_physical_pos(key.physical_pos.pos()) {
@@ -363,10 +745,28 @@ impl Combo {
             .collect()
     }
 
+    /// True if two keys of the combo are pressed by the same finger, which makes
+    /// the combo hard or impossible to chord. Adjacent keys are fine as a single
+    /// finger can press both at once.
+    pub fn has_finger_conflict(&self) -> bool {
+        if self.ordered {
+            return false;
+        }
+        self.keys.iter().enumerate().any(|(i, a)| {
+            self.keys[i + 1..].iter().any(|b| {
+                let (a, b) = (&a.physical_pos, &b.physical_pos);
+                let adjacent = a.col.abs_diff(b.col) + a.row.abs_diff(b.row) == 1;
+                a.finger == b.finger && a.pos() != b.pos() && !adjacent
+            })
+        })
+    }
+
     pub fn get_positions(&self) -> HashSet<(usize, usize)> {
         self.keys.iter().map(|key| key.physical_pos.pos()).collect()
     }
 
+    /// Two combos sharing any position are never an sfb, otherwise they're an sfb
+    /// if they share a finger.
     pub fn is_combo_sfb(&self, combo: &Combo) -> bool {
         if self
             .get_positions()
@@ -391,23 +791,95 @@ pub struct KeyboardSpec {
 }
 
 impl KeyboardSpec {
+    /// Finds a layout by name or alias. If there's no exact match a layout is
+    /// looked up case-insensitively, and then without size suffixes such as `_3x5_3`,
+    /// as long as only a single layout matches.
     pub fn get_layout(&self, id: &str) -> Option<&LayoutSpec> {
+        if let Some(layout) = self.get_layout_exact(id) {
+            return Some(layout);
+        }
+
+        let names = self.layout_names();
+        let find_unique = |matches: Vec<&&str>| match matches[..] {
+            [name] => self.get_layout_exact(name),
+            _ => None,
+        };
+
+        let case_insensitive: Vec<_> = names
+            .iter()
+            .filter(|name| name.eq_ignore_ascii_case(id))
+            .collect();
+        if let Some(layout) = find_unique(case_insensitive) {
+            return Some(layout);
+        }
+
+        let normalized_id = normalize_layout_name(id);
+        let normalized: Vec<_> = names
+            .iter()
+            .filter(|name| normalize_layout_name(name) == normalized_id)
+            .collect();
+        find_unique(normalized)
+    }
+
+    fn get_layout_exact(&self, id: &str) -> Option<&LayoutSpec> {
         if let Some(layout) = self.layouts.get(id) {
             return Some(layout);
         }
 
         if let Some(alias) = self.layout_aliases.as_ref().and_then(|map| map.get(id)) {
-            return self.get_layout(alias);
+            return self.get_layout_exact(alias);
         }
         None
     }
+
+    /// All layout names and aliases, sorted.
+    pub fn layout_names(&self) -> Vec<&str> {
+        let mut res: Vec<&str> = self.layouts.keys().map(|x| x.as_str()).collect();
+        if let Some(aliases) = &self.layout_aliases {
+            res.extend(aliases.keys().map(|x| x.as_str()));
+        }
+        res.sort();
+        res
+    }
+}
+
+// Lowercases and strips size suffixes, so `LAYOUT_split_3x5_3` becomes `layout_split`.
+fn normalize_layout_name(id: &str) -> String {
+    let mut res = id.to_lowercase();
+    while let Some((head, tail)) = res.rsplit_once('_') {
+        if tail.is_empty() || !tail.chars().all(|c| c.is_ascii_digit() || c == 'x') {
+            break;
+        }
+        res.truncate(head.len());
+    }
+    res
 }
 
+/// The keys of a `LAYOUT` macro in keymap.c are assumed to come in the same order
+/// as the `layout` array, as that's how QMK maps them to the matrix.
 #[derive(Deserialize, Debug)]
 pub struct LayoutSpec {
     layout: Vec<KeySpec>,
 }
 
+impl LayoutSpec {
+    pub fn validate(&self, layout_id: &str) -> Result<()> {
+        let mut seen: HashMap<(usize, usize), usize> = HashMap::new();
+        for (i, key) in self.layout.iter().enumerate() {
+            if let Some(prev) = seen.insert(key.matrix, i) {
+                return Err(ParseError::DuplicateMatrixPos {
+                    matrix: key.matrix,
+                    first: prev,
+                    second: i,
+                    layout: layout_id.to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct KeySpec {
     matrix: (usize, usize),
@@ -417,7 +889,7 @@ pub struct KeySpec {
 
 fn parse_layers_from_source(src: &str) -> Result<Vec<LayerDef>> {
     static KEYMAPS: LazyLock<Regex> = LazyLock::new(|| {
-        Regex::new(r"(?msx)const\s+uint16_t\s+PROGMEM\s+keymaps\[\]\[\w+\]\[\w+\]\s*=\s*\{(.+)};")
+        Regex::new(r"(?msx)const\s+uint16_t\s+PROGMEM\s+keymaps\[\]\[\w+\]\[\w+\]\s*=\s*\{(.+?)};")
             .unwrap()
     });
     static LAYER: LazyLock<Regex> = LazyLock::new(|| {
@@ -454,30 +926,202 @@ fn parse_layers_from_source(src: &str) -> Result<Vec<LayerDef>> {
     }
 }
 
-fn parse_combos_from_source(src: &str, base_layer: &Layer) -> Result<Vec<Combo>> {
+/// Collects the `#define NAME VALUE` aliases in keymap.c and the headers it includes
+/// from the same directory.
+pub fn parse_defines(input: &ParseSettings) -> Result<HashMap<String, String>> {
+    static INCLUDE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"(?m)^\s*#include\s+"([^"]+)""#).unwrap());
+
+    let keymap_c_path = input.keymap_c();
+    let keymap_dir = keymap_c_path.parent().unwrap_or(Utf8Path::new(""));
+    let keymap_c = normalize_source(&fs::read_to_string(&keymap_c_path)?);
+    let mut res = HashMap::new();
+    for caps in INCLUDE.captures_iter(&keymap_c) {
+        let header = keymap_dir.join(&caps[1]);
+        if header.is_file() {
+            res.extend(parse_defines_from_source(&fs::read_to_string(header)?));
+        }
+    }
+    // Defines in keymap.c come last to take priority
+    res.extend(parse_defines_from_source(&keymap_c));
+    Ok(res)
+}
+
+fn parse_defines_from_source(src: &str) -> HashMap<String, String> {
+    // Function like macros, such as `#define X(a)`, aren't aliases and don't match
+    static DEFINE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\s*#define\s+(\w+)\s+(.+)$").unwrap());
+
+    src.lines()
+        .filter_map(|line| DEFINE.captures(strip_comment(line)))
+        .map(|caps| (caps[1].to_string(), caps[2].trim().to_string()))
+        .collect()
+}
+
+/// Layer names in the order they're declared in `enum layers` or
+/// `enum layer_names`, if there is one.
+fn parse_layer_enum(src: &str) -> Option<Vec<String>> {
+    static LAYER_ENUM: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?ms)enum\s+(?:layers|layer_names)\s*\{([^}]*)\}").unwrap());
+
+    let caps = LAYER_ENUM.captures(src)?;
+    let body: Vec<_> = caps[1].lines().map(strip_comment).collect();
+    let names = body
+        .join("\n")
+        .split(',')
+        .filter_map(|x| {
+            // Skip explicit values such as `_BASE = 0`
+            let name = x.split('=').next().unwrap_or_default().trim();
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect();
+    Some(names)
+}
+
+/// The layer index QMK reports is the position in the enum, so the layers
+/// must be in the same order as the enum.
+fn order_layers_by_enum(
+    layer_defs: Vec<LayerDef>,
+    layer_names: &[String],
+) -> Result<Vec<LayerDef>> {
+    let mut by_name: HashMap<String, LayerDef> = HashMap::new();
+    for def in layer_defs {
+        if !layer_names.contains(&def.layer_id.0) {
+            return Err(ParseError::LayerNotDeclared {
+                layer: def.layer_id,
+                declared: layer_names.to_vec(),
+            }
+            .into());
+        }
+        by_name.insert(def.layer_id.0.clone(), def);
+    }
+
+    layer_names
+        .iter()
+        .map(|name| {
+            by_name.remove(name).ok_or_else(|| {
+                ParseError::LayerMissing {
+                    layer: name.clone(),
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+fn parse_encoders_from_source(src: &str) -> Result<Vec<EncoderDef>> {
+    static ENCODER_MAP: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"(?msx)const\s+uint16_t\s+PROGMEM\s+encoder_map\[\]\[\w+\]\[\w+\]\s*=\s*\{(.+?)};",
+        )
+        .unwrap()
+    });
+    static LAYER: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?ms)\[([\w\d_]+)\]\s*=\s*\{([^}]*)\}").unwrap());
+    static ENCODER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"ENCODER_CCW_CW\(").unwrap());
+
+    let Some(encoder_map) = ENCODER_MAP.captures(src) else {
+        return Ok(Vec::new());
+    };
+
+    let mut res = Vec::new();
+    for layer in LAYER.captures_iter(&encoder_map[1]) {
+        let layer_id = LayerId(layer[1].to_string());
+        let body = &layer[2];
+
+        let mut encoders = Vec::new();
+        for start in ENCODER.find_iter(body) {
+            let rest = &body[start.end()..];
+            let args = split_args(rest).map_err(|reason| ParseError::InvalidMacroCall {
+                reason: reason.to_string(),
+                line: format!("ENCODER_CCW_CW({}", rest.lines().next().unwrap_or_default()),
+            })?;
+            if args.len() != 2 {
+                return Err(ParseError::EncoderArgs {
+                    layer: layer_id,
+                    args: args.iter().map(|x| x.to_string()).collect(),
+                }
+                .into());
+            }
+            encoders.push(Encoder {
+                ccw: KeyId(args[0].to_string()),
+                cw: KeyId(args[1].to_string()),
+            });
+        }
+        res.push(EncoderDef { layer_id, encoders });
+    }
+    Ok(res)
+}
+
+// Splits the arguments of a macro call on top level commas,
+// with `s` starting just after the opening parenthesis.
+// Commas and parentheses in string literals, such as `"a, b"`, are ignored.
+// Returns the reason if the string literal or the call isn't closed.
+fn split_args(s: &str) -> Result<Vec<&str>, &'static str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut arg_start = 0;
+    let mut in_string = false;
+    let mut prev = None;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' if prev != Some('\\') => in_string = !in_string,
+            _ if in_string => {}
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                res.push(s[arg_start..i].trim());
+                return Ok(res);
+            }
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(s[arg_start..i].trim());
+                arg_start = i + 1;
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    if in_string {
+        Err("Unterminated string")
+    } else {
+        Err("Missing closing parenthesis")
+    }
+}
+
+fn parse_combos_from_source(
+    src: &str,
+    base_layer: &Layer,
+    macros: &[ComboMacro],
+) -> Result<Vec<Combo>> {
     let key_lookup: HashMap<String, Key> = base_layer
         .keys
         .iter()
         .map(|key| (key.id.0.to_owned(), key.clone()))
         .collect();
 
-    static SPEC: LazyLock<Regex> =
-        LazyLock::new(|| Regex::new(r"^\s*(COMB|SUBS)\((.+)\)\s*$").unwrap());
+    let names: Vec<_> = macros.iter().map(|x| regex::escape(&x.name)).collect();
+    // The arguments include the closing parenthesis, see `split_args`
+    let spec_re = Regex::new(&format!(r"^\s*({})\((.+\))\s*$", names.join("|")))?;
     static QUOTES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^"([^"]+)"$"#).unwrap());
 
     let mut res = Vec::new();
     for line in src.lines() {
-        if let Some(spec) = SPEC.captures(line) {
-            let args: Vec<_> = spec[2].split(",").map(|x| x.trim()).collect();
+        if let Some(spec) = spec_re.captures(strip_comment(line)) {
+            let invalid = |reason: &str| ParseError::InvalidMacroCall {
+                reason: reason.to_string(),
+                line: line.trim().to_string(),
+            };
+            let args = split_args(&spec[2]).map_err(invalid)?;
+            if args.len() < 3 {
+                return Err(invalid("Expected a name, an output and at least one key").into());
+            }
             let id = args[0].to_string();
             let output_s = args[1].to_string();
-            let output = match &spec[1] {
-                "SUBS" => match QUOTES.captures(&output_s) {
-                    Some(x) => x[1].to_string(),
-                    None => output_s,
-                },
-                "COMB" => output_s,
-                _ => panic!("No SUBS or COMB in regex match {}", &spec[1]),
+            let strip_quotes = macros.iter().any(|x| x.name == spec[1] && x.strip_quotes);
+            let ordered = macros.iter().any(|x| x.name == spec[1] && x.ordered);
+            let output = match QUOTES.captures(&output_s) {
+                Some(x) if strip_quotes => x[1].to_string(),
+                _ => output_s,
             };
 
             let keys = args[2..]
@@ -486,24 +1130,49 @@ fn parse_combos_from_source(src: &str, base_layer: &Layer) -> Result<Vec<Combo>>
                     key_lookup
                         .get(*x)
                         .cloned()
-                        .ok_or_eyre(format!("Couldn't find combo `{x}` in base layer"))
+                        .ok_or_else(|| ParseError::ComboKeyNotFound {
+                            combo: id.clone(),
+                            key: x.to_string(),
+                        })
                 })
-                .collect::<Result<Vec<_>>>()?;
-            res.push(Combo::new(id, output, keys));
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut combo = if ordered {
+                Combo::new_ordered(id, output, keys)
+            } else {
+                Combo::new(id, output, keys)
+            };
+            // Macros that strip quotes, such as `SUBS`, output strings
+            if strip_quotes {
+                combo.kind = ComboKind::Subs;
+            }
+            res.push(combo);
         }
     }
     Ok(res)
 }
 
+/// Removes a trailing `//` comment, ignoring `//` inside string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' if prev != Some('\\') => in_string = !in_string,
+            '/' if !in_string && prev == Some('/') => return &line[..i - 1],
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    line
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parse::MatrixHalf;
     use eyre::Result;
 
-    #[test]
-    fn test_parse_keymap() -> Result<()> {
-        let keymap_c = r#"
+    const KEYMAP_C: &str = r#"
 // clang-format off
 const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
     [_BASE] = LAYOUT(
@@ -521,8 +1190,8 @@ const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
                                  _______, _______,      _______
     )
 };
-        "#;
-        let keyboard_json = r#"
+"#;
+    const KEYBOARD_JSON: &str = r#"
 {
     "layouts": {
         "LAYOUT": {
@@ -570,9 +1239,9 @@ const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
         }
     }
 }
-        "#;
+"#;
 
-        let combos_def = r##"
+    const COMBOS_DEF: &str = r##"
 // Comment
 COMB(num,               NUMWORD,        MT_SPC, SE_E)
 
@@ -583,9 +1252,10 @@ COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
 SUBS(lt_eq,             "<=",           SE_F, SE_H)
 
 SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
-        "##;
+COMB(tab,               KC_TAB,         SE_T, SE_D)
+"##;
 
-        let render_input = r#"
+    const RENDER_INPUT: &str = r#"
 {
   "layers": {},
   "colors": {},
@@ -611,12 +1281,123 @@ SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
     "   44    4"
   ]
 }
+"#;
+    #[test]
+    fn test_parse_keymap() -> Result<()> {
+        let keymap_c = r#"
+// clang-format off
+const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
+    [_BASE] = LAYOUT(
+      SE_J,    SE_C,    SE_Y,    SE_F,    SE_P,         SE_X,    SE_W,    SE_O,    SE_U,    SE_DOT,
+      SE_R,    SE_S,    SE_T,    SE_H,    SE_K,         SE_M,    SE_N,    SE_A,    SE_I,    REPEAT,
+      SE_COMM, SE_V,    SE_G,    SE_D,    SE_B,         SE_SLSH, SE_L,    SE_LPRN, SE_RPRN, SE_UNDS,
+               xxxxxxx, xxxxxxx,
+                                 FUN,     MT_SPC,       SE_E
+    ),
+    [_NUM]  = LAYOUT(
+      SE_J,    SE_PLUS, SE_ASTR, SE_EXLM, SE_P,         SE_X,    _______, AT_U,    REPEAT,  _______,
+      SE_6,    SE_4,    SE_0,    SE_2,    SE_K,         _______, SE_3,    SE_1,    SE_5,    SE_7,
+      SE_COMM, _______, NUM_G,   SE_8,    _______,      SE_SLSH, SE_9,    SE_LPRN, SE_RPRN, SE_UNDS,
+               _______, _______,
+                                 _______, _______,      _______
+    )
+};
         "#;
-        let render_opts = RenderOpts::parse_from_str("id", render_input)?;
-
-        let keymap = Keymap::parse_from_source(keymap_c, keyboard_json, combos_def, &render_opts)?;
-
-        assert_eq!(keymap.layers.len(), 2);
+        let keyboard_json = r#"
+{
+    "layouts": {
+        "LAYOUT": {
+            "layout": [
+                { "matrix": [1, 0], "x": 0, "y": 0.93 },
+                { "matrix": [0, 1], "x": 1, "y": 0.31 },
+                { "matrix": [0, 2], "x": 2, "y": 0 },
+                { "matrix": [0, 3], "x": 3, "y": 0.28 },
+                { "matrix": [0, 4], "x": 4, "y": 0.42 },
+                { "matrix": [4, 0], "x": 7, "y": 0.42 },
+                { "matrix": [4, 1], "x": 8, "y": 0.28 },
+                { "matrix": [4, 2], "x": 9, "y": 0 },
+                { "matrix": [4, 3], "x": 10, "y": 0.31 },
+                { "matrix": [4, 4], "x": 11, "y": 0.93 },
+
+                { "matrix": [2, 0], "x": 0, "y": 1.93 },
+                { "matrix": [1, 1], "x": 1, "y": 1.31 },
+                { "matrix": [1, 2], "x": 2, "y": 1 },
+                { "matrix": [1, 3], "x": 3, "y": 1.28 },
+                { "matrix": [1, 4], "x": 4, "y": 1.42 },
+                { "matrix": [5, 0], "x": 7, "y": 1.42 },
+                { "matrix": [5, 1], "x": 8, "y": 1.28 },
+                { "matrix": [5, 2], "x": 9, "y": 1 },
+                { "matrix": [5, 3], "x": 10, "y": 1.31 },
+                { "matrix": [5, 4], "x": 11, "y": 1.93 },
+
+                { "matrix": [3, 0], "x": 0, "y": 2.93 },
+                { "matrix": [2, 1], "x": 1, "y": 2.31 },
+                { "matrix": [2, 2], "x": 2, "y": 2 },
+                { "matrix": [2, 3], "x": 3, "y": 2.28 },
+                { "matrix": [2, 4], "x": 4, "y": 2.42 },
+                { "matrix": [6, 0], "x": 7, "y": 2.42 },
+                { "matrix": [6, 1], "x": 8, "y": 2.28 },
+                { "matrix": [6, 2], "x": 9, "y": 2 },
+                { "matrix": [6, 3], "x": 10, "y": 2.31 },
+                { "matrix": [6, 4], "x": 11, "y": 2.93 },
+
+                { "matrix": [3, 1], "x": 1, "y": 3.31 },
+                { "matrix": [3, 2], "x": 2, "y": 3 },
+
+                { "matrix": [3, 3], "x": 3.5, "y": 3.75 },
+                { "matrix": [3, 4], "x": 4.5, "y": 4 },
+                { "matrix": [7, 0], "x": 6.5, "y": 4 }
+            ]
+        }
+    }
+}
+        "#;
+
+        let combos_def = r##"
+// Comment
+COMB(num,               NUMWORD,        MT_SPC, SE_E)
+
+SUBS(https,             "https://",     MT_SPC, SE_SLSH)
+COMB(comb_boot_r,       QK_BOOT,        SE_E, SE_L, SE_LPRN, SE_RPRN, SE_UNDS)
+
+COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
+SUBS(lt_eq,             "<=",           SE_F, SE_H)
+
+SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
+        "##;
+
+        let render_input = r#"
+{
+  "layers": {},
+  "colors": {},
+  "legend": [],
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": [
+    "54446    64445",
+    "21005    50012",
+    "64436    63446",
+    " 77",
+    "   80    0"
+  ],
+  "finger_assignments": [
+    "11233    33211",
+    "01233    33210",
+    "01233    33210",
+    " 12",
+    "   44    4"
+  ]
+}
+        "#;
+        let render_opts = RenderOpts::parse_from_str("id", render_input)?;
+
+        let keymap = Keymap::parse_from_source(keymap_c, keyboard_json, combos_def, &render_opts)?;
+
+        assert_eq!(keymap.layers.len(), 2);
         assert_eq!(keymap.layers[0].id.0, "_BASE");
         assert_eq!(keymap.layers[0].keys.len(), 35);
         assert_eq!(keymap.layers[1].id.0, "_NUM");
@@ -670,15 +1451,637 @@ SUBS(el_str_int,        "#{}"SS_TAP(X_LEFT),  SE_X, SE_W)
                 }
             }
         );
+        assert_eq!(keymap.combos[0].kind, ComboKind::Comb);
+        assert_eq!(keymap.combos[1].kind, ComboKind::Subs);
+        assert_eq!(keymap.combos[5].kind, ComboKind::Subs);
         assert!(keymap.combos[1].contains_input_key("MT_SPC"));
         assert!(!keymap.combos[3].contains_input_key("MT_SPC"));
         assert!(keymap.combos[3].is_horizontal_neighbour());
         assert!(!keymap.combos[3].is_vertical_neighbour());
         assert!(!keymap.combos[4].is_horizontal_neighbour());
         assert!(keymap.combos[4].is_vertical_neighbour());
+        assert_eq!(keymap.combos[0].output_len(), 1);
+        assert_eq!(keymap.combos[1].output_len(), 8);
+        assert_eq!(keymap.combos[5].output_len(), 3);
+
+        assert!(keymap.combos[0].is_cross_hand_thumb());
+        assert!(!keymap.combos[3].is_cross_hand_thumb());
 
         assert_eq!(keymap.combos[5].output, "\"#{}\"SS_TAP(X_LEFT)");
 
         Ok(())
     }
+
+    #[test]
+    fn test_output_display_length() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+        let base = &keymap.layers[0].id;
+        let width = |id| {
+            keymap
+                .find_combo_by_id(id)
+                .unwrap()
+                .output_display_length(&render_opts, base)
+        };
+
+        // `"#{}"SS_TAP(X_LEFT)` is shown as `#{}←`
+        assert_eq!(width("el_str_int"), 4.5);
+        assert_eq!(width("https"), 8.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_trailing_comment() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let combos_def = r#"
+COMB(num,               NUMWORD,        MT_SPC, SE_E) // number word
+SUBS(https,             "https://",     MT_SPC, SE_SLSH)  // Not a comment: "//"
+"#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+
+        assert_eq!(keymap.combos.len(), 2);
+        assert_eq!(keymap.combos[0].output, "NUMWORD");
+        assert_eq!(keymap.combos[1].output, "https://");
+        assert_eq!(strip_comment("SE_A // SE_B"), "SE_A ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finger_conflict() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+        assert!(!keymap.combos.iter().any(|x| x.has_finger_conflict()));
+        assert!(keymap.finger_conflicts().is_empty());
+
+        // SE_C and SE_V are both on the left ring finger, with SE_S between them
+        let combos_def = "COMB(ring,  KC_ESC,  SE_C, SE_V)";
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+        assert!(keymap.combos[0].has_finger_conflict());
+        assert_eq!(
+            keymap.finger_conflicts(),
+            vec!["combo ring uses the same finger for more than one of SE_C, SE_V"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diagonal_neighbour() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let combos_def = r#"
+COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
+SUBS(lt_eq,             "<=",           SE_F, SE_H)
+COMB(tab,               KC_TAB,         SE_T, SE_D)
+        "#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+
+        assert!(!keymap.combos[0].is_diagonal_neighbour());
+        assert!(!keymap.combos[1].is_diagonal_neighbour());
+        assert!(keymap.combos[2].is_diagonal_neighbour());
+        assert!(!keymap.combos[2].is_horizontal_neighbour());
+        assert!(!keymap.combos[2].is_vertical_neighbour());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_physical_neighbour() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let combos_def = r#"
+COMB(escape_sym,        ESC_SYM,        SE_T, SE_H)
+COMB(tab,               KC_TAB,         SE_T, SE_D)
+COMB(comb_boot_r,       QK_BOOT,        SE_E, SE_L, SE_LPRN)
+        "#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+
+        assert!(keymap.combos[0].is_physical_neighbour());
+        assert!(!keymap.combos[1].is_physical_neighbour());
+        // Only two key combos are placed between keys
+        assert!(!keymap.combos[2].is_physical_neighbour());
+        // A diagonal in the matrix that's adjacent on a column staggered board
+        let mut staggered = keymap.combos[1].clone();
+        staggered.keys[1].y = staggered.keys[0].y + 0.3;
+        assert!(staggered.is_physical_neighbour());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_key_by_matrix() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let mut keymap =
+            Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+
+        let mut sym = keymap.layers[1].clone();
+        sym.id = LayerId("_SYM".into());
+        for key in sym.keys.iter_mut() {
+            key.id = KeyId("KC_TRNS".into());
+        }
+        sym.keys[1].id = KeyId("SE_AT".into());
+        keymap.layers.push(sym);
+
+        let find = |layer: usize, pos: (usize, usize)| {
+            keymap
+                .find_key_by_matrix(layer, pos)
+                .map(|key| key.id.0.as_str())
+        };
+        // Defined on the highest layer
+        assert_eq!(find(2, (0, 1)), Some("SE_AT"));
+        assert_eq!(find(1, (0, 1)), Some("SE_PLUS"));
+        // Transparent on the highest layer
+        assert_eq!(find(2, (0, 2)), Some("SE_ASTR"));
+        // Transparent on all layers above base
+        assert_eq!(find(2, (4, 1)), Some("SE_W"));
+        assert_eq!(find(3, (0, 1)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_combo() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+
+        let tab = keymap.find_combo_by_id("tab").unwrap();
+        assert_eq!(tab.output, "KC_TAB");
+        assert_eq!(keymap.find_combo_by_output("KC_TAB").unwrap().id, "tab");
+        assert!(keymap.find_combo_by_id("KC_TAB").is_none());
+        assert!(keymap.find_combo_by_output("tab").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_summary() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+        let summary = keymap.combo_summary();
+
+        assert_eq!(summary.total, 7);
+        assert_eq!(summary.by_key_count, BTreeMap::from([(2, 6), (5, 1)]));
+        assert_eq!(summary.same_hand, 5);
+        assert_eq!(summary.cross_hand, 2);
+
+        let left_index = FingerAssignment {
+            finger: Finger::Index,
+            half: MatrixHalf::Left,
+        };
+        // lt_eq uses the left index twice but only counts once
+        assert_eq!(summary.top_fingers()[0], (left_index, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_macros() -> Result<()> {
+        let mut render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        render_opts.combo_macros =
+            serde_json::from_str(r#"["COMB", { "name": "SUBS", "strip_quotes": true }, "CMB"]"#)?;
+        let combos_def = r#"
+CMB(num,                NUMWORD,        MT_SPC, SE_E)
+SUBS(lt_eq,             "<=",           SE_F, SE_H)
+COMBO_TERM(ignored,     KC_ESC,         SE_T, SE_H)
+        "#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+
+        assert_eq!(keymap.combos.len(), 2);
+        assert_eq!(keymap.combos[0].id, "num");
+        assert_eq!(keymap.combos[0].output, "NUMWORD");
+        assert_eq!(keymap.combos[0].kind, ComboKind::Comb);
+        assert_eq!(keymap.combos[1].output, "<=");
+        assert_eq!(keymap.combos[1].kind, ComboKind::Subs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_on_layer() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let combos_def = r#"
+COMB(lt_eq,             SE_LABK,        SE_F, SE_H)
+COMB(sep,               SE_UNDS,        SE_SLSH, SE_COMM)
+        "#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+        let (base, num) = (&keymap.layers[0], &keymap.layers[1]);
+
+        assert!(keymap.combos[0].on_layer(base).is_some());
+        // SE_F and SE_H are replaced by symbols in the number layer
+        assert!(keymap.combos[0].on_layer(num).is_none());
+        let sep = keymap.combos[1].on_layer(num).unwrap();
+        assert_eq!(sep.keys[0].id.0, "SE_COMM");
+        assert_eq!(
+            sep.keys[1].matrix_pos,
+            num.find_key_by_id("SE_SLSH").unwrap().matrix_pos
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordered_combo() -> Result<()> {
+        let mut render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        render_opts.combo_macros =
+            serde_json::from_str(r#"["COMB", { "name": "SEQ", "ordered": true }]"#)?;
+        let combos_def = r#"
+COMB(chord,             KC_TAB,         SE_H, SE_T)
+SEQ(sequence,           KC_ESC,         SE_H, SE_T, SE_D)
+SEQ(sfb,                KC_ENT,         SE_F, SE_D)
+        "#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+        let ids = |combo: &Combo| -> Vec<String> {
+            combo.keys.iter().map(|key| key.id.0.clone()).collect()
+        };
+
+        let chord = &keymap.combos[0];
+        assert!(!chord.ordered);
+        assert_eq!(ids(chord), vec!["SE_T", "SE_H"]);
+
+        let sequence = &keymap.combos[1];
+        assert!(sequence.ordered);
+        assert_eq!(ids(sequence), vec!["SE_H", "SE_T", "SE_D"]);
+        assert!(sequence.sequence_sfbs().is_empty());
+
+        // Both on the left index, which can't be chorded but can be typed in sequence
+        let sfb = &keymap.combos[2];
+        assert!(!sfb.has_finger_conflict());
+        let sfbs = sfb.sequence_sfbs();
+        assert_eq!(sfbs.len(), 1);
+        assert_eq!(
+            (sfbs[0].0.id.0.as_str(), sfbs[0].1.id.0.as_str()),
+            ("SE_F", "SE_D")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_windows_line_endings() -> Result<()> {
+        let render_input = format!("\u{feff}{}", RENDER_INPUT.replace('\n', "\r\n"));
+        let render_opts = RenderOpts::parse_from_str("id", &render_input)?;
+        let keymap = Keymap::parse_from_source(
+            &KEYMAP_C.replace('\n', "\r\n"),
+            KEYBOARD_JSON,
+            &COMBOS_DEF.replace('\n', "\r\n"),
+            &render_opts,
+        )?;
+        let expected =
+            Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+
+        assert_eq!(keymap.layers.len(), expected.layers.len());
+        assert_eq!(keymap.combos.len(), expected.combos.len());
+        assert_eq!(keymap.combos[0].output, expected.combos[0].output);
+        assert!(keymap.combos.iter().all(|x| !x.output.contains('\r')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("a, b,c)"), Ok(vec!["a", "b", "c"]));
+        assert_eq!(
+            split_args(r#"x, "a, b", SE_X)"#),
+            Ok(vec!["x", r#""a, b""#, "SE_X"])
+        );
+        assert_eq!(
+            split_args(r#"x, "\",)", LT(1, KC_A), SE_X)"#),
+            Ok(vec!["x", r#""\",)""#, "LT(1, KC_A)", "SE_X"])
+        );
+        assert_eq!(
+            split_args(r#"x, "a, SE_X, SE_W)"#),
+            Err("Unterminated string")
+        );
+        assert_eq!(
+            split_args("x, SE_X, SE_W"),
+            Err("Missing closing parenthesis")
+        );
+    }
+
+    #[test]
+    fn test_malformed_combo() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        for combos_def in [r#"SUBS(x, "a, SE_X, SE_W)"#, "COMB(x, SE_X)"] {
+            let err = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<ParseError>(),
+                Some(ParseError::InvalidMacroCall { line, .. }) if line == combos_def
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_comma_in_subs() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let combos_def = r#"SUBS(comma_str,    "a, b",    SE_X, SE_W)"#;
+        let keymap = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, combos_def, &render_opts)?;
+
+        assert_eq!(keymap.combos.len(), 1);
+        assert_eq!(keymap.combos[0].output, "a, b");
+        assert_eq!(keymap.combos[0].keys.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_paths() {
+        let mut settings = ParseSettings {
+            qmk_root: "qmk".into(),
+            keyboard: "ferris/sweep".into(),
+            keymap: "default".into(),
+            strict: false,
+            keymap_c_path: None,
+            combos_def_path: None,
+            keyboard_json_path: None,
+        };
+        assert_eq!(
+            settings.keyboard_json(),
+            "qmk/keyboards/ferris/sweep/keyboard.json"
+        );
+        assert_eq!(
+            settings.keymap_c(),
+            "qmk/keyboards/ferris/keymaps/default/keymap.c"
+        );
+
+        settings.keyboard_json_path = Some("my/keyboard.json".into());
+        assert_eq!(settings.keyboard_json(), "my/keyboard.json");
+    }
+
+    #[test]
+    fn test_duplicate_keys() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let mut keymap =
+            Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+
+        // Fallback keys may be repeated
+        assert!(keymap.layers[0].duplicate_keys().is_empty());
+        assert!(keymap.layers[1].duplicate_keys().is_empty());
+        keymap.check_duplicate_keys(true)?;
+
+        keymap.layers[0].keys[1].id = KeyId("SE_J".into());
+        assert_eq!(
+            keymap.layers[0].duplicate_keys(),
+            vec![(&KeyId("SE_J".into()), vec![(1, 0), (0, 1)])]
+        );
+        assert!(keymap.check_duplicate_keys(false).is_ok());
+        let err = keymap.check_duplicate_keys(true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Key SE_J is used at multiple matrix positions [(1, 0), (0, 1)] in layer _BASE"
+        );
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::DuplicateKey { key, .. }) if key.0 == "SE_J"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_defines() {
+        let src = r#"
+#include "layers.h"
+#define MT_SPC LT(_SYM, KC_SPC) // Space or symbols
+#define ALIAS  SE_A
+#define FN(x)  LT(_FUN, x)
+#define EMPTY
+"#;
+        let defines = parse_defines_from_source(src);
+        assert_eq!(defines.len(), 2);
+        assert_eq!(defines["MT_SPC"], "LT(_SYM, KC_SPC)");
+        assert_eq!(defines["ALIAS"], "SE_A");
+    }
+
+    #[test]
+    fn test_layer_enum() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+
+        let enum_def = r#"
+enum layers {
+    _NUM, // Numbers
+    _BASE = 1,
+};
+"#;
+        let keymap_c = format!("{enum_def}{KEYMAP_C}");
+        assert_eq!(
+            parse_layer_enum(&keymap_c),
+            Some(vec!["_NUM".to_string(), "_BASE".to_string()])
+        );
+        // Layers follow the enum, not the keymaps array
+        let keymap = Keymap::parse_from_source(&keymap_c, KEYBOARD_JSON, "", &render_opts)?;
+        let ids: Vec<_> = keymap.layers.iter().map(|x| x.id.0.as_str()).collect();
+        assert_eq!(ids, vec!["_NUM", "_BASE"]);
+
+        let keymap_c = format!("enum layers {{ _BASE, _SYM }};\n{KEYMAP_C}");
+        assert!(
+            Keymap::parse_from_source(&keymap_c, KEYBOARD_JSON, COMBOS_DEF, &render_opts).is_err()
+        );
+
+        assert_eq!(
+            parse_layer_enum("enum layer_names { _BASE, _SYM };"),
+            Some(vec!["_BASE".to_string(), "_SYM".to_string()])
+        );
+        assert_eq!(parse_layer_enum(KEYMAP_C), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_from_qmk_json() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let from_c = Keymap::parse_from_source(KEYMAP_C, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+        let layers: Vec<Vec<&str>> = from_c
+            .layers
+            .iter()
+            .map(|layer| layer.keys.iter().map(|key| key.id.0.as_str()).collect())
+            .collect();
+        let keymap_json = serde_json::json!({
+            "keyboard": "test",
+            "keymap": "default",
+            "layout": "LAYOUT",
+            "layers": layers,
+        })
+        .to_string();
+
+        let keymap =
+            Keymap::parse_from_qmk_json(&keymap_json, KEYBOARD_JSON, COMBOS_DEF, &render_opts)?;
+        assert_eq!(keymap.layers.len(), from_c.layers.len());
+        assert_eq!(keymap.layers[0].id.0, "0");
+        assert_eq!(keymap.layers[1].id.0, "1");
+        for (a, b) in keymap.layers.iter().zip(&from_c.layers) {
+            assert_eq!(a.keys, b.keys);
+        }
+        assert_eq!(keymap.combos.len(), from_c.combos.len());
+        assert!(keymap.encoders.is_empty());
+
+        assert!(
+            Keymap::parse_from_qmk_json("{}", KEYBOARD_JSON, COMBOS_DEF, &render_opts).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_layouts() -> Result<()> {
+        let render_opts = RenderOpts::parse_from_str("id", RENDER_INPUT)?;
+        let keyboard_json = KEYBOARD_JSON.replace(
+            r#""layouts": {"#,
+            r#""layouts": {
+        "LAYOUT_small": {
+            "layout": [
+                { "matrix": [0, 1], "x": 1, "y": 0.31 },
+                { "matrix": [1, 1], "x": 1, "y": 1.31 }
+            ]
+        },"#,
+        );
+        let with_layer =
+            |layer: &str| KEYMAP_C.replace("    )\n};", &format!("    ),\n{layer}\n}};"));
+
+        let keymap_c = with_layer("    [_FUN] = LAYOUT_small(KC_F1, KC_F2\n    )");
+        let keymap =
+            Keymap::parse_from_source(&keymap_c, &keyboard_json, COMBOS_DEF, &render_opts)?;
+        let base = &keymap.layers[0];
+        let fun = &keymap.layers[2];
+        assert_eq!(fun.keys.len(), 2);
+        assert_eq!(
+            fun.find_key_by_id("KC_F1").unwrap().physical_pos,
+            base.find_key_by_id("SE_C").unwrap().physical_pos
+        );
+        assert_eq!(
+            fun.find_key_by_id("KC_F2").unwrap().physical_pos,
+            base.find_key_by_id("SE_S").unwrap().physical_pos
+        );
+
+        let keymap_c = with_layer("    [_FUN] = LAYOUT_small(KC_F1, KC_F2, KC_F3\n    )");
+        let err = Keymap::parse_from_source(&keymap_c, &keyboard_json, COMBOS_DEF, &render_opts)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Layer _FUN has 3 keys but its layout LAYOUT_small has 2 keys"
+        );
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::LayerKeyCount { layer, keys: 3, layout_keys: 2, .. }) if layer.0 == "_FUN"
+        ));
+
+        let keymap_c = with_layer("    [_FUN] = LAYOUT_tiny(KC_F1\n    )");
+        let err = Keymap::parse_from_source(&keymap_c, &keyboard_json, COMBOS_DEF, &render_opts)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to find layout spec LAYOUT_tiny for layer _FUN, available layouts are: LAYOUT, LAYOUT_small"
+        );
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::LayoutNotFound { layout, .. }) if layout == "LAYOUT_tiny"
+        ));
+
+        let combos_def = "COMB(missing, KC_NO, SE_S, SE_Q)";
+        let err = Keymap::parse_from_source(KEYMAP_C, &keyboard_json, combos_def, &render_opts)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::ComboKeyNotFound { combo, key }) if combo == "missing" && key == "SE_Q"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_layout() -> Result<()> {
+        let keyboard_json = r#"
+{
+    "layouts": {
+        "LAYOUT_split_3x5_3": { "layout": [] },
+        "LAYOUT_ortho_4x12": { "layout": [] },
+        "LAYOUT_ortho_5x12": { "layout": [] }
+    },
+    "layout_aliases": {
+        "LAYOUT": "LAYOUT_split_3x5_3"
+    }
+}
+        "#;
+        let spec: KeyboardSpec = serde_json::from_str(keyboard_json)?;
+
+        assert!(spec.get_layout("LAYOUT").is_some());
+        assert!(spec.get_layout("layout_split_3x5_3").is_some());
+        assert!(spec.get_layout("LAYOUT_split_3x5_2").is_some());
+        assert!(spec.get_layout("LAYOUT_split").is_some());
+        // Ambiguous
+        assert!(spec.get_layout("LAYOUT_ortho").is_none());
+        assert!(spec.get_layout("LAYOUT_other").is_none());
+
+        assert_eq!(
+            spec.layout_names(),
+            vec![
+                "LAYOUT",
+                "LAYOUT_ortho_4x12",
+                "LAYOUT_ortho_5x12",
+                "LAYOUT_split_3x5_3"
+            ]
+        );
+        assert_eq!(normalize_layout_name("LAYOUT_split_3x5_3"), "layout_split");
+        assert_eq!(normalize_layout_name("LAYOUT"), "layout");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_matrix_pos() -> Result<()> {
+        let keyboard_json = r#"
+{
+    "layouts": {
+        "LAYOUT": {
+            "layout": [
+                { "matrix": [0, 0], "x": 0, "y": 0 },
+                { "matrix": [0, 1], "x": 1, "y": 0 },
+                { "matrix": [0, 0], "x": 2, "y": 0 }
+            ]
+        }
+    }
+}
+        "#;
+        let spec: KeyboardSpec = serde_json::from_str(keyboard_json)?;
+        let err = spec.get_layout("LAYOUT").unwrap().validate("LAYOUT");
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Matrix position (0, 0) is used by both key 0 and 2 in layout LAYOUT"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_encoders() -> Result<()> {
+        let keymap_c = r#"
+const uint16_t PROGMEM encoder_map[][NUM_ENCODERS][NUM_DIRECTIONS] = {
+    [_BASE] = { ENCODER_CCW_CW(KC_VOLD, KC_VOLU), ENCODER_CCW_CW(C(KC_Z), C(KC_Y)) },
+    [_NUM]  = { ENCODER_CCW_CW(_______, _______), ENCODER_CCW_CW(KC_PGUP, KC_PGDN) },
+};
+        "#;
+        let encoders = parse_encoders_from_source(keymap_c)?;
+
+        assert_eq!(encoders.len(), 2);
+        assert_eq!(encoders[0].layer_id.0, "_BASE");
+        assert_eq!(
+            encoders[0].encoders,
+            vec![
+                Encoder {
+                    ccw: KeyId("KC_VOLD".into()),
+                    cw: KeyId("KC_VOLU".into()),
+                },
+                Encoder {
+                    ccw: KeyId("C(KC_Z)".into()),
+                    cw: KeyId("C(KC_Y)".into()),
+                },
+            ]
+        );
+        assert_eq!(encoders[1].layer_id.0, "_NUM");
+        assert_eq!(encoders[1].encoders[1].cw.0, "KC_PGDN");
+
+        assert!(parse_encoders_from_source("")?.is_empty());
+
+        Ok(())
+    }
 }
@@ -1,9 +1,22 @@
 #![allow(dead_code)]
 
+mod error;
 mod input_info;
 mod keymap;
 mod render_opts;
 
+pub use error::ParseError;
 pub use input_info::InputInfo;
-pub use keymap::{Combo, Key, KeyId, Keymap, Layer, LayerId};
-pub use render_opts::{Finger, FingerAssignment, MatrixHalf, PhysicalPos, RenderOpts};
+pub use keymap::{Combo, ComboKind, EncoderDef, Key, KeyId, Keymap, Layer, LayerId, ParseSettings};
+pub use render_opts::{
+    display_width, lighten_color, Finger, FingerAssignment, KeyOpts, KeycapStyle, MatrixHalf,
+    PhysicalPos, RenderOpts, ScoreWeights,
+};
+
+/// Strips a leading UTF-8 BOM and converts CRLF line endings, as files saved on
+/// Windows often have them.
+fn normalize_source(src: &str) -> String {
+    src.strip_prefix('\u{feff}')
+        .unwrap_or(src)
+        .replace("\r\n", "\n")
+}
@@ -1,13 +1,20 @@
-use camino::Utf8Path;
-use eyre::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::{eyre, Result};
+use palette::{FromColor, Hsv, IntoColor, Srgb};
 use regex::Regex;
 use serde::Deserialize;
+use serde_json::Value;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs;
+use std::str::FromStr;
 use std::sync::LazyLock;
 
+use super::normalize_source;
+use super::ParseError;
+
 #[derive(Debug, Clone)]
 pub struct RenderOpts {
     pub id: String,
@@ -15,6 +22,33 @@ pub struct RenderOpts {
     pub layer_keys: HashMap<String, HashMap<String, PartialKeyOpts>>,
     pub legend: Vec<LegendSpec>,
     pub colors: HashMap<String, String>,
+    // Used for classes without a color
+    pub default_color: String,
+    pub finger_colors: HashMap<String, String>,
+    pub encoder_positions: Vec<(f32, f32)>,
+    pub combo_macros: Vec<ComboMacro>,
+    pub layer_titles: HashMap<String, String>,
+    // Output file names keyed by layer id or output name, see `output_filename`
+    pub layer_filenames: HashMap<String, String>,
+    pub theme: Theme,
+    pub keycap_style: KeycapStyle,
+    // Extra space between the hands in key units, added to the x of right hand keys
+    // in the images only
+    pub hand_gap: f32,
+    // Round the rects of keys to whole pixels, for crisp edges when rasterized at 1x
+    pub snap_pixels: bool,
+    // Fill keys with a top to bottom gradient of their color, defined once per image
+    pub gradient_keys: bool,
+    // Fill of a rect behind the keys, such as `#ffffff` or `none`
+    pub background: Option<String>,
+    pub score_weights: ScoreWeights,
+    // Use `#define` aliases in the keymap to find titles for keys
+    pub resolve_defines: bool,
+    // Name -> value of the `#define`s in the keymap, if resolve_defines is set
+    pub defines: HashMap<String, String>,
+    // Names from the parse settings, to tell the images of different keymaps apart
+    pub keyboard: String,
+    pub keymap: String,
     pub physical_layout: PhysicalLayout,
     pub outputs: RenderOutputs,
 }
@@ -22,18 +56,60 @@ pub struct RenderOpts {
 impl RenderOpts {
     pub fn parse(file: &Utf8Path) -> Result<Self> {
         let src = fs::read_to_string(file)?;
-        Self::parse_from_str(file.file_stem().unwrap(), &src)
+        let value = parse_source(&src, file.parent().unwrap_or(Utf8Path::new("")))?;
+        Self::new(file.file_stem().unwrap(), serde_json::from_value(value)?)
     }
 
+    /// Layout files are relative to the current directory.
     pub fn parse_from_str(id: &str, s: &str) -> Result<Self> {
-        let spec: RenderSpec = serde_json::from_str(s)?;
-        Ok(Self::new(id, spec))
+        let value = parse_source(s, Utf8Path::new(""))?;
+        Self::new(id, serde_json::from_value(value)?)
+    }
+
+    /// Parses several files where later files override earlier ones, see `merge_json`.
+    /// The id is taken from the last file.
+    pub fn parse_merged(files: &[Utf8PathBuf]) -> Result<Self> {
+        let last = files.last().ok_or(ParseError::NoRenderOpts)?;
+        // Layout files are read before merging, as they're relative to the file they're in
+        let values = files
+            .iter()
+            .map(|file| {
+                let src = fs::read_to_string(file)?;
+                parse_source(&src, file.parent().unwrap_or(Utf8Path::new("")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::merge_values(last.file_stem().unwrap(), values)
+    }
+
+    pub fn merge_from_strs(id: &str, sources: &[&str]) -> Result<Self> {
+        let values = sources
+            .iter()
+            .map(|src| parse_source(src, Utf8Path::new("")))
+            .collect::<Result<Vec<_>>>()?;
+        Self::merge_values(id, values)
     }
 
-    fn new(id: &str, spec: RenderSpec) -> Self {
+    fn merge_values(id: &str, values: Vec<Value>) -> Result<Self> {
+        let mut merged = Value::Null;
+        for value in values {
+            merge_json(&mut merged, value, false);
+        }
+        let spec: RenderSpec = serde_json::from_value(merged)?;
+        Self::new(id, spec)
+    }
+
+    fn new(id: &str, spec: RenderSpec) -> Result<Self> {
         let mut default_keys = HashMap::new();
         let mut layer_keys: HashMap<String, HashMap<String, PartialKeyOpts>> = HashMap::new();
 
+        let mut colors = spec.colors;
+        for rule in &spec.palette {
+            // Explicit colors always win
+            for (class, color) in rule.shades()? {
+                colors.entry(class).or_insert(color);
+            }
+        }
+
         for (layer_id, layer) in spec.layers {
             for key_spec in &layer {
                 for key in &key_spec.keys {
@@ -51,19 +127,66 @@ impl RenderOpts {
             }
         }
 
-        Self {
+        Ok(Self {
             id: id.into(),
             default_keys,
             layer_keys,
             legend: spec.legend,
-            colors: spec.colors,
-            physical_layout: PhysicalLayout::new(spec.physical_layout, spec.finger_assignments),
+            colors,
+            default_color: spec.default_color,
+            finger_colors: spec.finger_colors,
+            encoder_positions: spec.encoder_positions,
+            combo_macros: spec.combo_macros,
+            layer_titles: spec.layer_titles,
+            layer_filenames: spec.layer_filenames,
+            theme: spec.theme,
+            keycap_style: spec.keycap_style,
+            hand_gap: spec.hand_gap,
+            snap_pixels: spec.snap_pixels,
+            gradient_keys: spec.gradient_keys,
+            background: spec.background,
+            score_weights: spec.score_weights,
+            resolve_defines: spec.resolve_defines,
+            defines: HashMap::new(),
+            keyboard: String::new(),
+            keymap: String::new(),
+            physical_layout: PhysicalLayout::new(
+                spec.physical_layout,
+                spec.finger_assignments,
+                spec.split,
+            )?,
             outputs: spec.outputs,
-        }
+        })
+    }
+
+    /// The color of a class, or the default color if the class has none.
+    pub fn class_color(&self, class: &str) -> &str {
+        self.colors.get(class).unwrap_or(&self.default_color)
+    }
+
+    /// The file name of a layer, such as `_BASE`, or of another output, such as
+    /// `legend` or `neighbour_combos`. Defaults to the name with an `.svg` extension.
+    pub fn output_filename(&self, name: &str) -> String {
+        self.layer_filenames
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| format!("{name}.svg"))
+    }
+
+    /// The inline color of a legend entry, or the color of its class.
+    pub fn legend_color<'a>(&'a self, item: &'a LegendSpec) -> &'a str {
+        item.color
+            .as_deref()
+            .unwrap_or_else(|| self.class_color(&item.class))
     }
 
     pub fn get(&self, layer_id: &str, key_id: &str) -> KeyOpts {
         let mut res = KeyOpts::with_defaults(key_id);
+        if self.resolve_defines && self.defines.contains_key(key_id) {
+            if let Some(title) = self.define_title(key_id) {
+                res.title = title;
+            }
+        }
 
         if let Some(opts) = self.default_keys.get(key_id) {
             res.merge(opts);
@@ -76,6 +199,145 @@ impl RenderOpts {
         }
         res
     }
+
+    /// The title of the keycode a `#define` alias resolves to,
+    /// for keys that don't have a title of their own.
+    fn define_title(&self, key_id: &str) -> Option<String> {
+        if key_id_to_title(key_id) != key_id {
+            return None;
+        }
+
+        let mut id = key_id;
+        // Limited in case the defines are cyclic
+        for _ in 0..8 {
+            let next = tap_keycode(self.defines.get(id)?);
+            let title = key_id_to_title(next);
+            if title != next {
+                return Some(title);
+            }
+            id = next;
+        }
+        None
+    }
+}
+
+fn parse_source(src: &str, dir: &Utf8Path) -> Result<Value> {
+    let mut value = serde_json::from_str(&strip_json_comments(&normalize_source(src)))?;
+    read_layout_files(&mut value, dir)?;
+    Ok(value)
+}
+
+// Replaces `//` line comments and `/* */` block comments outside of strings with
+// spaces, keeping the newlines so errors point to the right line.
+fn strip_json_comments(src: &str) -> String {
+    let mut res = String::with_capacity(src.len());
+    let mut chars = src.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            res.push(c);
+            match c {
+                '\\' => res.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                res.push(c);
+            }
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    res.push(if c == '\n' { '\n' } else { ' ' });
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// Replaces `physical_layout_file` and `finger_assignments_file` with the rows
+// of the file they point to, relative to `dir`.
+fn read_layout_files(value: &mut Value, dir: &Utf8Path) -> Result<()> {
+    let Value::Object(obj) = value else {
+        return Ok(());
+    };
+    for key in ["physical_layout", "finger_assignments"] {
+        let file_key = format!("{key}_file");
+        let Some(file) = obj.remove(&file_key) else {
+            continue;
+        };
+        if obj.contains_key(key) {
+            return Err(ParseError::LayoutFileConflict {
+                key: key.to_string(),
+                file_key,
+            }
+            .into());
+        }
+        let file = file.as_str().ok_or_else(|| ParseError::LayoutFileNotPath {
+            file_key: file_key.clone(),
+        })?;
+        let path = dir.join(file);
+        let src = fs::read_to_string(&path).map_err(|source| ParseError::ReadLayoutFile {
+            path: path.clone(),
+            source,
+        })?;
+        let mut rows: Vec<_> = normalize_source(&src).lines().map(String::from).collect();
+        while rows.last().is_some_and(|row| row.trim().is_empty()) {
+            rows.pop();
+        }
+        obj.insert(key.into(), rows.into());
+    }
+    Ok(())
+}
+
+// Objects are merged key by key and other values, including lists, are replaced.
+// The exception is the key lists in `layers`, which are concatenated so a later
+// file can add keys and override the options of earlier keys.
+fn merge_json(base: &mut Value, overlay: Value, concat_lists: bool) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let concat = concat_lists || key == "layers";
+                match base.get_mut(&key) {
+                    Some(x) => merge_json(x, value, concat),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) if concat_lists => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// The keycode sent on tap, such as `KC_SPC` for `LT(_SYM, KC_SPC)`.
+// Modifier wrappers such as `LSFT(SE_DOT)` are kept as they change the key.
+fn tap_keycode(value: &str) -> &str {
+    let value = value.trim();
+    match value.split_once('(') {
+        Some((name, args)) if name == "LT" || name == "MT" || name.ends_with("_T") => args
+            .strip_suffix(')')
+            .and_then(|args| args.rsplit(',').next())
+            .unwrap_or(value)
+            .trim(),
+        _ => value,
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -100,6 +362,39 @@ impl Ord for FingerAssignment {
     }
 }
 
+impl FingerAssignment {
+    /// All fingers, left hand first.
+    pub fn all() -> impl Iterator<Item = FingerAssignment> {
+        [MatrixHalf::Left, MatrixHalf::Right]
+            .into_iter()
+            .flat_map(|half| {
+                Finger::ALL
+                    .into_iter()
+                    .map(move |finger| Self { finger, half })
+            })
+    }
+}
+
+impl std::fmt::Display for FingerAssignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}-{}", self.half, self.finger)
+    }
+}
+
+impl FromStr for FingerAssignment {
+    type Err = ParseError;
+
+    // Parses the `Display` output, such as `left-pinky` or `right-index`.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        Self::all()
+            .find(|x| x.to_string() == s)
+            .ok_or_else(|| ParseError::UnknownFinger {
+                finger: s.to_string(),
+                accepted: Self::all().map(|x| x.to_string()).collect(),
+            })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Finger {
     Pinky,
@@ -110,21 +405,31 @@ pub enum Finger {
 }
 
 impl Finger {
-    pub fn from_char(c: char) -> Self {
+    pub const ALL: [Finger; 5] = [
+        Finger::Pinky,
+        Finger::Ring,
+        Finger::Middle,
+        Finger::Index,
+        Finger::Thumb,
+    ];
+
+    pub fn from_char(c: char) -> Result<Self> {
         let value = c
             .to_digit(10)
-            .expect("Physical layout should contain digits");
+            .ok_or_else(|| eyre!("Finger `{c}` isn't a digit"))?;
         Self::from_u32(value)
     }
 
-    pub fn from_u32(x: u32) -> Self {
+    pub fn from_u32(x: u32) -> Result<Self> {
         match x {
-            0 => Finger::Pinky,
-            1 => Finger::Ring,
-            2 => Finger::Middle,
-            3 => Finger::Index,
-            4 => Finger::Thumb,
-            _ => panic!("Finger value {} unknown", x),
+            0 => Ok(Finger::Pinky),
+            1 => Ok(Finger::Ring),
+            2 => Ok(Finger::Middle),
+            3 => Ok(Finger::Index),
+            4 => Ok(Finger::Thumb),
+            _ => Err(eyre!(
+                "Finger `{x}` unknown, expected 0 (pinky) to 4 (thumb)"
+            )),
         }
     }
 }
@@ -142,21 +447,43 @@ impl std::fmt::Display for Finger {
     }
 }
 
+impl FromStr for Finger {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        Self::ALL
+            .into_iter()
+            .find(|finger| finger.to_string() == s)
+            .ok_or_else(|| ParseError::UnknownFinger {
+                finger: s.to_string(),
+                accepted: Self::ALL.iter().map(|x| x.to_string()).collect(),
+            })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyOpts {
     pub id: String,
     pub title: String,
     pub hold_title: Option<String>,
+    pub shifted_title: Option<String>,
     pub class: String,
+    pub color: Option<String>,
 }
 
 impl KeyOpts {
     fn with_defaults(key_id: &str) -> Self {
+        let (title, hold_title) = match tap_hold(key_id) {
+            Some((tap, hold)) => (key_id_to_title(tap), Some(hold)),
+            None => (key_id_to_title(key_id), None),
+        };
         Self {
             id: key_id.to_string(),
-            title: key_id_to_title(key_id),
-            hold_title: None,
+            title,
+            hold_title,
+            shifted_title: None,
             class: "default".to_string(),
+            color: None,
         }
     }
 
@@ -168,14 +495,81 @@ impl KeyOpts {
         if let Some(ref hold_title) = opts.hold_title {
             self.hold_title = Some(hold_title.to_owned());
         }
+        if let Some(ref shifted_title) = opts.shifted_title {
+            self.shifted_title = Some(shifted_title.to_owned());
+        }
         if let Some(ref class) = opts.class {
             self.class = class.to_owned();
         }
+        if let Some(ref color) = opts.color {
+            self.color = Some(color.to_owned());
+        }
         self
     }
 }
 
+// The tap keycode and the title of the hold action of a layer-tap or mod-tap,
+// such as `LT(_SYM, KC_SPC)`, `MT(MOD_LCTL, KC_A)` or `LCTL_T(KC_A)`.
+fn tap_hold(id: &str) -> Option<(&str, String)> {
+    static LAYER_TAP: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^LT\(\s*_?(\w+)\s*,\s*(\w+)\s*\)$").unwrap());
+    static MOD_TAP: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^(?:MT\(\s*(?:MOD_)?(\w+)\s*,|(\w+)_T\()\s*(\w+)\s*\)$").unwrap()
+    });
+
+    if let Some(caps) = LAYER_TAP.captures(id) {
+        let layer = caps.get(1).unwrap().as_str();
+        return Some((caps.get(2).unwrap().as_str(), layer.to_string()));
+    }
+    let caps = MOD_TAP.captures(id)?;
+    let modifier = caps.get(1).or(caps.get(2)).unwrap().as_str();
+    let title = match modifier.strip_prefix(['L', 'R']).unwrap_or(modifier) {
+        "CTL" | "CTRL" => "Ctrl",
+        "SFT" | "SHIFT" => "Shift",
+        "ALT" | "OPT" => "Alt",
+        "GUI" | "CMD" | "WIN" => "Gui",
+        _ => modifier,
+    };
+    Some((caps.get(3).unwrap().as_str(), title.to_string()))
+}
+
+/// Width of a title in latin characters. Arrows and symbols such as `⌃` are
+/// usually drawn wider than a letter, and emoji and CJK glyphs twice as wide.
+pub fn display_width(title: &str) -> f32 {
+    title
+        .chars()
+        .map(|c| match c as u32 {
+            0..=0x7f => 1.0,
+            0x1100..=0x115f
+            | 0x2e80..=0xa4cf
+            | 0xac00..=0xd7a3
+            | 0xf900..=0xfaff
+            | 0xff00..=0xff60
+            | 0x1f300..=0x1faff => 2.0,
+            0x2190..=0x21ff | 0x2300..=0x23ff | 0x25a0..=0x27bf => 1.5,
+            _ => 1.2,
+        })
+        .sum()
+}
+
 fn key_id_to_title(id: &str) -> String {
+    // A macro call such as `SS_TAP(`, but not a keycode such as `KC_SS_X`
+    static SS_CALL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\bSS_[A-Z]+\(").unwrap());
+    if id.starts_with('"') || SS_CALL.is_match(id) {
+        return send_string_title(id);
+    }
+
+    // QMK Unicode keycodes, such as `UC(0x00E5)`
+    static UNICODE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^UC\(\s*0x([[:xdigit:]]+)\s*\)$").unwrap());
+    if let Some(c) = UNICODE
+        .captures(id)
+        .and_then(|caps| u32::from_str_radix(&caps[1], 16).ok())
+        .and_then(char::from_u32)
+    {
+        return c.to_string();
+    }
+
     static BASIC: LazyLock<Regex> =
         LazyLock::new(|| Regex::new(r"^(SE|KC)_([\w\d]|\d+|F\d+)$").unwrap());
     if let Some(basic) = BASIC.captures(id) {
@@ -226,6 +620,7 @@ fn key_id_to_title(id: &str) -> String {
         "KC_DOWN" => "↓",
         "KC_LEFT" => "←",
         "KC_RGHT" => "→",
+        "KC_RIGHT" => "→",
         "KC_HOME" => "Home",
         "KC_END" => "End",
         "KC_ESC" => "Esc",
@@ -242,12 +637,92 @@ fn key_id_to_title(id: &str) -> String {
     res.to_string()
 }
 
+/// Converts a QMK send string, such as `"#{}"SS_TAP(X_LEFT)`, into a readable title.
+fn send_string_title(s: &str) -> String {
+    static SS_MACRO: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^SS_(\w+)\(").unwrap());
+
+    let mut res = String::new();
+    let mut rest = s.trim();
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            let (literal, tail) = split_string_literal(&rest[1..]);
+            res.push_str(&literal);
+            rest = tail;
+        } else if let Some(caps) = SS_MACRO.captures(rest) {
+            let (args, tail) = split_macro_args(&rest[caps[0].len()..]);
+            match &caps[1] {
+                "TAP" => {
+                    let key = args.trim().trim_start_matches("X_");
+                    res.push_str(&key_id_to_title(&format!("KC_{key}")));
+                }
+                "DOWN" | "UP" | "DELAY" => {}
+                "LSFT" | "RSFT" => res.push_str(&format!("⇧{}", send_string_title(args))),
+                "LCTL" | "RCTL" => res.push_str(&format!("⌃{}", send_string_title(args))),
+                "LALT" | "RALT" => res.push_str(&format!("⌥{}", send_string_title(args))),
+                "LGUI" | "RGUI" => res.push_str(&format!("⌘{}", send_string_title(args))),
+                _ => res.push_str(&send_string_title(args)),
+            }
+            rest = tail;
+        } else {
+            // Not a send string, keep it as is
+            res.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    res
+}
+
+// Splits a string literal, without the opening quote, at the closing quote
+// and returns the unescaped literal with the remaining input.
+fn split_string_literal(s: &str) -> (String, &str) {
+    let mut res = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (res, &s[i + 1..]),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => res.push('⏎'),
+                Some((_, 't')) => res.push('⇥'),
+                Some((_, x)) => res.push(x),
+                None => {}
+            },
+            _ => res.push(c),
+        }
+    }
+    (res, "")
+}
+
+// Splits the arguments of a macro, after the opening paren, at the matching
+// closing paren and returns the arguments with the remaining input.
+fn split_macro_args(s: &str) -> (&str, &str) {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut prev = None;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' if prev != Some('\\') => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                if depth == 0 {
+                    return (&s[..i], &s[i + 1..]);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    (s, "")
+}
+
 #[derive(Debug, Clone)]
 pub struct PartialKeyOpts {
     pub id: String,
     pub title: Option<String>,
     pub hold_title: Option<String>,
+    pub shifted_title: Option<String>,
     pub class: Option<String>,
+    pub color: Option<String>,
 }
 
 impl PartialKeyOpts {
@@ -256,7 +731,9 @@ impl PartialKeyOpts {
             id: key_id.to_string(),
             title: spec.title.clone(),
             hold_title: spec.hold_title.clone(),
+            shifted_title: spec.shifted_title.clone(),
             class: spec.class.clone(),
+            color: spec.color.clone(),
         }
     }
 }
@@ -266,8 +743,48 @@ struct RenderSpec {
     layers: LayersSpec,
     legend: Vec<LegendSpec>,
     colors: HashMap<String, String>,
+    // Generates colors for classes missing from `colors`
+    #[serde(default)]
+    palette: Vec<PaletteRule>,
+    #[serde(default = "default_color")]
+    default_color: String,
+    // Keyed by `left-pinky`, `right-thumb` etc
+    #[serde(default)]
+    finger_colors: HashMap<String, String>,
+    // Where to annotate encoder actions, as (x, y) in key units
+    #[serde(default)]
+    encoder_positions: Vec<(f32, f32)>,
+    // Macros in combos.def that define a combo
+    #[serde(default = "default_combo_macros")]
+    combo_macros: Vec<ComboMacro>,
+    // Human readable captions for layers, keyed by layer id
+    #[serde(default)]
+    layer_titles: HashMap<String, String>,
+    // Such as `{ "_BASE": "base.svg", "legend": "colors.svg" }`
+    #[serde(default)]
+    layer_filenames: HashMap<String, String>,
+    #[serde(default)]
+    theme: Theme,
+    #[serde(default)]
+    keycap_style: KeycapStyle,
+    #[serde(default)]
+    hand_gap: f32,
+    #[serde(default)]
+    snap_pixels: bool,
+    #[serde(default)]
+    gradient_keys: bool,
+    #[serde(default)]
+    background: Option<String>,
+    #[serde(default)]
+    score_weights: ScoreWeights,
+    #[serde(default)]
+    resolve_defines: bool,
+    // Either can be read from a text file instead, see `read_layout_files`
     physical_layout: PhysicalLayoutSpec,
     finger_assignments: PhysicalLayoutSpec,
+    // How the rows of physical_layout and finger_assignments are split into hands
+    #[serde(default)]
+    split: SplitMode,
     outputs: RenderOutputs,
 }
 
@@ -279,13 +796,222 @@ struct KeySpec {
     keys: Vec<String>,
     title: Option<String>,
     hold_title: Option<String>,
+    // Small symbol in the top-right corner, such as the shifted glyph
+    shifted_title: Option<String>,
     class: Option<String>,
+    // Overrides the color of the class
+    color: Option<String>,
+}
+
+/// Shades of a base color for related classes, such as `nav` and `nav_alt`.
+/// The first class gets the base color and each following class is a step
+/// further from it, darker for light colors and lighter for dark ones.
+#[derive(Deserialize, Debug)]
+struct PaletteRule {
+    color: String,
+    classes: Vec<String>,
+}
+
+// Difference in HSV value between the shades of a palette rule
+const PALETTE_STEP: f32 = 0.12;
+
+impl PaletteRule {
+    fn shades(&self) -> Result<Vec<(String, String)>> {
+        let base: Srgb = Srgb::<u8>::from_str(&self.color)
+            .map_err(|err| ParseError::InvalidPaletteColor {
+                color: self.color.clone(),
+                reason: err.to_string(),
+            })?
+            .into();
+        let value = Hsv::from_color(base).value;
+        let step = if value > 0.5 {
+            -PALETTE_STEP
+        } else {
+            PALETTE_STEP
+        };
+
+        Ok(self
+            .classes
+            .iter()
+            .enumerate()
+            .map(|(i, class)| {
+                let color = lighten_color(base, step * i as f32);
+                (class.clone(), format!("#{:x}", Srgb::<u8>::from(color)))
+            })
+            .collect())
+    }
+}
+
+/// Shifts the HSV value of a color, negative amounts darken it.
+pub fn lighten_color(rgb: Srgb, amount: f32) -> Srgb {
+    let hsv: Hsv = rgb.into_color();
+    let value = (hsv.value + amount).clamp(0.0, 1.0);
+    Hsv::new(hsv.hue, hsv.saturation, value).into_color()
+}
+
+fn default_color() -> String {
+    "#e5c494".into()
+}
+
+fn default_combo_macros() -> Vec<ComboMacro> {
+    vec![
+        ComboMacro {
+            name: "COMB".into(),
+            strip_quotes: false,
+            ordered: false,
+        },
+        ComboMacro {
+            name: "SUBS".into(),
+            strip_quotes: true,
+            ordered: false,
+        },
+    ]
+}
+
+/// A macro used to define combos in combos.def, such as `COMB` or `SUBS`.
+/// Can be given as only the name or as `{ "name": "SUBS", "strip_quotes": true }`.
+/// A macro for sequence combos is given as `{ "name": "SEQ", "ordered": true }`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "ComboMacroSpec")]
+pub struct ComboMacro {
+    pub name: String,
+    // Remove surrounding quotes from the output, as `SUBS` outputs a string
+    pub strip_quotes: bool,
+    // The keys must be pressed in the order they're listed
+    pub ordered: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ComboMacroSpec {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        strip_quotes: bool,
+        #[serde(default)]
+        ordered: bool,
+    },
+}
+
+impl From<ComboMacroSpec> for ComboMacro {
+    fn from(spec: ComboMacroSpec) -> Self {
+        match spec {
+            ComboMacroSpec::Name(name) => ComboMacro {
+                name,
+                strip_quotes: false,
+                ordered: false,
+            },
+            ComboMacroSpec::Full {
+                name,
+                strip_quotes,
+                ordered,
+            } => ComboMacro {
+                name,
+                strip_quotes,
+                ordered,
+            },
+        }
+    }
+}
+
+/// How much each part of the layout score contributes, see the `score` command.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ScoreWeights {
+    pub sfb: f32,
+    pub pinky: f32,
+    pub imbalance: f32,
+    pub effort: f32,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            sfb: 1.0,
+            pinky: 0.5,
+            imbalance: 0.25,
+            effort: 1.0,
+        }
+    }
+}
+
+/// The corner radius and border widths of the keycaps, in px.
+/// The border is the part of the outer rect that's outside the inner rect.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct KeycapStyle {
+    pub rx: f32,
+    pub border_left: f32,
+    pub border_right: f32,
+    pub border_top: f32,
+    pub border_bottom: f32,
+    // The smaller keys of combo outputs
+    pub combo_rx: f32,
+    pub combo_border_x: f32,
+    pub combo_border_top: f32,
+    pub combo_border_bottom: f32,
+}
+
+impl Default for KeycapStyle {
+    fn default() -> Self {
+        Self {
+            rx: 5.0,
+            border_left: 6.0,
+            border_right: 6.0,
+            border_top: 4.0,
+            border_bottom: 8.0,
+            combo_rx: 4.0,
+            combo_border_x: 1.5,
+            combo_border_top: 1.0,
+            combo_border_bottom: 2.5,
+        }
+    }
+}
+
+/// Extra CSS for the rendered SVGs, on top of the default style.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Theme {
+    #[serde(default)]
+    pub preset: Option<ThemePreset>,
+    // Added after the preset, so it can override it
+    #[serde(default)]
+    pub css: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    Dark,
+}
+
+impl Theme {
+    /// The CSS rules to add to the `<style>` element, empty if there's no theme.
+    pub fn css(&self) -> String {
+        let mut res = String::new();
+        if let Some(ThemePreset::Dark) = self.preset {
+            res.push_str(
+                r#"    .keycap .border, .legend .border { stroke: #c8c8c8; }
+    .keycap .inner.border, .legend .inner.border { stroke: rgba(255,255,255,.1); }
+    .caption, .encoder { fill: #e8e8e8; }
+"#,
+            );
+        }
+        if let Some(css) = &self.css {
+            for line in css.lines() {
+                res.push_str(&format!("    {}\n", line.trim()));
+            }
+        }
+        res
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct LegendSpec {
     pub class: String,
     pub title: String,
+    // Used instead of the class color, for entries that aren't a key class
+    pub color: Option<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, PartialOrd, Ord, Hash)]
@@ -294,18 +1020,59 @@ pub enum MatrixHalf {
     Right,
 }
 
+impl std::fmt::Display for MatrixHalf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            MatrixHalf::Left => "left",
+            MatrixHalf::Right => "right",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct RenderOutputs {
     #[serde(default)]
     pub effort: bool,
+    #[serde(default)]
+    pub finger_zones: bool,
+    // Caption each layer image with its title
+    #[serde(default)]
+    pub layer_captions: bool,
+    // Caption each image with the keyboard and keymap name
+    #[serde(default)]
+    pub keymap_caption: bool,
+    // Add a hover tooltip with the key id, finger and effort to each layer key
+    #[serde(default)]
+    pub key_tooltips: bool,
     #[serde(default = "default_true")]
     pub layers: bool,
+    // Render all layers at the size of the largest layer, with the keys centered
+    #[serde(default)]
+    pub fixed_layer_size: bool,
     #[serde(default = "default_true")]
     pub legend: bool,
+    // Add the classes used by the keymap that aren't in the legend
+    #[serde(default)]
+    pub legend_unlisted_classes: bool,
     #[serde(default = "default_true")]
     pub combos: bool,
+    // All combos as cards in a single image
+    #[serde(default)]
+    pub combos_overview: bool,
+    // Place two key combos between keys that are adjacent on the rendered board,
+    // instead of in the matrix. Useful for column staggered boards.
+    #[serde(default)]
+    pub physical_neighbours: bool,
+    // Connect the keys of each combo to its output with a line, in the images of
+    // highlight groups and single combos
+    #[serde(default)]
+    pub combo_lines: bool,
     pub combo_keys_with_separate_imgs: HashSet<String>,
     pub combo_highlight_groups: HashMap<String, HashSet<String>>,
+    // Combo or highlight group id -> layer to draw it over, the base layer by default
+    #[serde(default)]
+    pub combo_layers: HashMap<String, String>,
     pub combo_background_layer_class: String,
     pub active_class_in_separate_layer: String,
 }
@@ -317,6 +1084,25 @@ fn default_true() -> bool {
 #[derive(Deserialize, Debug, Clone)]
 struct PhysicalLayoutSpec(Vec<String>);
 
+/// Sections of a physical layout row are separated by four spaces.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitMode {
+    /// A left and a right half.
+    #[default]
+    Halves,
+    /// A left, a center and a right section.
+    /// Keys in the center section belong to the closest hand.
+    Center,
+    /// Not split, keys belong to the closest hand.
+    None,
+    /// A one-handed board, all keys belong to the left hand.
+    /// Sections are only gaps between the keys.
+    Left,
+    /// A one-handed board, all keys belong to the right hand.
+    Right,
+}
+
 #[derive(Clone, Debug)]
 pub struct PhysicalLayout {
     index_to_pos: Vec<PhysicalPos>,
@@ -324,22 +1110,33 @@ pub struct PhysicalLayout {
 }
 
 impl PhysicalLayout {
-    pub fn new(effort_layout: PhysicalLayoutSpec, finger_layout: PhysicalLayoutSpec) -> Self {
-        assert_eq!(effort_layout.0.len(), finger_layout.0.len());
+    pub fn new(
+        effort_layout: PhysicalLayoutSpec,
+        finger_layout: PhysicalLayoutSpec,
+        split: SplitMode,
+    ) -> Result<Self> {
+        if effort_layout.0.len() != finger_layout.0.len() {
+            return Err(ParseError::LayoutRowCount {
+                effort_rows: effort_layout.0.len(),
+                finger_rows: finger_layout.0.len(),
+            }
+            .into());
+        }
 
-        let mut index_to_pos = Vec::new();
+        // Keys without a given half are assigned by column after all rows are parsed.
+        let mut keys: Vec<(usize, usize, Finger, u32, Option<MatrixHalf>)> = Vec::new();
 
-        for (row, (effort_line, finger_line)) in effort_layout
+        for (row, (effort_row, finger_row)) in effort_layout
             .0
             .into_iter()
             .zip(finger_layout.0.into_iter())
             .enumerate()
         {
             let mut col = 0;
-            for (split_i, (effort_line, finger_line)) in effort_line
+            for (split_i, (effort_line, finger_line)) in effort_row
                 .trim_end()
                 .split("    ")
-                .zip(finger_line.trim_end().split("    "))
+                .zip(finger_row.trim_end().split("    "))
                 .enumerate()
             {
                 for (effort, finger) in effort_line.chars().zip(finger_line.chars()) {
@@ -348,37 +1145,103 @@ impl PhysicalLayout {
                         continue;
                     }
 
-                    let half = match split_i {
-                        0 => MatrixHalf::Left,
-                        1 => MatrixHalf::Right,
-                        _ => panic!("More splits found in layout than 2"),
+                    let half = match (split, split_i) {
+                        (SplitMode::Halves | SplitMode::Center, 0) => Some(MatrixHalf::Left),
+                        (SplitMode::Halves, 1) | (SplitMode::Center, 2) => Some(MatrixHalf::Right),
+                        (SplitMode::Center, 1) | (SplitMode::None, _) => None,
+                        (SplitMode::Left, _) => Some(MatrixHalf::Left),
+                        (SplitMode::Right, _) => Some(MatrixHalf::Right),
+                        _ => {
+                            return Err(ParseError::TooManySplits {
+                                split,
+                                row: row + 1,
+                                line: effort_row.clone(),
+                            }
+                            .into())
+                        }
                     };
 
-                    let finger = Finger::from_char(finger);
+                    let finger = Finger::from_char(finger).map_err(|err| {
+                        ParseError::InvalidFingerAssignment {
+                            reason: err.to_string(),
+                            row: row + 1,
+                            line: finger_row.clone(),
+                        }
+                    })?;
+                    let effort = effort
+                        .to_digit(10)
+                        .ok_or_else(|| ParseError::InvalidEffort {
+                            effort,
+                            row: row + 1,
+                            line: effort_row.clone(),
+                        })?;
 
-                    index_to_pos.push(PhysicalPos {
-                        col,
-                        row,
-                        finger: FingerAssignment { finger, half },
-                        effort: effort
-                            .to_digit(10)
-                            .expect("Physical layout should contain digits"),
-                    });
+                    keys.push((col, row, finger, effort, half));
                     col += 1;
                 }
             }
         }
 
+        let width = keys.iter().map(|x| x.0 + 1).max().unwrap_or(0);
+        let index_to_pos: Vec<_> = keys
+            .into_iter()
+            .map(|(col, row, finger, effort, half)| {
+                // A key in the exact middle column counts as the left hand
+                let half = half.unwrap_or(if col * 2 < width {
+                    MatrixHalf::Left
+                } else {
+                    MatrixHalf::Right
+                });
+                PhysicalPos {
+                    col,
+                    row,
+                    finger: FingerAssignment { finger, half },
+                    effort,
+                }
+            })
+            .collect();
+
         let pos_to_index = index_to_pos
             .iter()
             .enumerate()
             .map(|x: (usize, &PhysicalPos)| ((x.1.col, x.1.row), x.0))
             .collect();
 
-        PhysicalLayout {
+        Ok(PhysicalLayout {
             index_to_pos,
             pos_to_index,
+        })
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.index_to_pos.len()
+    }
+
+    /// True if all keys belong to the same hand, such as with the `left` split.
+    pub fn is_one_handed(&self) -> bool {
+        self.index_to_pos
+            .windows(2)
+            .all(|x| x[0].finger.half == x[1].finger.half)
+    }
+
+    /// The row with the lowest average effort among the rows with the most keys,
+    /// so a small thumb cluster doesn't count as the home row.
+    pub fn home_row(&self) -> usize {
+        let mut rows: BTreeMap<usize, (usize, u32)> = BTreeMap::new();
+        for pos in &self.index_to_pos {
+            let (count, effort) = rows.entry(pos.row).or_insert((0, 0));
+            *count += 1;
+            *effort += pos.effort;
         }
+        let avg_effort = |count: usize, effort: u32| effort as f32 / count as f32;
+        rows.into_iter()
+            .min_by(|(_, (a_count, a_effort)), (_, (b_count, b_effort))| {
+                b_count.cmp(a_count).then_with(|| {
+                    avg_effort(*a_count, *a_effort).total_cmp(&avg_effort(*b_count, *b_effort))
+                })
+            })
+            .map(|(row, _)| row)
+            .unwrap_or(0)
     }
 
     pub fn index_to_pos(&self, index: usize) -> PhysicalPos {
@@ -411,6 +1274,13 @@ impl PhysicalPos {
     pub fn is_sfb(&self, other: &PhysicalPos) -> bool {
         self.pos() != other.pos() && self.finger == other.finger
     }
+
+    /// Euclidean distance in rows and columns.
+    pub fn distance(&self, other: &PhysicalPos) -> f32 {
+        let cols = self.col.abs_diff(other.col) as f32;
+        let rows = self.row.abs_diff(other.row) as f32;
+        (cols * cols + rows * rows).sqrt()
+    }
 }
 
 #[cfg(test)]
@@ -420,7 +1290,7 @@ mod tests {
 
     #[test]
     fn test_parse_render_opts() -> Result<()> {
-        let input = r#"
+        let input = r##"
 {
   "colors": {},
   "legend": [],
@@ -454,6 +1324,10 @@ mod tests {
         {
         "keys": ["SE_LPRN"],
         "title": "("
+        },
+        {
+        "keys": ["SE_RPRN"],
+        "color": "#ff0000"
         }
     ],
     "_NUM": [
@@ -464,7 +1338,7 @@ mod tests {
     ]
   }
 }
-        "#;
+        "##;
         let opts = RenderOpts::parse_from_str("id", input)?;
 
         let a = opts.get("_BASE", "SE_A");
@@ -474,7 +1348,9 @@ mod tests {
                 id: "SE_A".to_string(),
                 title: "A".to_string(),
                 hold_title: None,
+                shifted_title: None,
                 class: "default".to_string(),
+                color: None,
             }
         );
 
@@ -485,135 +1361,493 @@ mod tests {
                 id: "SE_LPRN".to_string(),
                 title: "(".to_string(),
                 hold_title: None,
+                shifted_title: None,
                 class: "management".to_string(),
+                color: None,
+            }
+        );
+
+        let rprn = opts.get("_NUM", "SE_RPRN");
+        assert_eq!(rprn.class, "management");
+        assert_eq!(rprn.color.as_deref(), Some("#ff0000"));
+        assert_eq!(opts.class_color("management"), "#e5c494");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_filename() -> Result<()> {
+        let input = r#"
+{
+  "colors": {},
+  "legend": [],
+  "layer_filenames": { "_BASE": "base.svg", "legend": "colors.svg" },
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": ["1"],
+  "finger_assignments": ["0"],
+  "layers": {}
+}
+"#;
+        let opts = RenderOpts::parse_from_str("id", input)?;
+        assert_eq!(opts.output_filename("_BASE"), "base.svg");
+        assert_eq!(opts.output_filename("legend"), "colors.svg");
+        assert_eq!(opts.output_filename("_NUM"), "_NUM.svg");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legend_color() -> Result<()> {
+        let input = r##"
+{
+  "colors": { "management": "#e5c494" },
+  "legend": [
+    { "class": "management", "title": "Management" },
+    { "class": "concept", "title": "Concept", "color": "#00ff00" }
+  ],
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": ["1"],
+  "finger_assignments": ["0"],
+  "layers": {}
+}
+"##;
+        let opts = RenderOpts::parse_from_str("id", input)?;
+        assert_eq!(opts.legend_color(&opts.legend[0]), "#e5c494");
+        assert_eq!(opts.legend_color(&opts.legend[1]), "#00ff00");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_string_title() {
+        assert_eq!(send_string_title(r##""#{}"SS_TAP(X_LEFT)"##), "#{}←");
+        assert_eq!(send_string_title(r#"SS_LSFT("a")"b""#), "⇧ab");
+        assert_eq!(
+            send_string_title(r#"SS_LCTL(SS_TAP(X_C))SS_DELAY(100)"\"x\"""#),
+            "⌃C\"x\""
+        );
+        assert_eq!(send_string_title(r#""())""#), "())");
+        assert_eq!(key_id_to_title(r#""<="SS_TAP(X_RIGHT)"#), "<=→");
+        assert_eq!(key_id_to_title("https://"), "https://");
+        assert_eq!(key_id_to_title("SS_TAP(X_ESC)"), "Esc");
+        assert_eq!(key_id_to_title("KC_SS_X"), "KC_SS_X");
+    }
+
+    #[test]
+    fn test_json_comments() -> Result<()> {
+        let src = r#"{
+  // Line comment
+  "url": "https://example.com", // After a value
+  /* Block
+     comment */ "escaped": "\" /* not a comment */",
+  "slashes": "/**/"
+}"#;
+        let stripped = strip_json_comments(src);
+        assert_eq!(stripped.lines().count(), src.lines().count());
+        let value: Value = serde_json::from_str(&stripped)?;
+        assert_eq!(value["url"], "https://example.com");
+        assert_eq!(value["escaped"], r#"" /* not a comment */"#);
+        assert_eq!(value["slashes"], "/**/");
+
+        // Strict JSON is unchanged
+        let json = r#"{ "a": "b//c", "d": [1, 2] }"#;
+        assert_eq!(strip_json_comments(json), json);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_palette() -> Result<()> {
+        let input = r##"
+{
+  "colors": { "nav_alt": "#ffffff" },
+  "palette": [
+    { "color": "#66c2a5", "classes": ["nav", "nav_alt", "nav_extra"] },
+    { "color": "#1f1f1f", "classes": ["dark", "dark_alt"] }
+  ],
+  "legend": [],
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": ["1"],
+  "finger_assignments": ["0"],
+  "layers": {}
+}
+"##;
+        let opts = RenderOpts::parse_from_str("id", input)?;
+        assert_eq!(opts.class_color("nav"), "#66c2a5");
+        assert_eq!(opts.class_color("nav_alt"), "#ffffff");
+        assert_eq!(opts.class_color("nav_extra"), "#468571");
+        assert_eq!(opts.class_color("dark"), "#1f1f1f");
+        assert_eq!(opts.class_color("dark_alt"), "#3e3e3e");
+
+        let err = RenderOpts::parse_from_str("id", &input.replace("#1f1f1f", "dark")).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid palette color `dark`"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tap_hold_titles() {
+        let opts = KeyOpts::with_defaults("LT(_SYM, KC_ESC)");
+        assert_eq!(opts.title, "Esc");
+        assert_eq!(opts.hold_title.as_deref(), Some("SYM"));
+        let opts = KeyOpts::with_defaults("MT(MOD_LSFT, SE_A)");
+        assert_eq!(opts.title, "A");
+        assert_eq!(opts.hold_title.as_deref(), Some("Shift"));
+        let opts = KeyOpts::with_defaults("RCTL_T(KC_TAB)");
+        assert_eq!(opts.title, "Tab");
+        assert_eq!(opts.hold_title.as_deref(), Some("Ctrl"));
+        // Combined modifiers keep their name
+        let opts = KeyOpts::with_defaults("MEH_T(SE_A)");
+        assert_eq!(opts.hold_title.as_deref(), Some("MEH"));
+
+        assert_eq!(KeyOpts::with_defaults("SE_A").hold_title, None);
+        assert_eq!(KeyOpts::with_defaults("LT(_SYM)").hold_title, None);
+    }
+
+    #[test]
+    fn test_keycap_style() -> Result<()> {
+        let style: KeycapStyle = serde_json::from_str(r#"{ "rx": 2, "combo_border_x": 3 }"#)?;
+        assert_eq!(
+            style,
+            KeycapStyle {
+                rx: 2.0,
+                combo_border_x: 3.0,
+                ..Default::default()
             }
         );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("Esc"), 3.0);
+        assert_eq!(display_width("#{}←"), 4.5);
+        assert_eq!(display_width("Å"), 1.2);
+        assert_eq!(display_width("😀"), 2.0);
+        assert_eq!(display_width(""), 0.0);
+    }
+
+    #[test]
+    fn test_unicode_title() {
+        assert_eq!(key_id_to_title("UC(0x00E5)"), "å");
+        assert_eq!(key_id_to_title("UC(0x1F600)"), "😀");
+        // Surrogates and out of range values aren't characters
+        assert_eq!(key_id_to_title("UC(0xD800)"), "UC(0xD800)");
+        assert_eq!(key_id_to_title("UC(0x110000)"), "UC(0x110000)");
+    }
+
+    #[test]
+    fn test_parse_finger() -> Result<()> {
+        assert_eq!("thumb".parse::<Finger>()?, Finger::Thumb);
+        assert_eq!(
+            "right-index".parse::<FingerAssignment>()?,
+            FingerAssignment {
+                finger: Finger::Index,
+                half: MatrixHalf::Right,
+            }
+        );
+
+        let err = "left-toe".parse::<FingerAssignment>().unwrap_err();
+        assert!(err.to_string().contains("left-pinky"));
+        assert!(err.to_string().contains("right-thumb"));
+        assert!("pinky".parse::<FingerAssignment>().is_err());
+
+        for finger in FingerAssignment::all() {
+            assert_eq!(finger.to_string().parse::<FingerAssignment>()?, finger);
+        }
 
         Ok(())
     }
 
-    // #[test]
-    // fn test_physical_layout() {
-    //     let spec = PhysicalLayoutSpec(vec![
-    //         "54446    64445".into(),
-    //         "21005    50012".into(),
-    //         "64436    63446".into(),
-    //         " 77".into(),
-    //         "   80    0".into(),
-    //     ]);
-    //     let layout = spec.convert();
-    //     assert_eq!(
-    //         layout.index_to_pos(0),
-    //         PhysicalPos {
-    //             col: 0,
-    //             row: 0,
-    //             half: MatrixHalf::Left,
-    //             value: 5
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(1),
-    //         PhysicalPos {
-    //             col: 1,
-    //             row: 0,
-    //             half: MatrixHalf::Left,
-    //             value: 4
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(5),
-    //         PhysicalPos {
-    //             col: 5,
-    //             row: 0,
-    //             half: MatrixHalf::Right,
-    //             value: 6
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(9),
-    //         PhysicalPos {
-    //             col: 9,
-    //             row: 0,
-    //             half: MatrixHalf::Right,
-    //             value: 5
-    //         }
-    //     );
-    //
-    //     assert_eq!(
-    //         layout.index_to_pos(10),
-    //         PhysicalPos {
-    //             col: 0,
-    //             row: 1,
-    //             half: MatrixHalf::Left,
-    //             value: 2
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(11),
-    //         PhysicalPos {
-    //             col: 1,
-    //             row: 1,
-    //             half: MatrixHalf::Left,
-    //             value: 1
-    //         }
-    //     );
-    //
-    //     assert_eq!(
-    //         layout.index_to_pos(20),
-    //         PhysicalPos {
-    //             col: 0,
-    //             row: 2,
-    //             half: MatrixHalf::Left,
-    //             value: 6
-    //         }
-    //     );
-    //
-    //     assert_eq!(
-    //         layout.index_to_pos(30),
-    //         PhysicalPos {
-    //             col: 1,
-    //             row: 3,
-    //             half: MatrixHalf::Left,
-    //             value: 7
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(31),
-    //         PhysicalPos {
-    //             col: 2,
-    //             row: 3,
-    //             half: MatrixHalf::Left,
-    //             value: 7
-    //         }
-    //     );
-    //
-    //     assert_eq!(
-    //         layout.index_to_pos(32),
-    //         PhysicalPos {
-    //             col: 3,
-    //             row: 4,
-    //             half: MatrixHalf::Left,
-    //             value: 8
-    //         }
-    //     );
-    //     assert_eq!(
-    //         layout.index_to_pos(33),
-    //         PhysicalPos {
-    //             col: 4,
-    //             row: 4,
-    //             half: MatrixHalf::Left,
-    //             value: 0
-    //         }
-    //     );
-    //
-    //     assert_eq!(
-    //         layout.index_to_pos(34),
-    //         PhysicalPos {
-    //             col: 5,
-    //             row: 4,
-    //             half: MatrixHalf::Right,
-    //             value: 0
-    //         }
-    //     );
-    // }
+    #[test]
+    fn test_define_titles() -> Result<()> {
+        let input = r#"
+{
+  "colors": {},
+  "legend": [],
+  "resolve_defines": true,
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": ["1"],
+  "finger_assignments": ["0"],
+  "layers": {
+    "default": [
+      {
+        "keys": ["CUSTOM"],
+        "title": "Custom"
+      }
+    ]
+  }
+}
+"#;
+        let mut opts = RenderOpts::parse_from_str("id", input)?;
+        assert!(opts.resolve_defines);
+        opts.defines = [
+            ("MT_SPC", "LT(_SYM, KC_SPC)"),
+            ("ALIAS", "SE_LPRN"),
+            ("NESTED", "LT(_SYM, ALIAS)"),
+            ("SHIFTED", "LT(_SYM, LSFT(SE_DOT))"),
+            ("CUSTOM", "SE_DOT"),
+            ("CYCLE", "CYCLE2"),
+            ("CYCLE2", "CYCLE"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        assert_eq!(opts.get("_BASE", "ALIAS").title, "(");
+        assert_eq!(opts.get("_BASE", "NESTED").title, "(");
+        assert_eq!(opts.get("_BASE", "LSFT(SE_DOT)").title, "LSFT(SE_DOT)");
+        // Modifiers aren't dropped
+        assert_eq!(opts.get("_BASE", "SHIFTED").title, "SHIFTED");
+        // Keycodes without a title keep their name
+        assert_eq!(opts.get("_BASE", "MT_SPC").title, "MT_SPC");
+        assert_eq!(opts.get("_BASE", "CYCLE").title, "CYCLE");
+        // Explicit titles have priority
+        assert_eq!(opts.get("_BASE", "CUSTOM").title, "Custom");
+
+        opts.resolve_defines = false;
+        assert_eq!(opts.get("_BASE", "ALIAS").title, "ALIAS");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_render_opts() -> Result<()> {
+        let base = r##"
+{
+  "colors": { "default": "#e5c494", "sym": "#8da0cb" },
+  "legend": [{ "class": "default", "title": "Default" }],
+  "outputs": {
+    "combo_keys_with_separate_imgs": [],
+    "combo_highlight_groups": {},
+    "combo_background_layer_class": "combo_background",
+    "active_class_in_separate_layer": "active_layer"
+  },
+  "physical_layout": ["11"],
+  "finger_assignments": ["01"],
+  "layers": {
+    "default": [
+      { "keys": ["SE_A"], "title": "a" },
+      { "keys": ["SE_B"], "title": "b" }
+    ]
+  }
+}
+"##;
+        let overlay = r##"
+{
+  "colors": { "sym": "#ff0000" },
+  "legend": [{ "class": "sym", "title": "Symbols" }],
+  "outputs": { "legend": false },
+  "layers": {
+    "default": [{ "keys": ["SE_B"], "title": "B" }],
+    "_NUM": [{ "keys": ["SE_A"], "class": "sym" }]
+  }
+}
+"##;
+        let opts = RenderOpts::merge_from_strs("id", &[base, overlay])?;
+
+        // Maps are merged
+        assert_eq!(opts.class_color("default"), "#e5c494");
+        assert_eq!(opts.class_color("sym"), "#ff0000");
+        assert!(!opts.outputs.legend);
+        assert_eq!(
+            opts.outputs.combo_background_layer_class,
+            "combo_background"
+        );
+        // Lists are replaced
+        assert_eq!(opts.legend.len(), 1);
+        assert_eq!(opts.legend[0].class, "sym");
+        // Except keys, where later keys override earlier ones
+        assert_eq!(opts.get("_BASE", "SE_A").title, "a");
+        assert_eq!(opts.get("_BASE", "SE_B").title, "B");
+        assert_eq!(opts.get("_NUM", "SE_A").class, "sym");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_theme_css() -> Result<()> {
+        assert_eq!(Theme::default().css(), "");
+
+        let theme: Theme = serde_json::from_str(
+            r#"{ "preset": "dark", "css": ".keycap { font-family: serif; }" }"#,
+        )?;
+        let css = theme.css();
+        assert!(css.contains("stroke: #c8c8c8"));
+        assert!(css.ends_with("    .keycap { font-family: serif; }\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_modes() -> Result<()> {
+        let spec = |rows: &[&str]| PhysicalLayoutSpec(rows.iter().map(|x| x.to_string()).collect());
+        let halves = |layout: &PhysicalLayout| -> Vec<MatrixHalf> {
+            (0..layout.key_count())
+                .map(|i| layout.index_to_pos(i).finger.half)
+                .collect()
+        };
+        let (l, r) = (MatrixHalf::Left, MatrixHalf::Right);
+
+        let layout =
+            PhysicalLayout::new(spec(&["11    11"]), spec(&["01    10"]), SplitMode::Halves)?;
+        assert_eq!(halves(&layout), vec![l, l, r, r]);
+        assert_eq!(layout.index_to_pos(2).col, 2);
+
+        let layout = PhysicalLayout::new(spec(&["1111"]), spec(&["0110"]), SplitMode::None)?;
+        assert_eq!(halves(&layout), vec![l, l, r, r]);
+
+        let layout = PhysicalLayout::new(
+            spec(&["11    111    11"]),
+            spec(&["01    333    10"]),
+            SplitMode::Center,
+        )?;
+        assert_eq!(halves(&layout), vec![l, l, l, l, r, r, r]);
+        assert!(!layout.is_one_handed());
+
+        let layout =
+            PhysicalLayout::new(spec(&["11    11"]), spec(&["01    23"]), SplitMode::Right)?;
+        assert_eq!(halves(&layout), vec![r, r, r, r]);
+        assert!(layout.is_one_handed());
+
+        let err = PhysicalLayout::new(
+            spec(&["11    11", "11    11    11"]),
+            spec(&["01    10", "01    10    10"]),
+            SplitMode::Halves,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::TooManySplits { row: 2, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_physical_layout() -> Result<()> {
+        let spec = |rows: &[&str]| PhysicalLayoutSpec(rows.iter().map(|x| x.to_string()).collect());
+        let layout = PhysicalLayout::new(
+            spec(&[
+                "54446    64445",
+                "21005    50012",
+                "64436    63446",
+                " 77",
+                "   80    0",
+            ]),
+            spec(&[
+                "11233    33211",
+                "01233    33210",
+                "01233    33210",
+                " 12",
+                "   44    4",
+            ]),
+            SplitMode::Halves,
+        )?;
+        let pos = |col, row, finger, half, effort| PhysicalPos {
+            col,
+            row,
+            finger: FingerAssignment { finger, half },
+            effort,
+        };
+
+        assert_eq!(layout.key_count(), 35);
+        assert_eq!(
+            layout.index_to_pos(0),
+            pos(0, 0, Finger::Ring, MatrixHalf::Left, 5)
+        );
+        assert_eq!(
+            layout.index_to_pos(5),
+            pos(5, 0, Finger::Index, MatrixHalf::Right, 6)
+        );
+        assert_eq!(
+            layout.index_to_pos(10),
+            pos(0, 1, Finger::Pinky, MatrixHalf::Left, 2)
+        );
+        // Leading spaces offset the column
+        assert_eq!(
+            layout.index_to_pos(30),
+            pos(1, 3, Finger::Ring, MatrixHalf::Left, 7)
+        );
+        assert_eq!(
+            layout.index_to_pos(34),
+            pos(5, 4, Finger::Thumb, MatrixHalf::Right, 0)
+        );
+        assert_eq!(layout.get((5, 4)), layout.index_to_pos(34));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_files() -> Result<()> {
+        let dir = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/parse/testdata");
+        let opts = RenderOpts::parse(&dir.join("layout_files.json"))?;
+        let layout = &opts.physical_layout;
+        let pos = layout.index_to_pos(0);
+        assert_eq!(
+            (pos.finger.to_string().as_str(), pos.effort),
+            ("left-ring", 2)
+        );
+        let pos = layout.index_to_pos(7);
+        assert_eq!(
+            (pos.finger.to_string().as_str(), pos.effort),
+            ("right-thumb", 0)
+        );
+        assert_eq!(pos.row, 1);
+
+        // An inline layout can't be given together with a file
+        let src = fs::read_to_string(dir.join("layout_files.json"))?
+            .replace(r#""split""#, r#""physical_layout": ["1"], "split""#);
+        let err = parse_source(&src, &dir).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Only one of physical_layout and physical_layout_file can be given"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_physical_layout_errors() {
+        let spec = |rows: &[&str]| PhysicalLayoutSpec(rows.iter().map(|x| x.to_string()).collect());
+
+        let err = PhysicalLayout::new(
+            spec(&["11    11", "11    11"]),
+            spec(&["01    10", "01    70"]),
+            SplitMode::Halves,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Finger `7` unknown, expected 0 (pinky) to 4 (thumb) in finger_assignments row 2: `01    70`"
+        );
+
+        let err = PhysicalLayout::new(spec(&["1x"]), spec(&["01"]), SplitMode::None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Effort `x` isn't a digit in physical_layout row 1: `1x`"
+        );
+
+        assert!(PhysicalLayout::new(spec(&["11"]), spec(&["01", "10"]), SplitMode::None).is_err());
+    }
 }
@@ -1,37 +1,49 @@
+use crate::keylog::KeylogStats;
+use crate::parse::display_width;
+use crate::parse::lighten_color;
 use crate::parse::Combo;
+use crate::parse::EncoderDef;
+use crate::parse::Finger;
+use crate::parse::FingerAssignment;
 use crate::parse::InputInfo;
 use crate::parse::Key;
+use crate::parse::KeyOpts;
+use crate::parse::KeycapStyle;
 use crate::parse::Layer;
 use crate::parse::MatrixHalf;
 use crate::parse::RenderOpts;
 use camino::Utf8Path;
-use eyre::Result;
+use eyre::{eyre, Result};
 use palette::{Hsv, IntoColor, Srgb};
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
 use std::str::FromStr;
 
 // TODO
 // - REFACTOR
-// - Add wrapping class specifying keyboard/keymap name
 
-pub fn render(info: &InputInfo, output_dir: &Utf8Path) -> Result<()> {
+pub fn render(info: &InputInfo, output_dir: &Utf8Path, debug_matrix: bool) -> Result<()> {
+    let info = &with_hand_gap(info);
+    warn_missing_colors(info);
+
     if info.render_opts.outputs.layers {
+        let canvas = layer_canvas(&info.keymap.layers, &info.render_opts);
         for layer in info.keymap.layers.iter() {
-            render_layer(layer, &info.render_opts, output_dir)?;
+            let encoders = info.keymap.find_encoders(&layer.id);
+            render_layer(layer, encoders, &info.render_opts, canvas, output_dir)?;
         }
     }
 
     if info.render_opts.outputs.legend {
-        render_legend(&info.render_opts, output_dir)?;
+        render_legend(info, output_dir)?;
     }
 
     if info.render_opts.outputs.combos {
-        let base_layer = &info.keymap.layers[0];
         render_combos(
             &info.keymap.combos,
-            base_layer,
+            &info.keymap.layers,
             &info.render_opts,
             output_dir,
         )?;
@@ -40,135 +52,433 @@ pub fn render(info: &InputInfo, output_dir: &Utf8Path) -> Result<()> {
     if info.render_opts.outputs.effort {
         let base_layer = &info.keymap.layers[0];
         render_effort_grid(base_layer, &info.render_opts, output_dir)?;
+        render_effort_heatmap(base_layer, &info.render_opts, output_dir)?;
+    }
+
+    if info.render_opts.outputs.finger_zones {
+        let base_layer = &info.keymap.layers[0];
+        render_finger_zones(base_layer, &info.render_opts, output_dir)?;
+    }
+
+    if debug_matrix {
+        let base_layer = &info.keymap.layers[0];
+        render_matrix_debug(base_layer, &info.render_opts, output_dir)?;
     }
 
     Ok(())
 }
 
-fn render_legend(render_opts: &RenderOpts, output_dir: &Utf8Path) -> Result<()> {
-    let path = output_dir.join("legend.svg");
+fn find_layer<'a>(info: &'a InputInfo, layer_id: &str) -> Result<&'a Layer> {
+    info.keymap
+        .layers
+        .iter()
+        .find(|layer| layer.id.0 == layer_id)
+        .ok_or_else(|| {
+            let ids: Vec<_> = info.keymap.layers.iter().map(|x| x.id.0.as_str()).collect();
+            eyre!(
+                "Unknown layer `{layer_id}`, expected one of: {}",
+                ids.join(", ")
+            )
+        })
+}
+
+/// Writes the image of a single layer, such as `_BASE`, without creating any files.
+pub fn write_layer_by_id(info: &InputInfo, layer_id: &str, file: &mut dyn Write) -> Result<()> {
+    let info = &with_hand_gap(info);
+    let layer = find_layer(info, layer_id)?;
+    let encoders = info.keymap.find_encoders(&layer.id);
+    let canvas = layer_canvas(&info.keymap.layers, &info.render_opts);
+    write_layer(file, layer, encoders, &info.render_opts, canvas)
+}
+
+// The hand gap only moves the keys in the images, combos included as they're
+// positioned from their keys. The parsed keymap keeps the positions of
+// keyboard.json, so the stats measure the real distances.
+fn with_hand_gap(info: &InputInfo) -> Cow<'_, InputInfo> {
+    let gap = info.render_opts.hand_gap;
+    if gap == 0.0 {
+        return Cow::Borrowed(info);
+    }
+    let mut info = info.clone();
+    let shift = |key: &mut Key| {
+        if key.physical_pos.finger.half == MatrixHalf::Right {
+            key.x += gap;
+        }
+    };
+    for layer in &mut info.keymap.layers {
+        layer.keys.iter_mut().for_each(shift);
+    }
+    for combo in &mut info.keymap.combos {
+        combo.keys.iter_mut().for_each(shift);
+    }
+    Cow::Owned(info)
+}
+
+// The options of all keys in all layers and of the combo outputs.
+fn used_key_opts(info: &InputInfo) -> Vec<KeyOpts> {
+    let render_opts = &info.render_opts;
+
+    let mut res = Vec::new();
+    for layer in &info.keymap.layers {
+        for key in &layer.keys {
+            res.push(render_opts.get(&layer.id.0, &key.id.0));
+        }
+    }
+    if let Some(base_layer) = info.keymap.layers.first() {
+        for combo in &info.keymap.combos {
+            res.push(render_opts.get(&base_layer.id.0, &combo.output));
+        }
+    }
+    res
+}
+
+// Classes without a color silently get the default color, which makes typos
+// in class names easy to miss.
+fn warn_missing_colors(info: &InputInfo) {
+    let render_opts = &info.render_opts;
+    let missing = missing_colors(info);
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: no color for classes {}, using {}",
+            missing.join(", "),
+            render_opts.default_color
+        );
+    }
+}
+
+/// Classes used by keys or the legend that aren't in the colors map.
+pub fn missing_colors(info: &InputInfo) -> Vec<String> {
+    let render_opts = &info.render_opts;
+
+    let mut classes: BTreeSet<_> = used_key_opts(info)
+        .into_iter()
+        .filter(|x| x.color.is_none())
+        .map(|x| x.class)
+        .collect();
+    for item in render_opts.legend.iter().filter(|x| x.color.is_none()) {
+        classes.insert(item.class.clone());
+    }
+
+    classes
+        .into_iter()
+        .filter(|class| !render_opts.colors.contains_key(class))
+        .collect()
+}
+
+fn render_legend(info: &InputInfo, output_dir: &Utf8Path) -> Result<()> {
+    let render_opts = &info.render_opts;
+    let path = output_dir.join(render_opts.output_filename("legend"));
     let mut file = File::create(&path)?;
 
+    let mut items: Vec<_> = render_opts
+        .legend
+        .iter()
+        .map(|item| LegendItem {
+            class: &item.class,
+            title: &item.title,
+            color: render_opts.legend_color(item),
+        })
+        .collect();
+
+    let unlisted = if render_opts.outputs.legend_unlisted_classes {
+        unlisted_legend_classes(info)
+    } else {
+        BTreeSet::new()
+    };
+    for class in &unlisted {
+        items.push(LegendItem {
+            class,
+            title: class,
+            color: render_opts.class_color(class),
+        });
+    }
+
+    write_legend(&mut file, &items, render_opts)?;
+
+    status!("{}", path);
+
+    Ok(())
+}
+
+// Classes used by the keymap that are missing from the legend.
+fn unlisted_legend_classes(info: &InputInfo) -> BTreeSet<String> {
+    let legend = &info.render_opts.legend;
+    used_key_opts(info)
+        .into_iter()
+        .map(|x| x.class)
+        .filter(|class| !legend.iter().any(|item| &item.class == class))
+        .collect()
+}
+
+struct LegendItem<'a> {
+    class: &'a str,
+    title: &'a str,
+    color: &'a str,
+}
+
+fn write_legend(
+    file: &mut dyn Write,
+    items: &[LegendItem],
+    render_opts: &RenderOpts,
+) -> Result<()> {
     let keymap_border = 10.0;
     let key_side = 54.0;
     let key_w = 4.0 * key_side;
     let key_h = key_side;
 
-    let item_count = render_opts.legend.len();
-    let columns = std::cmp::min(item_count, 4);
-    let rows = (item_count as f32 / columns as f32).ceil();
+    let (columns, rows) = grid_size(items.len(), 4);
 
     let max_x = columns as f32 * key_w + keymap_border * 2.0;
     let max_y = rows as f32 * key_h + keymap_border * 2.0;
 
-    writeln!(
+    write_svg_header(
         file,
-        r#"<svg width='{max_x}px'
-    class="keyboard-legend"
-    height='{max_y}x'
-    viewBox='0 0 {max_x} {max_y}'
-    xmlns='http://www.w3.org/2000/svg'
-    xmlns:xlink="http://www.w3.org/1999/xlink">
-"#
+        "keyboard-legend",
+        render_opts,
+        (0.0, 0.0, max_x, max_y),
     )?;
-
-    file.write_all(
-        r#" <style type='text/css'>
-    .legend .border { stroke: black; stroke-width: 1; }
+    write_style(
+        file,
+        r#"    .legend .border { stroke: black; stroke-width: 1; }
     .legend .inner.border { stroke: rgba(0,0,0,.1); }
     .legend { font-family: sans-serif; font-size: 11px}
-  </style>
-"#
-        .as_bytes(),
+"#,
+        &render_opts.theme.css(),
     )?;
+    if render_opts.gradient_keys {
+        write_gradient_defs(file, items.iter().map(|item| item.color))?;
+    }
 
-    let fallback_color = "#e5c494".to_string();
-    for (i, item) in render_opts.legend.iter().enumerate() {
+    for (i, item) in items.iter().enumerate() {
         let row = i / columns;
         let col = i - row * columns;
 
-        let class = &item.class;
-        let txt = &item.title;
-
         let x = keymap_border + col as f32 * key_w;
         let y = keymap_border + row as f32 * key_h;
 
-        let inner_color = render_opts
-            .colors
-            .get(&item.class)
-            .unwrap_or(&fallback_color);
-
         KeyRender {
             x,
             y,
             w: key_w,
             h: key_h,
             rx: 5.0,
-            class,
-            inner_color,
-            title: txt,
+            class: item.class,
+            inner_color: item.color,
+            title: item.title,
             hold_title: None,
+            shifted_title: None,
+            tooltip: None,
             border_left: 6.0,
             border_right: 6.0,
             border_top: 4.0,
             border_bottom: 8.0,
             text_h: 11.0,
+            compact: false,
+            snap_pixels: false,
+            gradient: render_opts.gradient_keys,
         }
-        .render(&mut file)?;
+        .render(file)?;
     }
 
     file.write_all("</svg>".as_bytes())?;
 
-    println!("{}", path);
-
     Ok(())
 }
 
-fn write_open_svg(file: &mut File, id: &str, keys: &[Key], key_w: f32, border: f32) -> Result<()> {
-    let mut max_x: f32 = 0.0;
-    let mut max_y: f32 = 0.0;
-    for key in keys.iter() {
-        max_x = max_x.max((1.0 + key.x) * key_w);
-        max_y = max_y.max((1.0 + key.y) * key_w);
+// The opening svg element, shared by all images. The root classes name the
+// keyboard and keymap so images of different keymaps can be told apart.
+fn write_svg_header(
+    file: &mut dyn Write,
+    class: &str,
+    render_opts: &RenderOpts,
+    (min_x, min_y, width, height): (f32, f32, f32, f32),
+) -> Result<()> {
+    let mut classes = format!("{class} {}", render_opts.id);
+    if !render_opts.keyboard.is_empty() {
+        classes.push_str(&format!(
+            " keyboard-{}",
+            css_class_name(&render_opts.keyboard)
+        ));
+    }
+    if !render_opts.keymap.is_empty() {
+        classes.push_str(&format!(" keymap-{}", css_class_name(&render_opts.keymap)));
     }
-    max_x += border * 2.0;
-    max_y += border * 2.0;
 
     writeln!(
         file,
-        r#"<svg width='{max_x}px'
-       class='keymap {id}'
-       height='{max_y}x'
-       viewBox='0 0 {max_x} {max_y}'
+        r#"<svg width='{width}px'
+       class='{classes}'
+       height='{height}px'
+       viewBox='{min_x} {min_y} {width} {height}'
        xmlns='http://www.w3.org/2000/svg'
        xmlns:xlink="http://www.w3.org/1999/xlink">
 "#
     )?;
+    Ok(())
+}
+
+// The style element with the rules of an image followed by the theme.
+fn write_style(file: &mut dyn Write, css: &str, theme_css: &str) -> Result<()> {
+    file.write_all(" <style type='text/css'>\n".as_bytes())?;
+    file.write_all(css.as_bytes())?;
+    file.write_all(theme_css.as_bytes())?;
+    file.write_all("  </style>\n".as_bytes())?;
+    Ok(())
+}
+
+// Columns and rows of a grid that fills rows first.
+fn grid_size(item_count: usize, max_columns: usize) -> (usize, usize) {
+    let columns = item_count.clamp(1, max_columns);
+    (columns, item_count.div_ceil(columns))
+}
+
+fn write_open_svg(
+    file: &mut dyn Write,
+    render_opts: &RenderOpts,
+    keys: &[Key],
+    key_w: f32,
+    border: f32,
+    canvas: Option<(f32, f32)>,
+    caption: Option<&str>,
+) -> Result<()> {
+    let banner = format!("{} / {}", render_opts.keyboard, render_opts.keymap);
+    let caption = match (render_opts.outputs.keymap_caption, caption) {
+        (true, Some(caption)) => Some(format!("{banner}: {caption}")),
+        (true, None) => Some(banner),
+        (false, caption) => caption.map(str::to_string),
+    };
+
+    // The keys are centered on a larger canvas by offsetting the viewBox,
+    // so the keys keep their coordinates.
+    let (keys_w, keys_h) = keys_size(keys, key_w);
+    let (canvas_w, canvas_h) = canvas.unwrap_or((keys_w, keys_h));
+    let min_x = (keys_w - canvas_w) / 2.0;
+    let mut min_y = (keys_h - canvas_h) / 2.0;
+    let max_x = canvas_w + border * 2.0;
+    let mut max_y = canvas_h + border * 2.0;
+
+    // The caption is placed above the keys by extending the viewBox upwards.
+    let caption_h = if caption.is_some() { 24.0 } else { 0.0 };
+    min_y -= caption_h;
+    max_y += caption_h;
+
+    write_svg_header(file, "keymap", render_opts, (min_x, min_y, max_x, max_y))?;
+
+    if let Some(background) = &render_opts.background {
+        let background = html_escape::encode_safe(background);
+        writeln!(
+            file,
+            r#"<rect x="{min_x}" y="{min_y}" width="{max_x}" height="{max_y}" fill="{background}" class="background"/>"#
+        )?;
+    }
 
-    file.write_all(
-        r#" <style type='text/css'>
-    .keycap .border { stroke: black; stroke-width: 1; }
+    let mut css = r#"    .keycap .border { stroke: black; stroke-width: 1; }
     .keycap .inner.border { stroke: rgba(0,0,0,.1); }
     .keycap { font-family: sans-serif; font-size: 11px }
     .keycap .sub { font-size: 9px }
     .combos .keycap { font-size: 8px }
-  </style>
 "#
-        .as_bytes(),
-    )?;
+    .to_string();
+    if uses_shifted_titles(render_opts) {
+        css.push_str("    .keycap .shifted { font-size: 8px }\n");
+    }
+    if render_opts.outputs.combo_lines {
+        css.push_str("    .combo-line { stroke: black; stroke-opacity: 0.5; stroke-width: 1 }\n");
+    }
+    write_style(file, &css, &render_opts.theme.css())?;
+    if render_opts.gradient_keys {
+        let colors = gradient_colors(render_opts);
+        write_gradient_defs(file, colors.iter().map(String::as_str))?;
+    }
+
+    if let Some(caption) = caption {
+        let text_x = min_x + max_x / 2.0;
+        let text_y = min_y + (border + caption_h) / 2.0;
+        let caption = html_escape::encode_safe(&caption);
+        writeln!(
+            file,
+            r#"<text x="{text_x}" y="{text_y}" text-anchor="middle" dominant-baseline="middle" font-family="sans-serif" font-size="14px" class="caption">{caption}</text>"#
+        )?;
+    }
 
     Ok(())
 }
 
-fn render_layer(layer: &Layer, render_opts: &RenderOpts, output_dir: &Utf8Path) -> Result<()> {
-    let path = output_dir.join(format!("{}.svg", layer.id.0));
+fn uses_shifted_titles(render_opts: &RenderOpts) -> bool {
+    render_opts
+        .default_keys
+        .values()
+        .chain(render_opts.layer_keys.values().flat_map(|x| x.values()))
+        .any(|x| x.shifted_title.is_some())
+}
+
+// The size of the area covered by the keys, without borders.
+fn keys_size(keys: &[Key], key_w: f32) -> (f32, f32) {
+    keys.iter().fold((0.0, 0.0), |(w, h), key| {
+        (w.max((1.0 + key.x) * key_w), h.max((1.0 + key.y) * key_w))
+    })
+}
+
+/// The size all layers are rendered at with `fixed_layer_size`, fitting the largest layer.
+fn layer_canvas(layers: &[Layer], render_opts: &RenderOpts) -> Option<(f32, f32)> {
+    if !render_opts.outputs.fixed_layer_size {
+        return None;
+    }
+    Some(layers.iter().fold((0.0, 0.0), |(w, h), layer| {
+        let (layer_w, layer_h) = keys_size(&layer.keys, LAYER_KEY_W);
+        (f32::max(w, layer_w), f32::max(h, layer_h))
+    }))
+}
+
+// Keyboard names contain `/`, such as `ferris/sweep`.
+fn css_class_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn render_layer(
+    layer: &Layer,
+    encoders: Option<&EncoderDef>,
+    render_opts: &RenderOpts,
+    canvas: Option<(f32, f32)>,
+    output_dir: &Utf8Path,
+) -> Result<()> {
+    let path = output_dir.join(render_opts.output_filename(&layer.id.0));
     let mut file = File::create(&path)?;
+    write_layer(&mut file, layer, encoders, render_opts, canvas)?;
+    status!("{}", path);
+    Ok(())
+}
 
-    let key_w = 54.0;
+const LAYER_KEY_W: f32 = 54.0;
+
+fn write_layer(
+    file: &mut dyn Write,
+    layer: &Layer,
+    encoders: Option<&EncoderDef>,
+    render_opts: &RenderOpts,
+    canvas: Option<(f32, f32)>,
+) -> Result<()> {
+    let key_w = LAYER_KEY_W;
     let border = 10.0;
 
+    let caption = render_opts.outputs.layer_captions.then(|| {
+        render_opts
+            .layer_titles
+            .get(&layer.id.0)
+            .unwrap_or(&layer.id.0)
+            .to_string()
+    });
+
     write_layer_keys(
-        &mut file,
+        file,
         layer,
         render_opts,
         border,
@@ -176,29 +486,74 @@ fn render_layer(layer: &Layer, render_opts: &RenderOpts, output_dir: &Utf8Path)
         None,
         None,
         None,
+        None,
+        None,
+        canvas,
+        caption.as_deref(),
     )?;
 
+    if let Some(encoders) = encoders {
+        write_encoders(file, layer, encoders, render_opts, border, key_w)?;
+    }
+
     file.write_all("</svg>".as_bytes())?;
 
-    println!("{}", path);
+    Ok(())
+}
+
+fn write_encoders(
+    file: &mut dyn Write,
+    layer: &Layer,
+    encoders: &EncoderDef,
+    render_opts: &RenderOpts,
+    keymap_border: f32,
+    key_w: f32,
+) -> Result<()> {
+    for (encoder, (x, y)) in encoders
+        .encoders
+        .iter()
+        .zip(render_opts.encoder_positions.iter())
+    {
+        let ccw = render_opts.get(&layer.id.0, &encoder.ccw.0).title;
+        let cw = render_opts.get(&layer.id.0, &encoder.cw.0).title;
+        let txt = html_escape::encode_safe(&format!("↺ {ccw}  ↻ {cw}")).to_string();
+
+        let text_x = keymap_border + (x + 0.5) * key_w;
+        let text_y = keymap_border + (y + 0.5) * key_w;
 
+        writeln!(
+            file,
+            r#"<text x="{text_x}" y="{text_y}" text-anchor="middle" dominant-baseline="middle" font-family="sans-serif" font-size="9px" class="encoder">{txt}</text>"#
+        )?;
+    }
     Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
 fn write_layer_keys(
-    file: &mut File,
+    file: &mut dyn Write,
     layer: &Layer,
     render_opts: &RenderOpts,
     keymap_border: f32,
     key_w: f32,
     override_class: Option<&str>,
     override_class_map: Option<HashMap<String, String>>,
+    override_color_map: Option<HashMap<(usize, usize), String>>,
+    override_title_map: Option<HashMap<(usize, usize), String>>,
     blank_class: Option<&str>,
+    canvas: Option<(f32, f32)>,
+    caption: Option<&str>,
 ) -> Result<()> {
-    write_open_svg(file, &render_opts.id, &layer.keys[..], key_w, keymap_border)?;
+    write_open_svg(
+        file,
+        render_opts,
+        &layer.keys[..],
+        key_w,
+        keymap_border,
+        canvas,
+        caption,
+    )?;
 
-    let fallback_color = "#e5c494".to_string();
     for key in layer.keys.iter() {
         let key_opts = render_opts.get(&layer.id.0, &key.id.0);
         let mut class = key_opts.class.as_str();
@@ -211,68 +566,129 @@ fn write_layer_keys(
                 class = x;
             }
         }
-        let inner_color = render_opts.colors.get(class).unwrap_or(&fallback_color);
+        // A color for a specific key only applies if the key keeps its own class.
+        let mut inner_color: &str = match &key_opts.color {
+            Some(color) if class == key_opts.class => color,
+            _ => render_opts.class_color(class),
+        };
+        if let Some(override_map) = &override_color_map {
+            if let Some(x) = override_map.get(&key.matrix_pos) {
+                inner_color = x;
+            }
+        }
 
+        let style = &render_opts.keycap_style;
         let x = keymap_border + key.x * key_w;
         let y = keymap_border + key.y * key_w;
         let w = key_w;
         let h = key_w;
 
-        let (title, hold_title) = if Some(class) == blank_class {
-            ("", None)
+        let override_title = override_title_map
+            .as_ref()
+            .and_then(|x| x.get(&key.matrix_pos));
+        let (title, hold_title, shifted_title) = if let Some(title) = override_title {
+            (title.as_str(), None, None)
+        } else if Some(class) == blank_class {
+            ("", None, None)
         } else {
-            (key_opts.title.as_str(), key_opts.hold_title.as_deref())
+            (
+                key_opts.title.as_str(),
+                key_opts.hold_title.as_deref(),
+                key_opts.shifted_title.as_deref(),
+            )
         };
 
+        let tooltip = render_opts.outputs.key_tooltips.then(|| {
+            let pos = &key.physical_pos;
+            format!("{}: {}, effort {}", key.id, pos.finger, pos.effort)
+        });
+
         KeyRender {
             x,
             y,
             w,
             h,
-            rx: 5.0,
+            rx: style.rx,
             class,
             inner_color,
             title,
             hold_title,
-            border_left: 6.0,
-            border_right: 6.0,
-            border_top: 4.0,
-            border_bottom: 8.0,
+            shifted_title,
+            tooltip,
+            border_left: style.border_left,
+            border_right: style.border_right,
+            border_top: style.border_top,
+            border_bottom: style.border_bottom,
             text_h: 11.0,
+            compact: false,
+            snap_pixels: render_opts.snap_pixels,
+            gradient: render_opts.gradient_keys,
         }
         .render(file)?;
     }
     Ok(())
 }
 
-fn render_combos(
-    combos: &[Combo],
-    base_layer: &Layer,
-    render_opts: &RenderOpts,
-    output_dir: &Utf8Path,
-) -> Result<()> {
-    let mut mid_triple_combos = Vec::new();
-    let mut neighbour_combos = Vec::new();
-    let mut combos_with_separate_layouts = HashMap::new();
-    let mut highlight_groups = HashMap::new();
-    let mut other_combos = Vec::new();
+/// The layer a combo or highlight group is drawn over, see `combo_layers`.
+fn combo_layer<'a>(id: &str, layers: &'a [Layer], render_opts: &RenderOpts) -> Result<&'a Layer> {
+    match render_opts.outputs.combo_layers.get(id) {
+        Some(layer_id) => layers
+            .iter()
+            .find(|layer| &layer.id.0 == layer_id)
+            .ok_or_else(|| eyre!("Unknown layer `{layer_id}` for combo {id}")),
+        None => Ok(&layers[0]),
+    }
+}
+
+/// Combos sorted into the images they're drawn in.
+#[derive(Default)]
+struct ComboGroups<'a> {
+    mid_triple: Vec<&'a Combo>,
+    neighbour: Vec<&'a Combo>,
+    // Ordered maps so the images are processed in the same order every run
+    separate_layouts: BTreeMap<&'a str, Vec<&'a Combo>>,
+    highlight: BTreeMap<&'a str, Vec<&'a Combo>>,
+    other: Vec<(Combo, &'a Layer)>,
+}
+
+fn group_combos<'a>(
+    combos: &'a [Combo],
+    layers: &'a [Layer],
+    render_opts: &'a RenderOpts,
+) -> Result<ComboGroups<'a>> {
+    let base_layer = &layers[0];
+    let mut groups = ComboGroups::default();
 
     for combo in combos {
+        // The combo keys are resolved from the base layer when parsing,
+        // so look them up again on the layer the combo is drawn over.
+        let layer = combo_layer(&combo.id, layers, render_opts)?;
+        let Some(resolved) = combo.on_layer(layer) else {
+            eprintln!(
+                "Warning: skipping combo {} as its keys aren't all on the layer {}",
+                combo.id, layer.id
+            );
+            continue;
+        };
+        let on_base_layer = layer.id == base_layer.id;
+
         let mut handled = false;
 
         // A combo can be contained in several of the separate layouts
         for key in &combo.keys {
             let id = &key.id.0;
-            if combo.keys.len() == 2
+            if on_base_layer
+                && combo.keys.len() == 2
                 && render_opts
                     .outputs
                     .combo_keys_with_separate_imgs
                     .contains(id)
             {
-                combos_with_separate_layouts
-                    .entry(id)
-                    .and_modify(|key_combos: &mut Vec<&Combo>| key_combos.push(combo))
-                    .or_insert_with(|| vec![combo]);
+                groups
+                    .separate_layouts
+                    .entry(id.as_str())
+                    .or_default()
+                    .push(combo);
                 handled = true;
             }
         }
@@ -284,82 +700,233 @@ fn render_combos(
 
         for (group_id, combo_ids) in &render_opts.outputs.combo_highlight_groups {
             if combo_ids.contains(&combo.id) {
-                highlight_groups
-                    .entry(group_id)
-                    .and_modify(|key_combos: &mut Vec<&Combo>| key_combos.push(combo))
-                    .or_insert_with(|| vec![combo]);
+                groups
+                    .highlight
+                    .entry(group_id.as_str())
+                    .or_default()
+                    .push(combo);
                 handled = true;
             }
         }
 
         if !handled {
-            if combo.is_mid_triple() {
-                mid_triple_combos.push(combo);
-            } else if combo.is_horizontal_neighbour() || combo.is_vertical_neighbour() {
-                neighbour_combos.push(combo);
+            if !on_base_layer {
+                groups.other.push((resolved, layer));
+            } else if combo.is_mid_triple() {
+                groups.mid_triple.push(combo);
+            } else if combo.is_cross_hand_thumb()
+                || (render_opts.outputs.physical_neighbours && combo.is_physical_neighbour())
+                || combo.is_horizontal_neighbour()
+                || combo.is_vertical_neighbour()
+                || combo.is_diagonal_neighbour()
+            {
+                groups.neighbour.push(combo);
             } else {
-                other_combos.push(combo);
+                groups.other.push((resolved, base_layer));
             }
         }
     }
+    Ok(groups)
+}
+
+fn render_combos(
+    combos: &[Combo],
+    layers: &[Layer],
+    render_opts: &RenderOpts,
+    output_dir: &Utf8Path,
+) -> Result<()> {
+    let base_layer = &layers[0];
+    let groups = group_combos(combos, layers, render_opts)?;
 
-    println!("Neighbours: {}", neighbour_combos.len());
+    status!("Neighbours: {}", groups.neighbour.len());
     CombosWithLayerRender {
-        combos: &neighbour_combos,
+        combos: &groups.neighbour,
         base_layer,
         render_opts,
-        path: &output_dir.join("neighbour_combos.svg"),
+        path: &output_dir.join(render_opts.output_filename("neighbour_combos")),
     }
     .render()?;
 
-    println!("Triple: {}", mid_triple_combos.len());
+    status!("Triple: {}", groups.mid_triple.len());
     CombosWithLayerRender {
-        combos: &mid_triple_combos,
+        combos: &groups.mid_triple,
         base_layer,
         render_opts,
-        path: &output_dir.join("mid_triple_combos.svg"),
+        path: &output_dir.join(render_opts.output_filename("mid_triple_combos")),
     }
     .render()?;
 
-    for (active_key, combos) in &combos_with_separate_layouts {
-        println!("{}: {}", active_key, combos.len());
+    for (active_key, combos) in &groups.separate_layouts {
+        status!("{}: {}", active_key, combos.len());
         ComboSeparateLayerRender {
             active_key,
             combos,
             base_layer,
             render_opts,
-            path: &output_dir.join(format!("{active_key}.svg")),
+            path: &output_dir.join(render_opts.output_filename(active_key)),
         }
         .render()?;
     }
 
-    println!("Groups: {}", highlight_groups.len());
-    for (group_id, combos) in &highlight_groups {
+    status!("Groups: {}", groups.highlight.len());
+    for (group_id, combos) in &groups.highlight {
+        let layer = combo_layer(group_id, layers, render_opts)?;
+        let combos: Vec<_> = combos
+            .iter()
+            .filter_map(|combo| {
+                let resolved = combo.on_layer(layer);
+                if resolved.is_none() {
+                    eprintln!(
+                        "Warning: skipping combo {} in group {} as its keys aren't all on the layer {}",
+                        combo.id, group_id, layer.id
+                    );
+                }
+                resolved
+            })
+            .collect();
         ComboGroupRender {
-            combos,
-            base_layer,
+            combos: &combos.iter().collect::<Vec<_>>(),
+            base_layer: layer,
             render_opts,
-            path: &output_dir.join(format!("{}.svg", group_id)),
+            path: &output_dir.join(render_opts.output_filename(group_id)),
         }
         .render()?;
     }
 
-    println!("Other: {}", other_combos.len());
-    for combo in &other_combos {
+    status!("Other: {}", groups.other.len());
+    for (combo, layer) in &groups.other {
         ComboSingleRender {
             combo,
+            base_layer: layer,
+            render_opts,
+            path: &output_dir.join(render_opts.output_filename(&combo.id)),
+        }
+        .render()?;
+    }
+
+    if render_opts.outputs.combos_overview {
+        let all: Vec<_> = combos.iter().collect();
+        CombosOverviewRender {
+            combos: &all,
             base_layer,
             render_opts,
-            path: &output_dir.join(format!("{}.svg", combo.id)),
+            path: &output_dir.join(render_opts.output_filename("combos_overview")),
         }
         .render()?;
     }
 
-    println!("Total: {}", combos.len());
+    status!("Total: {}", combos.len());
 
     Ok(())
 }
 
+struct CombosOverviewRender<'a> {
+    combos: &'a [&'a Combo],
+    base_layer: &'a Layer,
+    render_opts: &'a RenderOpts,
+    path: &'a Utf8Path,
+}
+
+impl<'a> CombosOverviewRender<'a> {
+    fn render(&self) -> Result<()> {
+        let mut file = File::create(self.path)?;
+        self.write(&mut file)?;
+        status!("{}", self.path);
+        Ok(())
+    }
+
+    fn write(&self, file: &mut dyn Write) -> Result<()> {
+        let border = 10.0;
+        let key_side = 54.0;
+        let card_w = 3.0 * key_side;
+        let card_h = key_side;
+
+        let style = &self.render_opts.keycap_style;
+
+        let (columns, rows) = grid_size(self.combos.len(), 4);
+        let max_x = columns as f32 * card_w + border * 2.0;
+        let max_y = rows as f32 * card_h + border * 2.0;
+
+        write_svg_header(
+            file,
+            "combos-overview",
+            self.render_opts,
+            (0.0, 0.0, max_x, max_y),
+        )?;
+        write_style(
+            file,
+            r#"    .keycap .border { stroke: black; stroke-width: 1; }
+    .keycap .inner.border { stroke: rgba(0,0,0,.1); }
+    .keycap { font-family: sans-serif; font-size: 11px }
+"#,
+            &self.render_opts.theme.css(),
+        )?;
+        if self.render_opts.gradient_keys {
+            let colors = gradient_colors(self.render_opts);
+            write_gradient_defs(file, colors.iter().map(String::as_str))?;
+        }
+
+        for (i, combo) in self.combos.iter().enumerate() {
+            let row = i / columns;
+            let col = i - row * columns;
+
+            let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
+            let inner_color = output_opts
+                .color
+                .as_deref()
+                .unwrap_or(self.render_opts.class_color(&output_opts.class));
+
+            // The output on the first line and the keys to press on the second
+            let keys: Vec<_> = combo
+                .keys
+                .iter()
+                .map(|key| {
+                    let title = self.render_opts.get(&self.base_layer.id.0, &key.id.0).title;
+                    if title.is_empty() {
+                        key.id.0.clone()
+                    } else {
+                        title.replace('\n', " ")
+                    }
+                })
+                .collect();
+            // The keys of a sequence combo are listed in the order they're pressed
+            let separator = if combo.ordered { " → " } else { " + " };
+            let title = format!(
+                "{}\n{}",
+                output_opts.title.replace('\n', " "),
+                keys.join(separator)
+            );
+
+            KeyRender {
+                x: border + col as f32 * card_w,
+                y: border + row as f32 * card_h,
+                w: card_w,
+                h: card_h,
+                rx: style.rx,
+                class: &output_opts.class,
+                inner_color,
+                title: &title,
+                hold_title: None,
+                shifted_title: None,
+                tooltip: None,
+                border_left: style.border_left,
+                border_right: style.border_right,
+                border_top: style.border_top,
+                border_bottom: style.border_bottom,
+                text_h: 14.0,
+                compact: false,
+                snap_pixels: self.render_opts.snap_pixels,
+                gradient: self.render_opts.gradient_keys,
+            }
+            .render(file)?;
+        }
+
+        file.write_all("</svg>".as_bytes())?;
+
+        Ok(())
+    }
+}
+
 struct CombosWithLayerRender<'a> {
     combos: &'a [&'a Combo],
     base_layer: &'a Layer,
@@ -370,12 +937,17 @@ struct CombosWithLayerRender<'a> {
 impl<'a> CombosWithLayerRender<'a> {
     fn render(&self) -> Result<()> {
         let mut file = File::create(self.path)?;
+        self.write(&mut file)?;
+        status!("{}", self.path);
+        Ok(())
+    }
 
+    fn write(&self, file: &mut dyn Write) -> Result<()> {
         let key_w = 54.0;
         let keymap_border = 10.0;
 
         write_layer_keys(
-            &mut file,
+            file,
             self.base_layer,
             self.render_opts,
             keymap_border,
@@ -388,37 +960,45 @@ impl<'a> CombosWithLayerRender<'a> {
             ),
             None,
             None,
+            None,
+            None,
+            None,
+            None,
         )?;
 
-        let fallback_color = "#e5c494".to_string();
+        write_order_badges(file, self.combos, keymap_border, key_w)?;
+
         writeln!(file, r#"<g class="combos">"#)?;
         for combo in self.combos {
             let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
 
             let title = &output_opts.title;
             let class = &output_opts.class;
-            let inner_color = self
-                .render_opts
-                .colors
-                .get(class)
-                .unwrap_or(&fallback_color);
+            let inner_color = output_opts
+                .color
+                .as_deref()
+                .unwrap_or(self.render_opts.class_color(class));
 
             ComboRender {
                 combo,
                 title,
+                title_w: combo.output_display_length(self.render_opts, &self.base_layer.id),
+                hold_title: output_opts.hold_title.as_deref(),
                 class,
                 inner_color,
                 keymap_border,
+                physical_neighbours: self.render_opts.outputs.physical_neighbours,
+                keycap_style: &self.render_opts.keycap_style,
+                snap_pixels: self.render_opts.snap_pixels,
+                gradient: self.render_opts.gradient_keys,
             }
-            .render(&mut file)?;
+            .render(file)?;
         }
 
         writeln!(file, r#"</g>"#)?;
 
         file.write_all("</svg>".as_bytes())?;
 
-        println!("{}", self.path);
-
         Ok(())
     }
 }
@@ -426,25 +1006,56 @@ impl<'a> CombosWithLayerRender<'a> {
 struct ComboRender<'a> {
     combo: &'a Combo,
     title: &'a str,
+    // See `Combo::output_display_length`
+    title_w: f32,
+    hold_title: Option<&'a str>,
     class: &'a str,
     inner_color: &'a str,
     keymap_border: f32,
+    // Prefer `Combo::is_physical_neighbour` over the matrix neighbours
+    physical_neighbours: bool,
+    keycap_style: &'a KeycapStyle,
+    snap_pixels: bool,
+    gradient: bool,
 }
 
 impl<'a> ComboRender<'a> {
-    fn render(&self, file: &mut File) -> Result<()> {
+    fn render(&self, file: &mut dyn Write) -> Result<()> {
         let key_w = 54.0;
         let combo_char_w = 5.0;
         let text_padding = 10.0;
-        let combo_key_h = 16.0;
+        let combo_key_h = if self.hold_title.is_some() {
+            16.0 + COMPACT_HOLD_TITLE_H
+        } else {
+            16.0
+        };
 
-        let calc_w = |title: &str, min_w: f32| {
-            let calc = title.chars().count() as f32 * combo_char_w + text_padding;
+        let calc_w = |min_w: f32| {
+            let calc = self.title_w * combo_char_w + text_padding;
             calc.max(min_w)
         };
 
-        if self.combo.is_vertical_neighbour() {
-            let w = calc_w(self.title, 28.0);
+        if self.combo.is_cross_hand_thumb() {
+            let (left, right) = if self.combo.keys[0].x < self.combo.keys[1].x {
+                (&self.combo.keys[0], &self.combo.keys[1])
+            } else {
+                (&self.combo.keys[1], &self.combo.keys[0])
+            };
+
+            // Span the gap between the thumb keys.
+            let gap_w = (right.x - left.x - 1.0) * key_w;
+            let w = calc_w(gap_w.max(28.0));
+
+            let mid_x = (left.x + 1.0 + right.x) / 2.0 * key_w;
+            let mid_y = ((left.y + right.y) / 2.0 + 0.5) * key_w;
+            let x = self.keymap_border + mid_x - w / 2.0;
+            let y = self.keymap_border + mid_y - combo_key_h / 2.0;
+
+            self.render_key(x, y, w, combo_key_h, file)?;
+        } else if self.physical_neighbours && self.combo.is_physical_neighbour() {
+            self.render_between_centers(key_w, calc_w(28.0), combo_key_h, file)?;
+        } else if self.combo.is_vertical_neighbour() {
+            let w = calc_w(28.0);
 
             let a = &self.combo.keys[0];
             let b = &self.combo.keys[1];
@@ -454,7 +1065,7 @@ impl<'a> ComboRender<'a> {
 
             self.render_key(x, y, w, combo_key_h, file)?;
         } else if self.combo.is_horizontal_neighbour() {
-            let w = calc_w(self.title, 28.0);
+            let w = calc_w(28.0);
 
             let a = &self.combo.keys[0];
             let b = &self.combo.keys[1];
@@ -471,8 +1082,10 @@ impl<'a> ComboRender<'a> {
             let x = self.keymap_border + a.x.max(b.x) * key_w - w / 2.0;
 
             self.render_key(x, y, w, combo_key_h, file)?;
+        } else if self.combo.is_diagonal_neighbour() {
+            self.render_between_centers(key_w, calc_w(28.0), combo_key_h, file)?;
         } else if self.combo.is_mid_triple() {
-            let w = calc_w(self.title, 80.0);
+            let w = calc_w(80.0);
 
             let a = &self.combo.keys[0];
             let b = &self.combo.keys[1];
@@ -494,10 +1107,30 @@ impl<'a> ComboRender<'a> {
         Ok(())
     }
 
-    fn render_key(&self, x: f32, y: f32, w: f32, h: f32, file: &mut File) -> Result<()> {
-        let border_x = 1.5;
-        let border_top = 1.0;
-        let border_bottom = 2.5;
+    // At the midpoint of the key centers.
+    fn render_between_centers(
+        &self,
+        key_w: f32,
+        w: f32,
+        h: f32,
+        file: &mut dyn Write,
+    ) -> Result<()> {
+        let a = &self.combo.keys[0];
+        let b = &self.combo.keys[1];
+
+        let mid_x = (a.x + b.x) / 2.0 * key_w + key_w / 2.0;
+        let mid_y = (a.y + b.y) / 2.0 * key_w + key_w / 2.0;
+        let x = self.keymap_border + mid_x - w / 2.0;
+        let y = self.keymap_border + mid_y - h / 2.0;
+
+        self.render_key(x, y, w, h, file)
+    }
+
+    fn render_key(&self, x: f32, y: f32, w: f32, h: f32, file: &mut dyn Write) -> Result<()> {
+        let style = self.keycap_style;
+        let border_x = style.combo_border_x;
+        let border_top = style.combo_border_top;
+        let border_bottom = style.combo_border_bottom;
 
         let combo_text_h = 8.0;
 
@@ -506,16 +1139,21 @@ impl<'a> ComboRender<'a> {
             y,
             w,
             h,
-            rx: 4.0,
+            rx: style.combo_rx,
             class: self.class,
             inner_color: self.inner_color,
             title: self.title,
-            hold_title: None,
+            hold_title: self.hold_title,
+            shifted_title: None,
+            tooltip: None,
             border_left: border_x,
             border_right: border_x,
             border_top,
             border_bottom,
             text_h: combo_text_h,
+            compact: true,
+            snap_pixels: self.snap_pixels,
+            gradient: self.gradient,
         }
         .render(file)?;
         Ok(())
@@ -532,6 +1170,13 @@ struct ComboSeparateLayerRender<'a> {
 
 impl<'a> ComboSeparateLayerRender<'a> {
     fn render(&self) -> Result<()> {
+        let mut file = File::create(self.path)?;
+        self.write(&mut file)?;
+        status!("{}", self.path);
+        Ok(())
+    }
+
+    fn write(&self, file: &mut dyn Write) -> Result<()> {
         let mut layer = self.base_layer.clone();
 
         let mut class_overrides = HashMap::new();
@@ -564,8 +1209,6 @@ impl<'a> ComboSeparateLayerRender<'a> {
                 .clone(),
         );
 
-        let mut file = File::create(self.path)?;
-
         let key_w = 54.0;
         let keymap_border = 10.0;
 
@@ -576,20 +1219,22 @@ impl<'a> ComboSeparateLayerRender<'a> {
             .as_str();
 
         write_layer_keys(
-            &mut file,
+            file,
             &layer,
             self.render_opts,
             keymap_border,
             key_w,
             Some(background_layer_class),
             Some(class_overrides),
+            None,
+            None,
             Some(background_layer_class),
+            None,
+            None,
         )?;
 
         writeln!(file, r"</svg>")?;
 
-        println!("{}", self.path);
-
         Ok(())
     }
 }
@@ -603,16 +1248,48 @@ struct ComboGroupRender<'a> {
 
 impl<'a> ComboGroupRender<'a> {
     fn render(&self) -> Result<()> {
-        let mut class_overrides = HashMap::new();
-        for combo in self.combos {
-            let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
-            let class = output_opts.class.to_string();
-            for key in &combo.keys {
-                class_overrides.insert(key.id.0.clone(), class.clone());
-            }
-        }
-
         let mut file = File::create(self.path)?;
+        self.write(&mut file)?;
+        status!("{}", self.path);
+        Ok(())
+    }
+
+    // The position and size of the output of a combo.
+    fn label_rect(&self, combo: &Combo, key_w: f32) -> (f32, f32, f32, f32) {
+        let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
+        let h = if output_opts.hold_title.is_some() {
+            18.0 + COMPACT_HOLD_TITLE_H
+        } else {
+            18.0
+        };
+        let w = if combo.keys.len() == 5 { 160.0 } else { 80.0 };
+        let x = if combo.keys.len() == 5 {
+            let dist = h;
+            if combo.keys[0].physical_pos.finger.half == MatrixHalf::Left {
+                (combo.keys[0].x + 1.0) * key_w + dist
+            } else {
+                combo.keys[4].x * key_w - w
+            }
+        } else {
+            (combo.min_x() + (combo.max_x() - combo.min_x()) / 2.0) * key_w
+        };
+        let y = if (combo.max_x() - combo.min_x()) > 3.0 {
+            (combo.min_y() + (combo.max_y() - combo.min_y()) / 2.0 + 1.0) * key_w - h
+        } else {
+            combo.min_y() * key_w - h * 0.6
+        };
+        (x, y, w, h)
+    }
+
+    fn write(&self, file: &mut dyn Write) -> Result<()> {
+        let mut class_overrides = HashMap::new();
+        for combo in self.combos {
+            let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
+            let class = output_opts.class.to_string();
+            for key in &combo.keys {
+                class_overrides.insert(key.id.0.clone(), class.clone());
+            }
+        }
 
         let key_w = 54.0;
         let keymap_border = 10.0;
@@ -625,76 +1302,137 @@ impl<'a> ComboGroupRender<'a> {
             .as_str();
 
         write_layer_keys(
-            &mut file,
+            file,
             self.base_layer,
             self.render_opts,
             keymap_border,
             key_w,
             Some(background_layer_class),
             Some(class_overrides),
+            None,
+            None,
             Some(background_layer_class),
+            None,
+            None,
         )?;
 
-        let fallback_color = "#e5c494".to_string();
+        // All lines are drawn before the outputs, so they don't cross the outputs
+        // of the other combos
+        if self.render_opts.outputs.combo_lines {
+            for combo in self.combos {
+                let (x, y, w, h) = self.label_rect(combo, key_w);
+                let label = (x + w / 2.0, y + h / 2.0);
+                write_combo_lines(file, combo, keymap_border, key_w, label)?;
+            }
+        }
+        write_order_badges(file, self.combos, keymap_border, key_w)?;
+
         for combo in self.combos {
             let output_opts = self.render_opts.get(&self.base_layer.id.0, &combo.output);
             let class = output_opts.class.to_string();
-            let inner_color = self
-                .render_opts
-                .colors
-                .get(&class)
-                .unwrap_or(&fallback_color);
-
-            let border_x = 1.5;
-            let border_top = 1.0;
-            let border_bottom = 2.5;
-            let h = 18.0;
-            let w = if combo.keys.len() == 5 { 160.0 } else { 80.0 };
-            let x = if combo.keys.len() == 5 {
-                let dist = h;
-                if combo.keys[0].physical_pos.finger.half == MatrixHalf::Left {
-                    (combo.keys[0].x + 1.0) * key_w + dist
-                } else {
-                    combo.keys[4].x * key_w - w
-                }
-            } else {
-                (combo.min_x() + (combo.max_x() - combo.min_x()) / 2.0) * key_w
-            };
-            let y = if (combo.max_x() - combo.min_x()) > 3.0 {
-                (combo.min_y() + (combo.max_y() - combo.min_y()) / 2.0 + 1.0) * key_w - h
-            } else {
-                combo.min_y() * key_w - h * 0.6
-            };
+            let inner_color = output_opts
+                .color
+                .as_deref()
+                .unwrap_or(self.render_opts.class_color(&class));
 
-            let title = &output_opts.title.replace("\n", "");
+            let style = &self.render_opts.keycap_style;
+            let border_x = style.combo_border_x;
+            let border_top = style.combo_border_top;
+            let border_bottom = style.combo_border_bottom;
+            let hold_title = output_opts.hold_title.as_deref();
+            let (x, y, w, h) = self.label_rect(combo, key_w);
+
+            let title = &combo_title(&output_opts.title);
 
             KeyRender {
                 x,
                 y,
                 w,
                 h,
-                rx: 4.0,
+                rx: style.combo_rx,
                 class: &class,
                 inner_color,
                 title,
-                hold_title: None,
+                hold_title,
+                shifted_title: None,
+                tooltip: None,
                 border_left: border_x,
                 border_right: border_x,
                 border_top,
                 border_bottom,
                 text_h: combo_text_h,
+                compact: true,
+                snap_pixels: self.render_opts.snap_pixels,
+                gradient: self.render_opts.gradient_keys,
             }
-            .render(&mut file)?;
+            .render(file)?;
         }
 
         writeln!(file, r"</svg>")?;
 
-        println!("{}", self.path);
-
         Ok(())
     }
 }
 
+// Lines from the center of each key of the combo to the center of its output,
+// drawn before the output so they end below it. Style with `.combo-line`.
+fn write_combo_lines(
+    file: &mut dyn Write,
+    combo: &Combo,
+    keymap_border: f32,
+    key_w: f32,
+    (label_x, label_y): (f32, f32),
+) -> Result<()> {
+    writeln!(file, r#"<g class="combo-lines">"#)?;
+    for key in &combo.keys {
+        let x = keymap_border + key.x * key_w + key_w / 2.0;
+        let y = keymap_border + key.y * key_w + key_w / 2.0;
+        writeln!(
+            file,
+            r#"<line x1="{x}" y1="{y}" x2="{label_x}" y2="{label_y}" class="combo-line"/>"#
+        )?;
+    }
+    writeln!(file, "</g>")?;
+    Ok(())
+}
+
+// Combo keys only fit a single line of title, the hold title is drawn separately.
+fn combo_title(title: &str) -> String {
+    title.lines().collect::<Vec<_>>().join(" ")
+}
+
+// Numbers the keys of the sequence combos in the order they're pressed,
+// with the style of the badges if there are any.
+fn write_order_badges(
+    file: &mut dyn Write,
+    combos: &[&Combo],
+    keymap_border: f32,
+    key_w: f32,
+) -> Result<()> {
+    if !combos.iter().any(|x| x.ordered) {
+        return Ok(());
+    }
+    write_style(
+        file,
+        r#"    .order circle { fill: white; stroke: black }
+    .order text { font-family: sans-serif; font-size: 8px }
+"#,
+        "",
+    )?;
+    for combo in combos.iter().filter(|x| x.ordered) {
+        for (i, key) in combo.keys.iter().enumerate() {
+            let x = keymap_border + key.x * key_w + 9.0;
+            let y = keymap_border + key.y * key_w + 9.0;
+            writeln!(
+                file,
+                r#"<g class="order"><circle cx="{x}" cy="{y}" r="6"/><text x="{x}" y="{y}" text-anchor="middle" dominant-baseline="central">{}</text></g>"#,
+                i + 1
+            )?;
+        }
+    }
+    Ok(())
+}
+
 struct ComboSingleRender<'a> {
     combo: &'a Combo,
     base_layer: &'a Layer,
@@ -704,6 +1442,13 @@ struct ComboSingleRender<'a> {
 
 impl<'a> ComboSingleRender<'a> {
     fn render(&self) -> Result<()> {
+        let mut file = File::create(self.path)?;
+        self.write(&mut file)?;
+        status!("{}", self.path);
+        Ok(())
+    }
+
+    fn write(&self, file: &mut dyn Write) -> Result<()> {
         let mut class_overrides = HashMap::new();
         let output_opts = self
             .render_opts
@@ -713,8 +1458,6 @@ impl<'a> ComboSingleRender<'a> {
             class_overrides.insert(key.id.0.clone(), class.clone());
         }
 
-        let mut file = File::create(self.path)?;
-
         let key_w = 54.0;
         let keymap_border = 10.0;
         let combo_text_h = 8.0;
@@ -726,27 +1469,35 @@ impl<'a> ComboSingleRender<'a> {
             .as_str();
 
         write_layer_keys(
-            &mut file,
+            file,
             self.base_layer,
             self.render_opts,
             keymap_border,
             key_w,
             Some(background_layer_class),
             Some(class_overrides),
+            None,
+            None,
             Some(background_layer_class),
+            None,
+            None,
         )?;
 
-        let fallback_color = "#e5c494".to_string();
-        let inner_color = self
-            .render_opts
-            .colors
-            .get(&class)
-            .unwrap_or(&fallback_color);
-
-        let border_x = 1.5;
-        let border_top = 1.0;
-        let border_bottom = 2.5;
-        let h = 18.0;
+        let inner_color = output_opts
+            .color
+            .as_deref()
+            .unwrap_or(self.render_opts.class_color(&class));
+
+        let style = &self.render_opts.keycap_style;
+        let border_x = style.combo_border_x;
+        let border_top = style.combo_border_top;
+        let border_bottom = style.combo_border_bottom;
+        let hold_title = output_opts.hold_title.as_deref();
+        let h = if hold_title.is_some() {
+            18.0 + COMPACT_HOLD_TITLE_H
+        } else {
+            18.0
+        };
         let w = if self.combo.keys.len() == 5 {
             120.0
         } else {
@@ -762,30 +1513,40 @@ impl<'a> ComboSingleRender<'a> {
             self.combo.min_y() * key_w - h * 0.6
         };
 
-        let title = &output_opts.title.replace("\n", "");
+        if self.render_opts.outputs.combo_lines {
+            let label = (x + w / 2.0, y + h / 2.0);
+            write_combo_lines(file, self.combo, keymap_border, key_w, label)?;
+        }
+        // Above the lines, so they don't hide the numbers
+        write_order_badges(file, &[self.combo], keymap_border, key_w)?;
+
+        let title = &combo_title(&output_opts.title);
 
         KeyRender {
             x,
             y,
             w,
             h,
-            rx: 4.0,
+            rx: style.combo_rx,
             class: &class,
             inner_color,
             title,
-            hold_title: None,
+            hold_title,
+            shifted_title: None,
+            tooltip: None,
             border_left: border_x,
             border_right: border_x,
             border_top,
             border_bottom,
             text_h: combo_text_h,
+            compact: true,
+            snap_pixels: self.render_opts.snap_pixels,
+            gradient: self.render_opts.gradient_keys,
         }
-        .render(&mut file)?;
+        .render(file)?;
 
         writeln!(file, r"</svg>")?;
 
-        println!("{}", self.path);
-
         Ok(())
     }
 }
@@ -795,7 +1556,7 @@ fn render_effort_grid(
     render_opts: &RenderOpts,
     output_dir: &Utf8Path,
 ) -> Result<()> {
-    let path = output_dir.join("effort_grid.svg");
+    let path = output_dir.join(render_opts.output_filename("effort_grid"));
     let mut file = File::create(&path)?;
 
     let mut override_class_map = HashMap::new();
@@ -824,13 +1585,312 @@ fn render_effort_grid(
         None,
         Some(override_class_map),
         None,
+        None,
+        None,
+        None,
+        None,
+    )?;
+
+    file.write_all("</svg>".as_bytes())?;
+    status!("{}", path);
+    Ok(())
+}
+
+fn render_effort_heatmap(
+    base_layer: &Layer,
+    render_opts: &RenderOpts,
+    output_dir: &Utf8Path,
+) -> Result<()> {
+    let path = output_dir.join(render_opts.output_filename("effort_heatmap"));
+    let mut file = File::create(&path)?;
+
+    let efforts = base_layer.keys.iter().map(|key| key.physical_pos.effort);
+    let min_effort = efforts.clone().min().unwrap_or(0);
+    let max_effort = efforts.max().unwrap_or(0);
+
+    let override_color_map = base_layer
+        .keys
+        .iter()
+        .map(|key| {
+            let color = effort_color(key.physical_pos.effort, min_effort, max_effort);
+            (key.matrix_pos, color)
+        })
+        .collect();
+
+    let key_w = 54.0;
+    let border = 10.0;
+
+    write_layer_keys(
+        &mut file,
+        base_layer,
+        render_opts,
+        border,
+        key_w,
+        None,
+        None,
+        Some(override_color_map),
+        None,
+        None,
+        None,
+        None,
+    )?;
+
+    file.write_all("</svg>".as_bytes())?;
+    status!("{}", path);
+    Ok(())
+}
+
+/// How `render_usage` annotates the keys.
+#[derive(Debug, Default)]
+pub struct UsageOpts {
+    // Show the share of the presses on the layer instead of the number of presses
+    pub percent: bool,
+    // Color the keys by how often they're pressed
+    pub heatmap: bool,
+}
+
+/// Renders a layer, the base layer by default, with the number of times each key
+/// was pressed in a keylog. The keys of combos count as pressed.
+pub fn render_usage(
+    info: &InputInfo,
+    stats: &KeylogStats,
+    layer_id: Option<&str>,
+    opts: &UsageOpts,
+    path: &Utf8Path,
+) -> Result<()> {
+    let info = &with_hand_gap(info);
+    let layer = match layer_id {
+        Some(id) => find_layer(info, id)?,
+        None => &info.keymap.layers[0],
+    };
+    let mut file = File::create(path)?;
+    write_usage(
+        &mut file,
+        layer,
+        &info.render_opts,
+        &stats.layer_position_frequency(&layer.id),
+        opts,
+    )?;
+    status!("{}", path);
+    Ok(())
+}
+
+fn write_usage(
+    file: &mut dyn Write,
+    layer: &Layer,
+    render_opts: &RenderOpts,
+    presses: &HashMap<(usize, usize), u32>,
+    opts: &UsageOpts,
+) -> Result<()> {
+    let key_w = LAYER_KEY_W;
+    let border = 10.0;
+    let count = |key: &Key| presses.get(&key.physical_pos.pos()).copied().unwrap_or(0);
+    let total: u32 = presses.values().sum();
+
+    let override_color_map = opts.heatmap.then(|| {
+        let max = layer.keys.iter().map(count).max().unwrap_or(0);
+        layer
+            .keys
+            .iter()
+            .map(|key| (key.matrix_pos, effort_color(count(key), 0, max)))
+            .collect()
+    });
+
+    write_layer_keys(
+        file,
+        layer,
+        render_opts,
+        border,
+        key_w,
+        None,
+        None,
+        override_color_map,
+        None,
+        None,
+        None,
+        None,
     )?;
 
+    // In the bottom left corner, clear of the hold title in the middle
+    let style = &render_opts.keycap_style;
+    for key in &layer.keys {
+        let label = if opts.percent {
+            format!("{:.1}%", count(key) as f32 / total.max(1) as f32 * 100.0)
+        } else {
+            count(key).to_string()
+        };
+        let x = border + key.x * key_w + style.border_left + 2.0;
+        let y = border + (key.y + 1.0) * key_w - style.border_bottom - 2.0;
+        writeln!(
+            file,
+            r#"<text x="{x}" y="{y}" font-family="sans-serif" font-size="8px" class="presses">{label}</text>"#
+        )?;
+    }
+
     file.write_all("</svg>".as_bytes())?;
-    println!("{}", path);
     Ok(())
 }
 
+// Green for the lowest effort, red for the highest.
+fn effort_color(effort: u32, min_effort: u32, max_effort: u32) -> String {
+    let t = if max_effort > min_effort {
+        (effort - min_effort) as f32 / (max_effort - min_effort) as f32
+    } else {
+        0.0
+    };
+    let hsv = Hsv::new(120.0 * (1.0 - t), 0.45, 0.9);
+    let rgb: Srgb = hsv.into_color();
+    format!("#{:x}", Srgb::<u8>::from(rgb))
+}
+
+fn render_matrix_debug(
+    base_layer: &Layer,
+    render_opts: &RenderOpts,
+    output_dir: &Utf8Path,
+) -> Result<()> {
+    let path = output_dir.join(render_opts.output_filename("matrix_debug"));
+    let mut file = File::create(&path)?;
+    write_matrix_debug(&mut file, base_layer, render_opts)?;
+    status!("{}", path);
+    Ok(())
+}
+
+// Labels each key with its matrix position and its position in the physical layout,
+// both as row,col, to help find mismatches between them.
+fn write_matrix_debug(
+    file: &mut dyn Write,
+    base_layer: &Layer,
+    render_opts: &RenderOpts,
+) -> Result<()> {
+    let override_title_map = base_layer
+        .keys
+        .iter()
+        .map(|key| {
+            let (row, col) = key.matrix_pos;
+            let pos = &key.physical_pos;
+            (
+                key.matrix_pos,
+                format!("m {row},{col}\np {},{}", pos.row, pos.col),
+            )
+        })
+        .collect();
+
+    let key_w = 54.0;
+    let border = 10.0;
+
+    write_layer_keys(
+        file,
+        base_layer,
+        render_opts,
+        border,
+        key_w,
+        None,
+        None,
+        None,
+        Some(override_title_map),
+        None,
+        None,
+        None,
+    )?;
+    file.write_all("</svg>".as_bytes())?;
+    Ok(())
+}
+
+fn render_finger_zones(
+    base_layer: &Layer,
+    render_opts: &RenderOpts,
+    output_dir: &Utf8Path,
+) -> Result<()> {
+    let path = output_dir.join(render_opts.output_filename("finger_zones"));
+    let mut file = File::create(&path)?;
+
+    let override_color_map = base_layer
+        .keys
+        .iter()
+        .map(|key| {
+            (
+                key.matrix_pos,
+                finger_color(render_opts, key.physical_pos.finger),
+            )
+        })
+        .collect();
+
+    let key_w = 54.0;
+    let border = 10.0;
+
+    write_layer_keys(
+        &mut file,
+        base_layer,
+        render_opts,
+        border,
+        key_w,
+        None,
+        None,
+        Some(override_color_map),
+        None,
+        None,
+        None,
+        None,
+    )?;
+
+    file.write_all("</svg>".as_bytes())?;
+    status!("{}", path);
+
+    let legend_path = output_dir.join(render_opts.output_filename("finger_zones_legend"));
+    let mut legend_file = File::create(&legend_path)?;
+
+    let fingers: BTreeSet<FingerAssignment> = base_layer
+        .keys
+        .iter()
+        .map(|key| key.physical_pos.finger)
+        .collect();
+    let legend: Vec<_> = fingers
+        .into_iter()
+        .map(|finger| {
+            (
+                format!("{} {}", finger.half, finger.finger),
+                finger_color(render_opts, finger),
+            )
+        })
+        .collect();
+    let items: Vec<_> = legend
+        .iter()
+        .map(|(title, color)| LegendItem {
+            class: "finger_zone",
+            title,
+            color,
+        })
+        .collect();
+
+    write_legend(&mut legend_file, &items, render_opts)?;
+    status!("{}", legend_path);
+
+    Ok(())
+}
+
+fn finger_color(render_opts: &RenderOpts, finger: FingerAssignment) -> String {
+    let id = finger.to_string();
+    if let Some(color) = render_opts.finger_colors.get(&id) {
+        return color.clone();
+    }
+
+    let base = match finger.finger {
+        Finger::Pinky => "#8da0cb",
+        Finger::Ring => "#66c2a5",
+        Finger::Middle => "#fc8d62",
+        Finger::Index => "#e78ac3",
+        Finger::Thumb => "#a6d854",
+    };
+    match finger.half {
+        MatrixHalf::Left => base.to_string(),
+        MatrixHalf::Right => {
+            // Slightly darker to tell the hands apart
+            let color = lighten_color(Srgb::from_str(base).unwrap().into(), -0.15);
+            format!("#{:x}", Srgb::<u8>::from(color))
+        }
+    }
+}
+
 struct KeyRender<'a> {
     x: f32,
     y: f32,
@@ -842,47 +1902,79 @@ struct KeyRender<'a> {
     title: &'a str,
     text_h: f32,
     hold_title: Option<&'a str>,
+    // Drawn small in the top-right corner, above a single line title.
+    shifted_title: Option<&'a str>,
+    // Shown on hover as a `<title>` of the key group.
+    tooltip: Option<String>,
     border_left: f32,
     border_right: f32,
     border_top: f32,
     border_bottom: f32,
+    // Small keys, such as combos, fit the hold title inside the key.
+    compact: bool,
+    // Round the rects to whole pixels, see `RenderOpts::snap_pixels`.
+    snap_pixels: bool,
+    // Fill with the gradient of the inner color, see `write_gradient_defs`.
+    gradient: bool,
 }
 
+// Extra height given to a compact key to make room for the hold title.
+const COMPACT_HOLD_TITLE_H: f32 = 7.0;
+// Approximate width of a character relative to the font size.
+const CHAR_W_PER_FONT_SIZE: f32 = 0.6;
+
 impl<'a> KeyRender<'a> {
-    fn render(&self, file: &mut File) -> Result<()> {
-        let outer_x = self.x;
-        let outer_y = self.y;
-        let outer_w = self.w;
-        let outer_h = self.h;
+    fn render(&self, file: &mut dyn Write) -> Result<()> {
+        let snap = |v: f32| if self.snap_pixels { v.round() } else { v };
+
+        let outer_x = snap(self.x);
+        let outer_y = snap(self.y);
+        let outer_w = snap(self.w);
+        let outer_h = snap(self.h);
 
-        let inner_w = outer_w - (self.border_left + self.border_right);
-        let inner_h = outer_h - (self.border_top + self.border_bottom);
+        let inner_w = snap(outer_w - (self.border_left + self.border_right));
+        let inner_h = snap(outer_h - (self.border_top + self.border_bottom));
 
-        let inner_x = outer_x + self.border_left;
-        let inner_y = outer_y + self.border_top;
+        let inner_x = snap(outer_x + self.border_left);
+        let inner_y = snap(outer_y + self.border_top);
 
         let inner_color = self.inner_color;
+        // Falls back to the flat color for colors without a gradient, such as heatmaps
+        let inner_fill = if self.gradient {
+            format!("url(#{}) {inner_color}", gradient_id(inner_color))
+        } else {
+            inner_color.to_string()
+        };
         let outer_color = lighten_color(Srgb::from_str(inner_color).unwrap().into(), -0.03);
         let outer_color = format!("#{:x}", Srgb::<u8>::from(outer_color));
 
         let class = self.class;
         let rx = self.rx;
 
+        writeln!(file, r#"    <g class="keycap {class}">"#)?;
+        if let Some(tooltip) = &self.tooltip {
+            let tooltip = html_escape::encode_safe(tooltip);
+            writeln!(file, "      <title>{tooltip}</title>")?;
+        }
         writeln!(
             file,
-            r##"    <g class="keycap {class}">
-      <rect x="{outer_x}" y="{outer_y}"
+            r##"      <rect x="{outer_x}" y="{outer_y}"
             width="{outer_w}" height="{outer_h}"
             rx="{rx}" fill="{outer_color}" class="outer border"/>
       <rect x="{inner_x}" y="{inner_y}"
             width="{inner_w}" height="{inner_h}"
-            rx="{rx}" fill="{inner_color}" class="inner border"/>
+            rx="{rx}" fill="{inner_fill}" class="inner border"/>
 "##,
         )?;
 
+        let compact_hold_title = self.hold_title.filter(|_| self.compact);
+
         let text = self.title.lines().collect::<Vec<_>>();
         if !text.is_empty() {
-            let y_offset = (text.len() - 1) as f32 * self.text_h / 2.0;
+            let mut y_offset = (text.len() - 1) as f32 * self.text_h / 2.0;
+            if compact_hold_title.is_some() {
+                y_offset += COMPACT_HOLD_TITLE_H / 2.0;
+            }
             let text_x = inner_x + inner_w / 2.0;
             let text_y = inner_y + inner_h / 2.0 - y_offset;
 
@@ -903,7 +1995,31 @@ impl<'a> KeyRender<'a> {
             writeln!(file, "</text>")?;
         }
 
-        if let Some(subtxt) = self.hold_title {
+        if let Some(shifted) = self.shifted_title {
+            let text_x = inner_x + inner_w - 2.0;
+            let text_y = inner_y + 2.0;
+            let shifted = html_escape::encode_safe(shifted);
+
+            writeln!(
+                file,
+                r#"<text x="{text_x}" y="{text_y}" text-anchor="end" dominant-baseline="hanging" class="shifted">{shifted}</text>"#
+            )?;
+        }
+
+        if let Some(subtxt) = compact_hold_title {
+            let text_x = inner_x + inner_w / 2.0;
+            let text_y = inner_y + inner_h - 1.0;
+            // Shrink the font so a long hold title doesn't overflow the key.
+            let max_size = COMPACT_HOLD_TITLE_H - 1.0;
+            let fit_size = inner_w / (display_width(subtxt) * CHAR_W_PER_FONT_SIZE);
+            let font_size = max_size.min(fit_size);
+            let subtxt = html_escape::encode_safe(subtxt);
+
+            writeln!(
+                file,
+                r#"<text x="{text_x}" y="{text_y}" text-anchor="middle" class="sub" style="font-size: {font_size:.1}px">{subtxt}</text>"#
+            )?;
+        } else if let Some(subtxt) = self.hold_title {
             let text_x = inner_x + inner_w / 2.0;
             let text_y = inner_y + inner_w + 6.2;
 
@@ -917,14 +2033,816 @@ impl<'a> KeyRender<'a> {
     }
 }
 
-fn lighten_color(rgb: Srgb, amount: f32) -> Srgb {
-    // Convert RGB to HSV
-    let hsv: Hsv = rgb.into_color();
+// How much lighter the top and darker the bottom of a gradient key is.
+const GRADIENT_STEP: f32 = 0.06;
+
+// The colors keys can be drawn with, used to define the gradients up front.
+fn gradient_colors(render_opts: &RenderOpts) -> BTreeSet<String> {
+    let key_colors = render_opts
+        .default_keys
+        .values()
+        .chain(
+            render_opts
+                .layer_keys
+                .values()
+                .flat_map(|keys| keys.values()),
+        )
+        .filter_map(|x| x.color.clone());
+    let finger_colors = FingerAssignment::all().map(|x| finger_color(render_opts, x));
+
+    render_opts
+        .colors
+        .values()
+        .cloned()
+        .chain([render_opts.default_color.clone()])
+        .chain(key_colors)
+        .chain(finger_colors)
+        .collect()
+}
+
+fn gradient_id(color: &str) -> String {
+    format!(
+        "key-gradient-{}",
+        color.trim_start_matches('#').to_lowercase()
+    )
+}
+
+// A `<linearGradient>` from a lighter to a darker shade of each color,
+// referenced by `KeyRender` as the fill of the inner rect.
+fn write_gradient_defs<'a>(
+    file: &mut dyn Write,
+    colors: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    let by_id: BTreeMap<String, &str> = colors
+        .into_iter()
+        .map(|color| (gradient_id(color), color))
+        .collect();
+
+    writeln!(file, "  <defs>")?;
+    for (id, color) in by_id {
+        let Ok(rgb) = Srgb::from_str(color) else {
+            continue;
+        };
+        let shade = |amount| {
+            let x = lighten_color(rgb.into(), amount);
+            format!("#{:x}", Srgb::<u8>::from(x))
+        };
+        let (top, bottom) = (shade(GRADIENT_STEP), shade(-GRADIENT_STEP));
+        writeln!(
+            file,
+            r#"    <linearGradient id="{id}" x1="0" y1="0" x2="0" y2="1">
+      <stop offset="0" stop-color="{top}"/>
+      <stop offset="1" stop-color="{bottom}"/>
+    </linearGradient>"#
+        )?;
+    }
+    writeln!(file, "  </defs>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parse::Keymap;
+    use camino::Utf8PathBuf;
+
+    pub(crate) fn golden_info() -> Result<InputInfo> {
+        let render_opts = RenderOpts::parse_from_str(
+            "render_opts.json",
+            include_str!("testdata/render_opts.json"),
+        )?;
+        let keymap = Keymap::parse_from_source(
+            include_str!("testdata/keymap.c"),
+            include_str!("testdata/keyboard.json"),
+            include_str!("testdata/combos.def"),
+            &render_opts,
+        )?;
+        Ok(InputInfo {
+            keymap,
+            render_opts,
+        })
+    }
+
+    // Compare against a checked in svg. Run with `UPDATE_GOLDEN=1` to regenerate
+    // the files after an intended change to the output.
+    fn assert_golden(name: &str, out: Vec<u8>) -> Result<()> {
+        let actual = String::from_utf8(out)?;
+        let path = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/render/testdata")
+            .join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &actual)?;
+        }
+        let expected = std::fs::read_to_string(&path)?;
+        assert_eq!(actual, expected, "{name} differs from the golden file");
+        Ok(())
+    }
+
+    #[test]
+    fn test_golden_base_layer() -> Result<()> {
+        let info = golden_info()?;
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        assert_golden("base_layer.svg", out)
+    }
+
+    #[test]
+    fn test_background() -> Result<()> {
+        let mut info = golden_info()?;
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        assert!(!String::from_utf8(out)?.contains(r#"class="background""#));
+
+        info.render_opts.background = Some("none".to_string());
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        let svg = String::from_utf8(out)?;
+        let first_child = svg
+            .lines()
+            .find(|x| x.starts_with('<') && !x.starts_with("<svg"));
+        assert_eq!(
+            first_child,
+            Some(r#"<rect x="0" y="0" width="668" height="290" fill="none" class="background"/>"#)
+        );
 
-    // Increase the lightness
-    let new_value = (hsv.value + amount).min(1.0); // Ensure it doesn't exceed 1.0
-    let new_hsv = Hsv::new(hsv.hue, hsv.saturation, new_value);
+        info.render_opts.background = Some(r#"red" onload="x"#.to_string());
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        assert!(String::from_utf8(out)?.contains(r#"fill="red&quot; onload=&quot;x" class"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hand_gap() -> Result<()> {
+        let mut info = golden_info()?;
+        info.render_opts.hand_gap = 1.5;
+        let spread = with_hand_gap(&info);
+        let base = &spread.keymap.layers[0];
+
+        assert_eq!(base.find_key_by_id("SE_P").unwrap().x, 4.0);
+        assert_eq!(base.find_key_by_id("SE_X").unwrap().x, 8.5);
+        let combo = spread.keymap.find_combo_by_id("el_str_int").unwrap();
+        assert_eq!(combo.min_x(), 8.5);
+        // The parsed keymap is left as is
+        assert_eq!(info.keymap.layers[0].find_key_by_id("SE_X").unwrap().x, 7.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_caption_viewbox() -> Result<()> {
+        let mut info = golden_info()?;
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        assert!(String::from_utf8(out)?.contains("viewBox='0 0 668 290'"));
+
+        info.render_opts.outputs.layer_captions = true;
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        assert!(String::from_utf8(out)?.contains("viewBox='0 -24 668 314'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_layer_size() -> Result<()> {
+        let mut info = golden_info()?;
+        assert_eq!(layer_canvas(&info.keymap.layers, &info.render_opts), None);
+
+        info.render_opts.outputs.fixed_layer_size = true;
+        assert_eq!(
+            layer_canvas(&info.keymap.layers, &info.render_opts),
+            Some((648.0, 270.0))
+        );
+
+        // A wider canvas centers the keys
+        let mut out = Vec::new();
+        let layer = &info.keymap.layers[0];
+        write_layer(
+            &mut out,
+            layer,
+            None,
+            &info.render_opts,
+            Some((702.0, 270.0)),
+        )?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains("width='722px'"));
+        assert!(svg.contains("viewBox='-27 0 722 290'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_layer_by_id() -> Result<()> {
+        let info = golden_info()?;
+        let mut out = Vec::new();
+        write_layer_by_id(&info, "_BASE", &mut out)?;
+        assert_golden("base_layer.svg", out)?;
+
+        let err = write_layer_by_id(&info, "_MISSING", &mut Vec::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Unknown layer `_MISSING`, expected one of: _BASE"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_layer() -> Result<()> {
+        let mut info = golden_info()?;
+        let layers = &info.keymap.layers;
+        assert_eq!(
+            combo_layer("escape_sym", layers, &info.render_opts)?.id.0,
+            "_BASE"
+        );
+
+        info.render_opts.outputs.combo_layers = [
+            ("escape_sym".to_string(), "_NUM".to_string()),
+            ("group".to_string(), "_MISSING".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let layers = &info.keymap.layers;
+        assert_eq!(
+            combo_layer("escape_sym", layers, &info.render_opts)?.id.0,
+            "_NUM"
+        );
+        assert_eq!(combo_layer("num", layers, &info.render_opts)?.id.0, "_BASE");
+        assert!(combo_layer("group", layers, &info.render_opts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_combos_order() -> Result<()> {
+        let mut info = golden_info()?;
+        let outputs = &mut info.render_opts.outputs;
+        outputs.combo_keys_with_separate_imgs = ["SE_T", "SE_F", "SE_N"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        for (group_id, combo_id) in [("zeta", "num"), ("alpha", "https"), ("mid", "num")] {
+            outputs
+                .combo_highlight_groups
+                .insert(group_id.into(), [combo_id.to_string()].into());
+        }
+
+        let groups = group_combos(&info.keymap.combos, &info.keymap.layers, &info.render_opts)?;
+        let keys: Vec<_> = groups.separate_layouts.keys().copied().collect();
+        assert_eq!(keys, vec!["SE_F", "SE_N", "SE_T"]);
+        let ids: Vec<_> = groups.highlight.keys().copied().collect();
+        assert_eq!(ids, vec!["alpha", "mid", "zeta"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shifted_title() -> Result<()> {
+        let shifted_opts = r#"
+{
+  "layers": {
+    "_BASE": [{ "keys": ["SE_LPRN"], "title": "(", "shifted_title": "[" }]
+  }
+}
+"#;
+        let mut info = golden_info()?;
+        let render_layer = |info: &InputInfo| -> Result<String> {
+            let mut out = Vec::new();
+            write_layer(
+                &mut out,
+                &info.keymap.layers[0],
+                None,
+                &info.render_opts,
+                None,
+            )?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert!(!render_layer(&info)?.contains("shifted"));
 
-    // Convert back to RGB
-    new_hsv.into_color()
+        info.render_opts = RenderOpts::merge_from_strs(
+            "render_opts.json",
+            &[include_str!("testdata/render_opts.json"), shifted_opts],
+        )?;
+        let svg = render_layer(&info)?;
+        assert!(svg.contains(".keycap .shifted { font-size: 8px }"));
+        assert!(svg.contains(r#"class="shifted">[</text>"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keycap_style() -> Result<()> {
+        let mut info = golden_info()?;
+        info.render_opts.keycap_style.rx = 2.0;
+        info.render_opts.keycap_style.border_bottom = 4.0;
+
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains(r#"rx="2" fill="#));
+        assert!(!svg.contains(r#"rx="5""#));
+        // 54 - 4 - 4
+        assert!(svg.contains(r#"width="42" height="46""#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let info = golden_info()?;
+        let base = &info.keymap.layers[0];
+        let pos = |id| base.find_key_by_id(id).unwrap().physical_pos.pos();
+        let presses: HashMap<_, _> = [(pos("SE_T"), 3), (pos("SE_E"), 1)].into();
+
+        let write = |opts: &UsageOpts| -> Result<String> {
+            let mut out = Vec::new();
+            write_usage(&mut out, base, &info.render_opts, &presses, opts)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let svg = write(&UsageOpts::default())?;
+        assert_eq!(svg.matches(r#"class="presses">3<"#).count(), 1);
+        assert_eq!(svg.matches(r#"class="presses">1<"#).count(), 1);
+        assert!(svg.contains(r#"class="presses">0<"#));
+        assert!(!svg.contains(&effort_color(3, 0, 3)));
+
+        let svg = write(&UsageOpts {
+            percent: true,
+            heatmap: true,
+        })?;
+        assert!(svg.contains(r#"class="presses">75.0%<"#));
+        assert!(svg.contains(r#"class="presses">25.0%<"#));
+        assert!(svg.contains(&format!(
+            r#"fill="{}" class="inner border""#,
+            effort_color(3, 0, 3)
+        )));
+        assert!(svg.ends_with("</svg>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_tap_hold() -> Result<()> {
+        let info = golden_info()?;
+        let base = &info.keymap.layers[0];
+        let keys: Vec<_> = ["SE_T", "SE_S"]
+            .iter()
+            .map(|id| base.find_key_by_id(id).unwrap().clone())
+            .collect();
+        let combo = Combo::new("esc_num".into(), "LT(_NUM, KC_ESC)".into(), keys);
+        let mut out = Vec::new();
+        ComboSingleRender {
+            combo: &combo,
+            base_layer: base,
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("esc_num.svg"),
+        }
+        .write(&mut out)?;
+        let svg = String::from_utf8(out)?;
+
+        assert!(svg.contains(r#"dy="0">Esc</tspan>"#));
+        assert!(svg.contains(r#"class="sub" style="font-size: 6.0px">NUM</text>"#));
+
+        assert_eq!(combo_title("Mouse\nLeft"), "Mouse Left");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_combo_lines() -> Result<()> {
+        let mut info = golden_info()?;
+        let base = &info.keymap.layers[0];
+        let combos: Vec<_> = info.keymap.combos.iter().take(2).collect();
+        let key_count: usize = combos.iter().map(|x| x.keys.len()).sum();
+
+        let write_group = |render_opts: &RenderOpts| -> Result<String> {
+            let mut out = Vec::new();
+            ComboGroupRender {
+                combos: &combos,
+                base_layer: base,
+                render_opts,
+                path: Utf8Path::new("group.svg"),
+            }
+            .write(&mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert!(!write_group(&info.render_opts)?.contains("combo-line"));
+
+        info.render_opts.outputs.combo_lines = true;
+        let svg = write_group(&info.render_opts)?;
+        assert!(svg.contains(".combo-line { stroke: black;"));
+        assert!(!svg.contains(r#"stroke="black""#));
+        assert_eq!(svg.matches(r#"class="combo-line""#).count(), key_count);
+        // Drawn below the combo outputs, including the outputs of the other combos
+        assert!(svg.rfind("combo-line").unwrap() < svg.rfind("keycap").unwrap());
+        let lines = svg.find(r#"class="combo-line""#).unwrap()..svg.rfind("combo-line").unwrap();
+        assert!(!svg[lines].contains("keycap"));
+
+        let combo = combos[0];
+        let key = &combo.keys[0];
+        let mut out = Vec::new();
+        ComboSingleRender {
+            combo,
+            base_layer: base,
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("single.svg"),
+        }
+        .write(&mut out)?;
+        let svg = String::from_utf8(out)?;
+        assert_eq!(
+            svg.matches(r#"class="combo-line""#).count(),
+            combo.keys.len()
+        );
+        let (x1, y1) = (10.0 + key.x * 54.0 + 27.0, 10.0 + key.y * 54.0 + 27.0);
+        assert!(svg.contains(&format!(r#"<line x1="{x1}" y1="{y1}""#)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_badges() -> Result<()> {
+        let info = golden_info()?;
+        let base = &info.keymap.layers[0];
+        let keys: Vec<_> = ["SE_T", "SE_S"]
+            .iter()
+            .map(|id| base.find_key_by_id(id).unwrap().clone())
+            .collect();
+        let write = |combo: &Combo| -> Result<String> {
+            let mut out = Vec::new();
+            ComboSingleRender {
+                combo,
+                base_layer: base,
+                render_opts: &info.render_opts,
+                path: Utf8Path::new("seq.svg"),
+            }
+            .write(&mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let chord = Combo::new("chord".into(), "KC_ESC".into(), keys.clone());
+        let svg = write(&chord)?;
+        assert!(!svg.contains(r#"class="order""#));
+        assert!(!svg.contains(".order"));
+
+        let sequence = Combo::new_ordered("seq".into(), "KC_ESC".into(), keys);
+        let svg = write(&sequence)?;
+        let badges: Vec<_> = svg
+            .lines()
+            .filter(|x| x.starts_with(r#"<g class="order">"#))
+            .collect();
+        assert_eq!(badges.len(), 2);
+        assert_eq!(svg.matches(".order circle").count(), 1);
+        assert!(badges[0].ends_with(">1</text></g>"));
+        assert!(badges[1].ends_with(">2</text></g>"));
+
+        // The images with several combos number the keys as well
+        let combos = [&sequence];
+        let mut out = Vec::new();
+        CombosWithLayerRender {
+            combos: &combos,
+            base_layer: base,
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("neighbour.svg"),
+        }
+        .write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?
+                .matches(r#"<g class="order">"#)
+                .count(),
+            2
+        );
+
+        let mut out = Vec::new();
+        ComboGroupRender {
+            combos: &combos,
+            base_layer: base,
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("group.svg"),
+        }
+        .write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?
+                .matches(r#"<g class="order">"#)
+                .count(),
+            2
+        );
+
+        let mut out = Vec::new();
+        CombosOverviewRender {
+            combos: &combos,
+            base_layer: base,
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("overview.svg"),
+        }
+        .write(&mut out)?;
+        assert!(String::from_utf8(out)?.contains("T → S"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matrix_debug() -> Result<()> {
+        let info = golden_info()?;
+        let mut out = Vec::new();
+        write_matrix_debug(&mut out, &info.keymap.layers[0], &info.render_opts)?;
+        let svg = String::from_utf8(out)?;
+
+        assert!(svg.contains(r#"dy="0">m 0,1</tspan>"#));
+        assert!(svg.contains(r#"dy="11">p 0,1</tspan>"#));
+        // Titles aren't shown
+        assert!(!svg.contains(">J</tspan>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keymap_names() -> Result<()> {
+        let mut info = golden_info()?;
+        info.render_opts.keyboard = "ferris/sweep".to_string();
+        info.render_opts.keymap = "default".to_string();
+        info.render_opts.outputs.keymap_caption = true;
+
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        let svg = String::from_utf8(out)?;
+        assert!(
+            svg.contains("class='keymap render_opts.json keyboard-ferris-sweep keymap-default'")
+        );
+        assert!(svg.contains(r#"class="caption">ferris&#x2F;sweep &#x2F; default</text>"#));
+
+        info.render_opts.outputs.layer_captions = true;
+        let mut out = Vec::new();
+        write_layer(
+            &mut out,
+            &info.keymap.layers[0],
+            None,
+            &info.render_opts,
+            None,
+        )?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains(r#"class="caption">ferris&#x2F;sweep &#x2F; default: "#));
+
+        let mut out = Vec::new();
+        write_legend(&mut out, &[], &info.render_opts)?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains(
+            "class='keyboard-legend render_opts.json keyboard-ferris-sweep keymap-default'"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlisted_legend_classes() -> Result<()> {
+        let info = golden_info()?;
+        // `management` is in the legend
+        let unlisted: Vec<_> = unlisted_legend_classes(&info).into_iter().collect();
+        assert_eq!(unlisted, vec!["blank", "combo"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_combos_overview() -> Result<()> {
+        let info = golden_info()?;
+        let combos: Vec<_> = info.keymap.combos.iter().collect();
+        let mut out = Vec::new();
+        CombosOverviewRender {
+            combos: &combos,
+            base_layer: &info.keymap.layers[0],
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("combos_overview.svg"),
+        }
+        .write(&mut out)?;
+        let svg = String::from_utf8(out)?;
+
+        assert!(svg.starts_with("<svg width='668px'"));
+        assert!(svg.contains("height='128px'"));
+        assert_eq!(svg.matches("<g class=\"keycap").count(), combos.len());
+        assert!(svg.contains(r#"<tspan x="577" dy="0">Esc</tspan>"#));
+        assert!(svg.contains(r#"<tspan x="577" dy="14">T + H</tspan>"#));
+        assert!(svg.ends_with("</svg>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_golden_legend() -> Result<()> {
+        let info = golden_info()?;
+        let items: Vec<_> = info
+            .render_opts
+            .legend
+            .iter()
+            .map(|item| LegendItem {
+                class: &item.class,
+                title: &item.title,
+                color: info.render_opts.class_color(&item.class),
+            })
+            .collect();
+        let mut out = Vec::new();
+        write_legend(&mut out, &items, &info.render_opts)?;
+        assert_golden("legend.svg", out)
+    }
+
+    #[test]
+    fn test_golden_neighbour_combos() -> Result<()> {
+        let info = golden_info()?;
+        let combos: Vec<_> = info
+            .keymap
+            .combos
+            .iter()
+            .filter(|combo| combo.is_horizontal_neighbour() || combo.is_vertical_neighbour())
+            .collect();
+        assert!(!combos.is_empty());
+        let mut out = Vec::new();
+        CombosWithLayerRender {
+            combos: &combos,
+            base_layer: &info.keymap.layers[0],
+            render_opts: &info.render_opts,
+            path: Utf8Path::new("neighbour_combos.svg"),
+        }
+        .write(&mut out)?;
+        assert_golden("neighbour_combos.svg", out)
+    }
+
+    #[test]
+    fn test_render_key_to_buffer() -> Result<()> {
+        let mut out = Vec::new();
+        KeyRender {
+            x: 0.0,
+            y: 0.0,
+            w: 54.0,
+            h: 54.0,
+            rx: 4.0,
+            class: "default",
+            inner_color: "#e5c494",
+            title: "A\n&",
+            text_h: 10.0,
+            hold_title: Some("sym"),
+            shifted_title: Some("{"),
+            tooltip: Some("SE_A: left-index, effort 1".to_string()),
+            border_left: 3.0,
+            border_right: 3.0,
+            border_top: 2.0,
+            border_bottom: 6.0,
+            compact: false,
+            snap_pixels: false,
+            gradient: false,
+        }
+        .render(&mut out)?;
+        let svg = String::from_utf8(out)?;
+
+        assert!(svg.starts_with(
+            "    <g class=\"keycap default\">\n      <title>SE_A: left-index, effort 1</title>\n"
+        ));
+        assert!(svg.contains(r##"fill="#e5c494" class="inner border""##));
+        assert!(svg.contains(r#"<tspan x="27" dy="0">A</tspan>"#));
+        assert!(svg.contains(r#"<tspan x="27" dy="10">&amp;</tspan>"#));
+        assert!(svg.contains(r#"class="sub">sym</text>"#));
+        assert!(svg.contains(
+            r#"<text x="49" y="4" text-anchor="end" dominant-baseline="hanging" class="shifted">{</text>"#
+        ));
+        assert!(svg.trim_end().ends_with("</g>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snap_pixels() -> Result<()> {
+        let key = |snap_pixels| KeyRender {
+            x: 10.4,
+            y: 20.6,
+            w: 54.0,
+            h: 16.0,
+            rx: 4.0,
+            class: "combo",
+            inner_color: "#8da0cb",
+            title: "esc",
+            text_h: 8.0,
+            hold_title: None,
+            shifted_title: None,
+            tooltip: None,
+            border_left: 1.5,
+            border_right: 1.5,
+            border_top: 1.0,
+            border_bottom: 2.5,
+            compact: true,
+            snap_pixels,
+            gradient: false,
+        };
+
+        let mut out = Vec::new();
+        key(false).render(&mut out)?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains(r#"<rect x="10.4" y="20.6""#));
+        assert!(svg.contains(r#"<rect x="11.9" y="21.6""#));
+
+        let mut out = Vec::new();
+        key(true).render(&mut out)?;
+        let svg = String::from_utf8(out)?;
+        assert!(svg.contains(r#"<rect x="10" y="21""#));
+        assert!(svg.contains(
+            r#"<rect x="12" y="22"
+            width="51" height="13""#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gradient_keys() -> Result<()> {
+        let mut info = golden_info()?;
+        let layer = &info.keymap.layers[0];
+        let mut out = Vec::new();
+        write_layer(&mut out, layer, None, &info.render_opts, None)?;
+        assert!(!String::from_utf8(out)?.contains("<defs>"));
+
+        info.render_opts.gradient_keys = true;
+        info.render_opts
+            .colors
+            .insert("sym".into(), "#8DA0CB".into());
+        let layer = &info.keymap.layers[0];
+        let mut out = Vec::new();
+        write_layer(&mut out, layer, None, &info.render_opts, None)?;
+        let svg = String::from_utf8(out)?;
+
+        assert_eq!(svg.matches("<defs>").count(), 1);
+        // Differently cased colors share a gradient
+        assert_eq!(
+            svg.matches(r#"<linearGradient id="key-gradient-8da0cb""#)
+                .count(),
+            1
+        );
+        assert!(svg.contains(r##"fill="url(#key-gradient-e5c494) #e5c494""##));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_legend_to_buffer() -> Result<()> {
+        let items = [LegendItem {
+            class: "sym",
+            title: "Symbols",
+            color: "#8da0cb",
+        }];
+        let info = golden_info()?;
+        let mut out = Vec::new();
+        write_legend(&mut out, &items, &info.render_opts)?;
+        let svg = String::from_utf8(out)?;
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"<g class="keycap sym">"#));
+        assert!(svg.contains("Symbols"));
+        assert!(svg.ends_with("</svg>"));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences `status!`, warnings and errors are still printed.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints progress, such as the written files and combo counts, to stderr
+/// unless `--quiet` is given.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::status::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}